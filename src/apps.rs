@@ -0,0 +1,97 @@
+//! Merge related processes into one application, for --group-apps
+//!
+//! Processes belong to the same application if one is the parent of the other and they run the
+//! same executable (master and workers), or if they run in the same systemd service or container
+//! cgroup (helpers and sidecars).
+
+use crate::procs::{self, ProcDesc};
+use std::collections::HashMap;
+
+pub struct App {
+    /// Name of the topmost process
+    pub name: Option<String>,
+    /// Indexes into the process list, in its order
+    pub members: Vec<usize>,
+}
+
+/// Group processes into applications, in the order of their first member
+pub fn group(processes: &[ProcDesc]) -> Vec<App> {
+    let mut parent = (0..processes.len()).collect::<Vec<_>>();
+    let by_pid = (processes.iter().enumerate())
+        .flat_map(|(i, pd)| pd.pids().map(move |pid| (pid, i)))
+        .collect::<HashMap<_, _>>();
+    let mut by_cgroup = HashMap::new();
+    for (i, pd) in processes.iter().enumerate() {
+        let parent_process = pd.ppid.and_then(|ppid| by_pid.get(&ppid));
+        if let Some(&p) = parent_process {
+            if pd.info.exe.is_some() && processes[p].info.exe == pd.info.exe {
+                union(&mut parent, p, i);
+            }
+        }
+        if let Some(cgroup) = pd.cgroup.as_deref().filter(|cg| is_app_cgroup(cg)) {
+            let &mut first = by_cgroup.entry(cgroup).or_insert(i);
+            union(&mut parent, first, i);
+        }
+    }
+    let mut apps = Vec::<App>::new();
+    let mut app_of_root = HashMap::new();
+    for i in 0..processes.len() {
+        let root = find(&mut parent, i);
+        let app = *app_of_root.entry(root).or_insert_with(|| {
+            apps.push(App {
+                name: None,
+                members: Vec::new(),
+            });
+            apps.len() - 1
+        });
+        apps[app].members.push(i);
+    }
+    for app in &mut apps {
+        let is_member = |pid| by_pid.get(&pid).is_some_and(|i| app.members.contains(i));
+        let top = (app.members.iter())
+            .find(|&&i| !processes[i].ppid.is_some_and(is_member))
+            .unwrap_or(&app.members[0]);
+        app.name = processes[*top].name.clone();
+    }
+    apps
+}
+
+/// Cgroups that contain one application, unlike e.g. login sessions or user@.service
+fn is_app_cgroup(path: &str) -> bool {
+    let unit = path.rsplit('/').next().unwrap_or_default();
+    (unit.ends_with(".service") && !unit.starts_with("user@"))
+        || procs::container_id(path).is_some()
+}
+
+fn find(parent: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parent[root] != root {
+        root = parent[root];
+    }
+    parent[i] = root;
+    root
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    // The earlier process stays the root, keeping the order stable
+    parent[a.max(b)] = a.min(b);
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_app_cgroup;
+
+    #[test]
+    fn app_cgroups() {
+        assert!(is_app_cgroup("/system.slice/postgresql@15-main.service"));
+        assert!(is_app_cgroup("/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod0c2b.slice/cri-containerd-9c5e0a3f8b7d6c5e9c5e0a3f8b7d6c5e9c5e0a3f8b7d6c5e9c5e0a3f8b7d6c5e.scope"));
+        assert!(!is_app_cgroup(
+            "/user.slice/user-1000.slice/session-3.scope"
+        ));
+        assert!(!is_app_cgroup(
+            "/user.slice/user-1000.slice/user@1000.service"
+        ));
+        assert!(!is_app_cgroup("/"));
+    }
+}
@@ -0,0 +1,186 @@
+//! lls bench: how long collecting and rendering takes with many listening sockets
+//!
+//! The sockets are created in a network namespace of lls' own, so the socket dumps only see
+//! them. The process scan still sees all processes of the machine.
+
+use crate::{exit, log, options::Columns, terminal, timings, users::UsersCache, Listing};
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+use std::{
+    fs,
+    io::{self, Write},
+    net::{Ipv4Addr, Ipv6Addr, TcpListener, UdpSocket},
+    os::fd::OwnedFd,
+    time::{Duration, Instant},
+};
+
+/// For stdio, the netlink sockets, /proc and the like
+const SPARE_FDS: usize = 64;
+
+pub fn run(args: impl Iterator<Item = String>) -> Result<exit::Status> {
+    let (mut sockets, rounds) = parse_args(args).map_err(exit::usage)?;
+    // Safety: no other threads exist yet, and unshare only affects the calling one
+    if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+        return Err(io::Error::last_os_error())
+            .context("Create a network namespace, which needs root or CAP_SYS_ADMIN");
+    }
+    // The namespace's own sysctl, for more ephemeral ports than the default 28232
+    fs::write("/proc/sys/net/ipv4/ip_local_port_range", "1024 65535").ok();
+    let available = raise_fd_limit().saturating_sub(SPARE_FDS);
+    if sockets > available {
+        log::warn!(
+            "Only {available} file descriptors available, benchmarking with as many sockets"
+        );
+        sockets = available;
+    }
+    let held = create(sockets)?;
+
+    let columns = Columns::default();
+    let users_cache = UsersCache::new();
+    let mut phases = Vec::<(String, Vec<Duration>)>::new();
+    timings::enable();
+    for round in 0..rounds {
+        let start = Instant::now();
+        let mut incomplete = Vec::new();
+        let iface_info = crate::interfaces_routes(&mut incomplete, false, &Default::default());
+        let (_, mut lps, _) = crate::scan(
+            &iface_info,
+            None,
+            &columns,
+            false,
+            false,
+            &users_cache,
+            &Default::default(),
+            &[],
+            None,
+            &[],
+            false,
+            None,
+            &mut incomplete,
+        )?;
+        let found = lps.iter().map(|pd| pd.sockets.len()).sum::<usize>();
+        if round == 0 && found < held.len() {
+            log::warn!("Only {found} of {} sockets were found", held.len());
+        }
+        let mut bytes = 0;
+        timings::time("render", || {
+            lps.iter_mut().for_each(|pd| pd.sockets.sort());
+            lps.sort();
+            let listing = Listing {
+                processes: lps,
+                interfaces: Vec::new(),
+                unknown: Vec::new(),
+            };
+            let tree = listing.tree(
+                &iface_info,
+                &Default::default(),
+                &columns,
+                false,
+                false,
+                None,
+            );
+            tree.render(None, &terminal::theme(false), &mut |s| bytes += s.len());
+        });
+        let measured = timings::take()
+            .into_iter()
+            .chain([("total".into(), start.elapsed())]);
+        for (phase, duration) in measured {
+            match phases.iter_mut().find(|(p, _)| *p == phase) {
+                Some((_, durations)) => durations.push(duration),
+                None => phases.push((phase, vec![duration])),
+            }
+        }
+        log::debug!("Round {round} rendered {bytes} bytes");
+    }
+    drop(held);
+    report(sockets, rounds, &mut phases, &mut io::stdout().lock())?;
+    Ok(exit::Status::Matches)
+}
+
+/// [--sockets N] [--rounds N]
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(usize, usize)> {
+    let (mut sockets, mut rounds) = (10_000, 5);
+    while let Some(arg) = args.next() {
+        let target = match arg.as_str() {
+            "--sockets" => &mut sockets,
+            "--rounds" => &mut rounds,
+            _ => bail!("Unknown argument to lls bench: {arg:?}"),
+        };
+        let value = args
+            .next()
+            .with_context(|| format!("Argument to {arg} is missing"))?;
+        *target = (value.parse().ok())
+            .filter(|&n| n > 0)
+            .with_context(|| format!("Expected a positive number for {arg}, not {value:?}"))?;
+    }
+    Ok((sockets, rounds))
+}
+
+/// Raise the soft limit on open files to the hard one, and return it
+fn raise_fd_limit() -> usize {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // Safety: limit is a valid rlimit to write to and read from
+    unsafe {
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) != 0 {
+            return 1024;
+        }
+        limit.rlim_cur = limit.rlim_max;
+        libc::setrlimit(libc::RLIMIT_NOFILE, &limit);
+        libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit);
+    }
+    usize::try_from(limit.rlim_cur).unwrap_or(usize::MAX)
+}
+
+/// A mix of TCP listeners on 0.0.0.0 and :: (dual-stack), and UDP sockets on 0.0.0.0
+fn create(sockets: usize) -> Result<Vec<OwnedFd>> {
+    (0..sockets)
+        .map(|i| -> Result<OwnedFd> {
+            Ok(match i % 4 {
+                0 | 1 => TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?.into(),
+                2 => TcpListener::bind((Ipv6Addr::UNSPECIFIED, 0))?.into(),
+                _ => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?.into(),
+            })
+        })
+        .enumerate()
+        .map(|(i, socket)| socket.with_context(|| format!("Create socket {} of {sockets}", i + 1)))
+        .collect()
+}
+
+fn report(
+    sockets: usize,
+    rounds: usize,
+    phases: &mut [(String, Vec<Duration>)],
+    out: &mut impl Write,
+) -> Result<()> {
+    let ms = |d: Duration| format!("{:.3}ms", d.as_secs_f64() * 1e3);
+    let mut lines = vec![["phase".into(), "min".into(), "median".into(), "max".into()]];
+    for (phase, durations) in phases.iter_mut() {
+        durations.sort();
+        lines.push([
+            phase.clone(),
+            ms(durations[0]),
+            ms(durations[durations.len() / 2]),
+            ms(durations[durations.len() - 1]),
+        ]);
+    }
+    let width = lines.iter().map(|l| l[0].len()).max().unwrap_or(0);
+    let num = lines
+        .iter()
+        .flat_map(|l| l[1..].iter().map(String::len))
+        .max()
+        .unwrap_or(0);
+    writeln!(
+        out,
+        "lls {}, {sockets} sockets, {rounds} rounds",
+        env!("CARGO_PKG_VERSION")
+    )
+    .context("Write output")?;
+    for [phase, times @ ..] in lines {
+        let times = times.iter().map(|t| format!("{t:>num$}")).join("  ");
+        writeln!(out, "{phase:width$}  {times}").context("Write output")?;
+    }
+    Ok(())
+}
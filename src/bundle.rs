@@ -0,0 +1,204 @@
+//! Support bundle for bug reports and offline analysis, for lls capture
+//!
+//! The archive is created once the options are valid, before the sandbox is applied and nothing
+//! can be opened for writing anymore, and kept open. Its content is collected in memory until
+//! then. Command lines and process status, which may hold secrets, are left out unless
+//! --keep-cmdlines asks for them.
+
+use crate::{
+    enrich::Enrichment,
+    json, log, netlink,
+    options::{Capture, Filters, JsonShape},
+    procs::{root, ProcDesc},
+    termtree::{Theme, Tree},
+    IfaceInfo, Listing,
+};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::{
+    fs::{read, read_dir, read_link, File, OpenOptions},
+    io::Read,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Socket tables as the kernel shows them, to compare against what lls made of them
+const PROC_FILES: &[&str] = &[
+    "net/tcp",
+    "net/tcp6",
+    "net/udp",
+    "net/udp6",
+    "net/udplite",
+    "net/udplite6",
+    "net/raw",
+    "net/raw6",
+    "net/sctp/eps",
+    "net/unix",
+    "net/protocols",
+    "net/sockstat",
+    "net/sockstat6",
+];
+
+/// What lls reads of each process with sockets
+const PROCESS_FILES: &[&str] = &["comm", "stat", "cgroup"];
+/// What else lls reads of them, only with --keep-cmdlines
+const CMDLINE_FILES: &[&str] = &["cmdline", "status"];
+
+pub struct Bundle {
+    path: PathBuf,
+    cmdlines: bool,
+    archive: File,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    /// Create the archive, readable only by its owner, and collect system information. The
+    /// netlink traffic has to be recorded since [netlink::start_recording], before any queries.
+    pub fn create(capture: &Capture) -> Result<Bundle> {
+        let Capture { path, cmdlines } = capture;
+        let archive = (OpenOptions::new().write(true).create(true).truncate(true))
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("Create {path:?}"))?;
+        let mut about = format!("lls {}\n", env!("CARGO_PKG_VERSION"));
+        if *cmdlines {
+            log::warn!(
+                "The bundle includes command lines, check {path:?} for secrets before sharing it"
+            );
+            let args = std::env::args().skip(1).collect::<Vec<_>>();
+            about.push_str(&format!("args {args:?}\n"));
+        }
+        for info in ["sys/kernel/osrelease", "sys/kernel/version", "self/status"] {
+            if let Ok(text) = std::fs::read_to_string(Path::new("/proc").join(info)) {
+                about.push_str(&format!("\n/proc/{info}:\n{text}"));
+            }
+        }
+        let mut files = vec![("about.txt".to_owned(), about.into_bytes())];
+        for name in PROC_FILES {
+            // Not all protocols are loaded or supported
+            if let Ok(content) = read(root().join(name)) {
+                files.push((format!("proc/{name}"), content));
+            }
+        }
+        Ok(Bundle {
+            path: path.to_owned(),
+            cmdlines: *cmdlines,
+            archive,
+            files,
+        })
+    }
+
+    /// Snapshot the processes' command lines, status and file descriptors, before the sandbox
+    /// hides them
+    pub fn processes(&mut self, lps: &[ProcDesc]) {
+        for pid in lps.iter().flat_map(|pd| pd.pids()) {
+            let dir = root().join(pid.to_string());
+            let cmdline_files = CMDLINE_FILES.iter().filter(|_| self.cmdlines);
+            for name in PROCESS_FILES.iter().chain(cmdline_files) {
+                // Processes exit, and not everything is readable without root
+                if let Ok(content) = read(dir.join(name)) {
+                    self.files.push((format!("proc/{pid}/{name}"), content));
+                }
+            }
+            let Ok(fds) = read_dir(dir.join("fd")) else {
+                continue;
+            };
+            let mut links = (fds.flatten())
+                .filter_map(|fd| {
+                    let target = read_link(fd.path()).ok()?;
+                    let fd = fd.file_name().to_str()?.parse::<u32>().ok()?;
+                    Some((fd, target))
+                })
+                .collect::<Vec<_>>();
+            links.sort();
+            let links = (links.into_iter())
+                .map(|(fd, target)| format!("{fd} -> {}\n", target.display()))
+                .collect::<String>();
+            self.files
+                .push((format!("proc/{pid}/fd"), links.into_bytes()));
+        }
+    }
+
+    /// Write the archive: the collected files, the netlink traffic, the result in a form that
+    /// --from-file can read, and the rendered tree
+    pub fn finish(
+        mut self,
+        listing: &Listing,
+        iface_info: &IfaceInfo,
+        filters: &Filters,
+        enrichment: &Enrichment,
+        tree: &Tree,
+    ) -> Result<()> {
+        if let Some(pcap) = netlink::recording() {
+            self.files.push(("netlink.pcap".to_owned(), pcap));
+        }
+        let mut capture = Vec::new();
+        json::write(
+            listing,
+            iface_info,
+            filters,
+            JsonShape::Tree,
+            enrichment,
+            &mut capture,
+        )?;
+        if !self.cmdlines {
+            let mut value = serde_json::from_slice(&capture).context("Reread capture")?;
+            redact(&mut value);
+            capture = serde_json::to_vec(&value).context("Redact capture")?;
+        }
+        self.files.push((CAPTURE.to_owned(), capture));
+        let mut output = Vec::new();
+        tree.render(None, &Theme::default(), &mut |s| output.extend(s));
+        self.files.push(("output.txt".to_owned(), output));
+
+        let path = &self.path;
+        let mtime = (SystemTime::now().duration_since(UNIX_EPOCH))
+            .unwrap_or_default()
+            .as_secs();
+        let zstd = zstd::Encoder::new(self.archive, 0).context("Start compression")?;
+        let mut tar = tar::Builder::new(zstd);
+        for (name, content) in &self.files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(mtime);
+            (tar.append_data(&mut header, name, &content[..]))
+                .with_context(|| format!("Write {name} to {path:?}"))?;
+        }
+        (tar.into_inner().and_then(|zstd| zstd.finish()))
+            .with_context(|| format!("Write {path:?}"))?;
+        Ok(())
+    }
+}
+
+/// The result as JSON inside the archive, for --from-file
+const CAPTURE: &str = "capture.json";
+
+/// The JSON out of an lls capture archive
+pub fn capture(archive: &[u8]) -> Result<String> {
+    let mut tar = tar::Archive::new(zstd::Decoder::new(archive)?);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if *entry.path()? == *Path::new(CAPTURE) {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            return Ok(text);
+        }
+    }
+    bail!("No {CAPTURE} in the archive")
+}
+
+/// Drop the command lines from --json output
+fn redact(json: &mut Value) {
+    match json {
+        Value::Object(fields) => {
+            if let Some(cmdline) = fields.get_mut("cmdline") {
+                *cmdline = Value::Null;
+            }
+            fields.values_mut().for_each(redact);
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
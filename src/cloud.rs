@@ -0,0 +1,254 @@
+//! Public addresses and security groups of cloud instances, from the metadata service, for --cloud
+//!
+//! Clouds map public IPv4 addresses to the instance's private ones outside of it, so from the
+//! inside, a listener on 0.0.0.0 looks the same whether or not the internet can reach it.
+
+use crate::{
+    http, log,
+    netlink::sock::{Family, SockInfo},
+    Ino,
+};
+use anyhow::{Context, Result};
+use itertools::Itertools;
+#[cfg(feature = "json")]
+use serde_json::Value;
+use std::{collections::HashMap, fs::read_to_string, net::IpAddr};
+
+/// Link-local address of the metadata services of all major clouds
+const METADATA: &str = "http://169.254.169.254";
+
+#[derive(Debug, Default)]
+pub struct Nic {
+    /// Private and public address pairs, IPv6 addresses are their own public address
+    pub public: Vec<(IpAddr, IpAddr)>,
+    /// Addresses configured on the instance
+    pub private: Vec<IpAddr>,
+    /// AWS security groups or GCP network tags, which select the firewall rules
+    pub groups: Vec<String>,
+}
+
+/// How a socket can be reached from outside the cloud network
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Exposure {
+    pub public: Vec<IpAddr>,
+    pub groups: Vec<String>,
+}
+
+/// Network interfaces of the instance, according to the metadata service of its cloud
+pub fn instance() -> Result<Vec<Nic>> {
+    let dmi = |file| read_to_string(format!("/sys/class/dmi/id/{file}")).unwrap_or_default();
+    let (vendor, product) = (dmi("sys_vendor"), dmi("product_name"));
+    // Everything else, e.g. OpenStack, implements EC2's API
+    let (provider, nics): (_, fn() -> Result<Vec<Nic>>) = match (vendor.trim(), product.trim()) {
+        ("Google", _) => ("GCP", gcp),
+        ("Microsoft Corporation", "Virtual Machine") => ("Azure", azure),
+        _ => ("EC2", ec2),
+    };
+    log::debug!("Querying the {provider} metadata service");
+    let nics = nics().with_context(|| format!("Query {provider} metadata service"))?;
+    let public = (nics.iter())
+        .flat_map(|nic| nic.public.iter().map(|(_, public)| public))
+        .join(", ");
+    match public.is_empty() {
+        true => log::info!("{provider} instance without public addresses"),
+        false => log::info!("{provider} instance with public addresses {public}"),
+    }
+    Ok(nics)
+}
+
+fn ec2() -> Result<Vec<Nic>> {
+    // IMDSv2, instances that only allow v1 don't know the request
+    let token = http::request(
+        "PUT",
+        &format!("{METADATA}/latest/api/token"),
+        &[("X-aws-ec2-metadata-token-ttl-seconds", "60")],
+        "",
+    );
+    let headers = (token.as_deref().ok())
+        .map(|token| ("X-aws-ec2-metadata-token", token.trim()))
+        .into_iter()
+        .collect::<Vec<_>>();
+    let get = |path: &str| {
+        let url = format!("{METADATA}/latest/meta-data/network/interfaces/macs/{path}");
+        http::request("GET", &url, &headers, "")
+    };
+    // Missing entries are 404, e.g. ipv6s without IPv6
+    let list = |path: &str| -> Vec<String> {
+        let lines = get(path).unwrap_or_default();
+        (lines.lines())
+            .map(|l| l.trim().trim_end_matches('/').to_owned())
+            .filter(|l| !l.is_empty())
+            .collect()
+    };
+    let mut ret = Vec::new();
+    for mac in get("")?.lines().map(|l| l.trim_end_matches('/')) {
+        let addrs = |path| {
+            let addrs = list(&format!("{mac}/{path}"));
+            addrs
+                .iter()
+                .filter_map(|a| a.parse().ok())
+                .collect::<Vec<IpAddr>>()
+        };
+        let ipv6s = addrs("ipv6s");
+        let mut public = ipv6s.iter().map(|&a| (a, a)).collect::<Vec<_>>();
+        for ip in addrs("ipv4-associations/") {
+            let private = get(&format!("{mac}/ipv4-associations/{ip}"));
+            if let Some(private) = private.ok().and_then(|p| p.trim().parse().ok()) {
+                public.push((private, ip));
+            }
+        }
+        ret.push(Nic {
+            public,
+            private: addrs("local-ipv4s").into_iter().chain(ipv6s).collect(),
+            groups: list(&format!("{mac}/security-groups")),
+        });
+    }
+    Ok(ret)
+}
+
+#[cfg(feature = "json")]
+fn gcp() -> Result<Vec<Nic>> {
+    let get = |path: &str| -> Result<Value> {
+        let url = format!("{METADATA}/computeMetadata/v1/instance/{path}");
+        let json = http::request("GET", &url, &[("Metadata-Flavor", "Google")], "")?;
+        serde_json::from_str(&json).context("Parse response")
+    };
+    let tags = get("tags").unwrap_or_default();
+    Ok(gcp_nics(&get("network-interfaces/?recursive=true")?, &tags))
+}
+
+#[cfg(feature = "json")]
+fn gcp_nics(interfaces: &Value, tags: &Value) -> Vec<Nic> {
+    let addr = |v: &Value| v.as_str().and_then(|a| a.parse::<IpAddr>().ok());
+    let items = |v: &Value| v.as_array().cloned().unwrap_or_default();
+    let groups = (items(tags).iter())
+        .filter_map(|t| t.as_str().map(Into::into))
+        .collect::<Vec<String>>();
+    let mut ret = Vec::new();
+    for interface in items(interfaces) {
+        let private = addr(&interface["ip"]);
+        let ipv6s = (items(&interface["ipv6AccessConfigs"]).iter())
+            .filter_map(|c| addr(&c["externalIpv6"]))
+            .collect::<Vec<_>>();
+        let public = (items(&interface["accessConfigs"]).iter())
+            .filter_map(|c| Some((private?, addr(&c["externalIp"])?)))
+            .chain(ipv6s.iter().map(|&a| (a, a)))
+            .collect();
+        ret.push(Nic {
+            public,
+            private: private.into_iter().chain(ipv6s).collect(),
+            groups: groups.clone(),
+        });
+    }
+    ret
+}
+
+#[cfg(feature = "json")]
+fn azure() -> Result<Vec<Nic>> {
+    let url = format!("{METADATA}/metadata/instance/network?api-version=2021-02-01");
+    let json = http::request("GET", &url, &[("Metadata", "true")], "")?;
+    let network = serde_json::from_str(&json).context("Parse response")?;
+    Ok(azure_nics(&network))
+}
+
+#[cfg(feature = "json")]
+fn azure_nics(network: &Value) -> Vec<Nic> {
+    let addr = |v: &Value| v.as_str().and_then(|a| a.parse::<IpAddr>().ok());
+    let interfaces = network["interface"].as_array().into_iter().flatten();
+    let mut ret = Vec::new();
+    for interface in interfaces {
+        let mut nic = Nic::default();
+        for family in ["ipv4", "ipv6"] {
+            for ip in interface[family]["ipAddress"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                let Some(private) = addr(&ip["privateIpAddress"]) else {
+                    continue;
+                };
+                nic.private.push(private);
+                if let Some(public) = addr(&ip["publicIpAddress"]) {
+                    nic.public.push((private, public));
+                }
+            }
+        }
+        ret.push(nic);
+    }
+    ret
+}
+
+#[cfg(not(feature = "json"))]
+fn gcp() -> Result<Vec<Nic>> {
+    anyhow::bail!("lls was built without JSON support, which the GCP metadata needs")
+}
+
+#[cfg(not(feature = "json"))]
+fn azure() -> Result<Vec<Nic>> {
+    anyhow::bail!("lls was built without JSON support, which the Azure metadata needs")
+}
+
+/// Set how sockets on non-loopback addresses can be reached from outside the cloud network
+pub fn annotate(socks: &mut HashMap<Ino, SockInfo>, nics: &[Nic]) {
+    for sock in socks.values_mut() {
+        if sock.is_inet() && !sock.addr.is_loopback() {
+            sock.cloud = Some(exposure(sock.addr, sock.family, nics));
+        }
+    }
+}
+
+/// Public addresses and groups of the interfaces that a listener on addr can be reached through
+fn exposure(addr: IpAddr, family: Family, nics: &[Nic]) -> Exposure {
+    let reachable = |a: &IpAddr| match (addr.is_unspecified(), family) {
+        (false, _) => *a == addr,
+        (true, Family::Both) => addr.is_ipv6(),
+        (true, _) => a.is_ipv4() == addr.is_ipv4(),
+    };
+    let mut ret = Exposure::default();
+    for nic in nics.iter().filter(|nic| nic.private.iter().any(reachable)) {
+        let public = nic.public.iter().filter(|(private, _)| reachable(private));
+        ret.public.extend(public.map(|&(_, public)| public));
+        ret.groups.extend(nic.groups.iter().cloned());
+    }
+    ret.public = ret.public.into_iter().unique().collect();
+    ret.groups = ret.groups.into_iter().unique().collect();
+    ret
+}
+
+#[cfg(all(test, feature = "json"))]
+mod test {
+    use super::{azure_nics, exposure, gcp_nics};
+    use crate::netlink::sock::Family;
+    use std::net::IpAddr;
+
+    #[test]
+    fn metadata() {
+        let network = serde_json::json!({"interface": [{
+            "ipv4": {"ipAddress": [
+                {"privateIpAddress": "10.0.0.4", "publicIpAddress": "20.0.0.7"},
+                {"privateIpAddress": "10.0.0.5", "publicIpAddress": ""},
+            ]},
+            "ipv6": {"ipAddress": [{"privateIpAddress": "fd00::4"}]},
+        }]});
+        let nics = azure_nics(&network);
+        let ip = |a: &str| a.parse::<IpAddr>().unwrap();
+        assert_eq!(nics[0].public, [(ip("10.0.0.4"), ip("20.0.0.7"))]);
+        assert_eq!(nics[0].private.len(), 3);
+        assert!(exposure(ip("10.0.0.5"), Family::V4, &nics)
+            .public
+            .is_empty());
+        let wildcard = exposure(ip("0.0.0.0"), Family::V4, &nics);
+        assert_eq!(wildcard.public, [ip("20.0.0.7")]);
+        assert!(exposure(ip("::"), Family::V6, &nics).public.is_empty());
+
+        let interfaces = serde_json::json!([{
+            "ip": "10.128.0.2",
+            "accessConfigs": [{"externalIp": "34.0.0.9", "type": "ONE_TO_ONE_NAT"}],
+            "ipv6AccessConfigs": [{"externalIpv6": "2600:1900::1"}],
+        }]);
+        let nics = gcp_nics(&interfaces, &serde_json::json!(["web", "ssh"]));
+        let exposure = exposure(ip("::"), Family::Both, &nics);
+        assert_eq!(exposure.public, [ip("34.0.0.9"), ip("2600:1900::1")]);
+        assert_eq!(exposure.groups, ["web", "ssh"]);
+    }
+}
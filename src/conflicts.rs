@@ -0,0 +1,49 @@
+//! Unrelated processes listening on overlapping addresses with the same port
+
+use crate::{
+    netlink::sock::{Family, SockInfo},
+    procs::ProcDesc,
+};
+use std::net::IpAddr;
+
+pub struct Conflict<'a> {
+    pub wildcard: (&'a ProcDesc<'a>, &'a SockInfo<'a>),
+    pub specific: (&'a ProcDesc<'a>, &'a SockInfo<'a>),
+}
+
+/// Pairs of sockets of different processes where a wildcard listener overlaps a listener on a
+/// specific address, which the kernel only allows with SO_REUSEPORT/SO_REUSEADDR.
+///
+/// Listeners on exactly the same address are assumed to be an intentional SO_REUSEPORT group.
+pub fn find<'a>(processes: &'a [ProcDesc<'a>]) -> Vec<Conflict<'a>> {
+    let sockets = processes
+        .iter()
+        .flat_map(|pd| pd.sockets.iter().map(move |sock| (pd, sock)))
+        .collect::<Vec<_>>();
+    let mut ret = Vec::new();
+    for &(wpd, wsock) in &sockets {
+        for &(spd, ssock) in &sockets {
+            if wpd.pid != spd.pid
+                && wsock.port == ssock.port
+                && wsock.protocol == ssock.protocol
+                && covers(wsock, ssock)
+            {
+                ret.push(Conflict {
+                    wildcard: (wpd, wsock),
+                    specific: (spd, ssock),
+                });
+            }
+        }
+    }
+    ret
+}
+
+fn covers(wildcard: &SockInfo, specific: &SockInfo) -> bool {
+    wildcard.addr.is_unspecified()
+        && wildcard.addr != specific.addr
+        && match wildcard.addr {
+            // Including ::ffff:0.0.0.0 on a dual-stack socket
+            IpAddr::V4(_) => specific.addr.is_ipv4(),
+            IpAddr::V6(_) => wildcard.family == Family::Both || specific.addr.is_ipv6(),
+        }
+}
@@ -0,0 +1,148 @@
+//! Container images of processes, from the container runtime's metadata on disk
+//!
+//! The metadata is looked up in the file system --proc-root belongs to. Rootless docker and
+//! podman keep theirs in the home and runtime directory of the user whose systemd instance runs
+//! the container.
+
+use crate::procs::{self, ProcDesc};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+/// Set the image of processes running in docker, podman, or containerd containers
+pub fn resolve(processes: &mut [ProcDesc]) {
+    let mut cache = HashMap::<String, Option<String>>::new();
+    for pd in processes {
+        let Some((id, owner)) = container(pd.pid) else {
+            continue;
+        };
+        pd.image = (cache.entry(id))
+            .or_insert_with_key(|id| image(id, owner))
+            .clone();
+    }
+}
+
+/// The id of the container the process runs in, see [procs::container_id]
+///
+/// With legacy cgroup v1, there is one line per hierarchy instead of the single unified 0::
+/// line. Some hierarchies may not be managed by the runtime, so all of them are searched.
+///
+/// Rootless containers run below the user's systemd instance, e.g.
+/// /user.slice/user-1000.slice/user@1000.service/…/libpod-<id>.scope, which tells their owner.
+fn container(pid: i32) -> Option<(String, Option<u32>)> {
+    let cgroups = procs::process(pid).ok()?.cgroups().ok()?;
+    (cgroups.into_iter()).find_map(|cg| {
+        let id = procs::container_id(&cg.pathname)?;
+        Some((id, owner_in_path(&cg.pathname)))
+    })
+}
+
+fn owner_in_path(path: &str) -> Option<u32> {
+    path.split('/').find_map(|part| {
+        let uid = part.strip_prefix("user@")?.strip_suffix(".service")?;
+        uid.parse().ok()
+    })
+}
+
+/// Where the runtimes of a rootless container's owner keep their state
+struct Rootless {
+    /// ~/.local/share, for docker's data-root and podman's graphroot
+    data: Option<PathBuf>,
+    /// $XDG_RUNTIME_DIR, for podman's runroot
+    runtime: PathBuf,
+}
+
+fn image(id: &str, owner: Option<u32>) -> Option<String> {
+    let rootless = owner.map(|uid| Rootless {
+        data: home(uid).map(|home| home.join(".local/share")),
+        runtime: in_sysroot(&format!("/run/user/{uid}")),
+    });
+    let rootless = rootless.as_ref();
+    (docker(id, rootless))
+        .or_else(|| containerd(id))
+        .or_else(|| podman(id, rootless))
+}
+
+/// A path of the file system --proc-root belongs to
+fn in_sysroot(path: &str) -> PathBuf {
+    procs::sysroot().join(path.trim_start_matches('/'))
+}
+
+/// The home directory of a user according to the sysroot's /etc/passwd
+fn home(uid: u32) -> Option<PathBuf> {
+    let passwd = read_to_string(in_sysroot("/etc/passwd")).ok()?;
+    passwd.lines().find_map(|line| {
+        // name:password:uid:gid:gecos:home:shell
+        let fields = line.split(':').collect::<Vec<_>>();
+        let home = fields.get(5).filter(|_| fields[2].parse() == Ok(uid))?;
+        Some(in_sysroot(home))
+    })
+}
+
+fn json(path: &Path) -> Option<Value> {
+    serde_json::from_str(&read_to_string(path).ok()?).ok()
+}
+
+fn docker(id: &str, rootless: Option<&Rootless>) -> Option<String> {
+    let mut data_roots = vec![in_sysroot("/var/lib/docker")];
+    data_roots.extend(
+        rootless
+            .and_then(|r| r.data.as_ref())
+            .map(|d| d.join("docker")),
+    );
+    let config = (data_roots.iter())
+        .find_map(|root| json(&root.join(format!("containers/{id}/config.v2.json"))))?;
+    Some(config["Config"]["Image"].as_str()?.into())
+}
+
+fn containerd(id: &str) -> Option<String> {
+    let spec = json(&in_sysroot(&format!(
+        "/run/containerd/io.containerd.runtime.v2.task/k8s.io/{id}/config.json"
+    )))?;
+    Some(
+        spec["annotations"]["io.kubernetes.cri.image-name"]
+            .as_str()?
+            .into(),
+    )
+}
+
+fn podman(id: &str, rootless: Option<&Rootless>) -> Option<String> {
+    // The graphroot, and the runroot for containers in a transient store
+    let mut storages = vec![
+        in_sysroot("/var/lib/containers/storage"),
+        in_sysroot("/run/containers/storage"),
+    ];
+    if let Some(rootless) = rootless {
+        storages.extend(rootless.data.as_ref().map(|d| d.join("containers/storage")));
+        storages.push(rootless.runtime.join("containers"));
+    }
+    let container = storages.iter().find_map(|storage| {
+        let containers = json(&storage.join("overlay-containers/containers.json"))?;
+        let containers = containers.as_array()?;
+        containers.iter().find(|c| c["id"] == id).cloned()
+    })?;
+    // The metadata is JSON encoded as a string
+    let metadata: Value = serde_json::from_str(container["metadata"].as_str()?).ok()?;
+    Some(metadata["image-name"].as_str()?.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::owner_in_path;
+
+    #[test]
+    fn rootless_owner() {
+        let id = "4f1e9c0e3b8d2a7f6c5b4a39281706f5e4d3c2b1a09f8e7d6c5b4a3928170605";
+        let rootless = format!(
+            "/user.slice/user-1000.slice/user@1000.service/user.slice/libpod-{id}.scope/container"
+        );
+        assert_eq!(owner_in_path(&rootless), Some(1000));
+        assert_eq!(
+            owner_in_path(&format!("/system.slice/docker-{id}.scope")),
+            None
+        );
+    }
+}
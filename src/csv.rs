@@ -0,0 +1,61 @@
+//! One row per socket with a header line, for --csv and --tsv
+
+use crate::{
+    options::{Columns, Delimiter, Filters},
+    rows::rows,
+    IfaceInfo, Listing,
+};
+use anyhow::{Context, Result};
+use std::io::Write;
+
+pub fn write(
+    listing: &Listing,
+    iface_info: &IfaceInfo,
+    filters: &Filters,
+    columns: &Columns,
+    delimiter: Delimiter,
+    out: &mut impl Write,
+) -> Result<()> {
+    let header = columns.0.iter().map(|c| c.name().to_owned());
+    writeln!(out, "{}", line(header, delimiter)).context("Write output")?;
+    for row in rows(listing, iface_info, filters) {
+        let record = columns.0.iter().map(|&c| row.get(c));
+        writeln!(out, "{}", line(record, delimiter)).context("Write output")?;
+    }
+    Ok(())
+}
+
+fn line(fields: impl Iterator<Item = String>, delimiter: Delimiter) -> String {
+    let fields = fields.map(|f| field(&f, delimiter)).collect::<Vec<_>>();
+    fields.join(match delimiter {
+        Delimiter::Comma => ",",
+        Delimiter::Tab => "\t",
+    })
+}
+
+fn field(value: &str, delimiter: Delimiter) -> String {
+    match delimiter {
+        // RFC 4180: quoted if needed, with quotes doubled
+        Delimiter::Comma if value.contains([',', '"', '\n', '\r']) => {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        }
+        Delimiter::Comma => value.into(),
+        // TSV has no quoting, fields just can't contain tabs or line breaks
+        Delimiter::Tab => value.replace(['\t', '\n', '\r'], " "),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::field;
+    use crate::options::Delimiter;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(field("sshd", Delimiter::Comma), "sshd");
+        assert_eq!(field("1-3,7", Delimiter::Comma), "\"1-3,7\"");
+        assert_eq!(field("say \"hi\"", Delimiter::Comma), "\"say \"\"hi\"\"\"");
+        assert_eq!(field("1-3,7", Delimiter::Tab), "1-3,7");
+        assert_eq!(field("a\tb\nc", Delimiter::Tab), "a b c");
+    }
+}
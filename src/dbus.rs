@@ -0,0 +1,383 @@
+//! Just enough of a D-Bus client for calling methods with string arguments on the system bus
+
+use crate::users;
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    time::Duration,
+};
+
+const SYSTEM_BUS: &str = "/run/dbus/system_bus_socket";
+const TIMEOUT: Duration = Duration::from_secs(2);
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+// Header fields
+const PATH: u8 = 1;
+const INTERFACE: u8 = 2;
+const MEMBER: u8 = 3;
+const ERROR_NAME: u8 = 4;
+const REPLY_SERIAL: u8 = 5;
+const DESTINATION: u8 = 6;
+const SIGNATURE: u8 = 8;
+
+/// A decoded value, dict entries are structs of key and value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    /// Also object paths and signatures
+    Str(String),
+    Array(Vec<Value>),
+    Struct(Vec<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub fn str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            Value::Variant(v) => v.str(),
+            _ => None,
+        }
+    }
+    pub fn items(&self) -> &[Value] {
+        match self {
+            Value::Array(items) | Value::Struct(items) => items,
+            Value::Variant(v) => v.items(),
+            _ => &[],
+        }
+    }
+    /// Value of a key in an a{s…} dict
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.items().iter().find_map(|entry| match entry.items() {
+            [k, v] if k.str() == Some(key) => Some(v),
+            _ => None,
+        })
+    }
+}
+
+struct Message {
+    kind: u8,
+    fields: Vec<(u8, Value)>,
+    body: Vec<u8>,
+    big_endian: bool,
+}
+
+pub struct Bus {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Bus {
+    pub fn system() -> Result<Self> {
+        // Only the plain unix:path=… form
+        let address = std::env::var("DBUS_SYSTEM_BUS_ADDRESS").ok();
+        let path = (address.as_deref())
+            .and_then(|a| a.strip_prefix("unix:path="))
+            .map(|p| p.split(',').next().unwrap_or(p))
+            .unwrap_or(SYSTEM_BUS);
+        let mut stream =
+            UnixStream::connect(path).with_context(|| format!("Connect to D-Bus at {path}"))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let uid = users::get_effective_uid().to_string();
+        let hex = uid.bytes().map(|b| format!("{b:02x}")).collect::<String>();
+        (stream.write_all(format!("\0AUTH EXTERNAL {hex}\r\n").as_bytes()))
+            .context("Authenticate")?;
+        // Byte by byte, the line mustn't be read past
+        let mut line = Vec::new();
+        while !line.ends_with(b"\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).context("Authenticate")?;
+            line.push(byte[0]);
+        }
+        ensure!(
+            line.starts_with(b"OK "),
+            "Authentication rejected: {}",
+            String::from_utf8_lossy(&line).trim()
+        );
+        stream.write_all(b"BEGIN\r\n").context("Authenticate")?;
+        let mut bus = Bus { stream, serial: 0 };
+        let dbus = "org.freedesktop.DBus";
+        bus.call(dbus, "/org/freedesktop/DBus", dbus, "Hello", &[])?;
+        Ok(bus)
+    }
+
+    /// Call a method with string arguments, and return the values of the reply
+    pub fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[&str],
+    ) -> Result<Vec<Value>> {
+        self.serial += 1;
+        let mut body = Writer::default();
+        args.iter().for_each(|arg| body.str(arg));
+        let mut msg = Writer::default();
+        msg.0.extend([b'l', METHOD_CALL, 0, 1]);
+        msg.u32(body.0.len() as u32);
+        msg.u32(self.serial);
+        let mut fields = Writer::default();
+        let signature = "s".repeat(args.len());
+        let mut headers = vec![
+            (PATH, 'o', path),
+            (INTERFACE, 's', interface),
+            (MEMBER, 's', member),
+            (DESTINATION, 's', destination),
+        ];
+        if !args.is_empty() {
+            headers.push((SIGNATURE, 'g', &signature));
+        }
+        for (code, kind, value) in headers {
+            // The array content starts at offset 16, aligned like the message
+            fields.align(8);
+            fields.0.extend([code, 1, kind as u8, 0]);
+            match kind {
+                'g' => fields.sig(value),
+                _ => fields.str(value),
+            }
+        }
+        msg.u32(fields.0.len() as u32);
+        msg.0.extend(fields.0);
+        msg.align(8);
+        msg.0.extend(body.0);
+        (self.stream.write_all(&msg.0)).with_context(|| format!("Call {member}"))?;
+        loop {
+            let msg = self.read().with_context(|| format!("Call {member}"))?;
+            let field = |code| (msg.fields.iter()).find_map(|(c, v)| (*c == code).then_some(v));
+            let reply_to = match field(REPLY_SERIAL) {
+                Some(Value::UInt(serial)) => *serial,
+                _ => continue,
+            };
+            if reply_to != self.serial as u64 {
+                continue;
+            }
+            let signature = field(SIGNATURE).and_then(Value::str).unwrap_or_default();
+            let values = Reader::new(&msg.body, 0, msg.big_endian).values(signature)?;
+            match msg.kind {
+                METHOD_RETURN => return Ok(values),
+                ERROR => {
+                    let name = field(ERROR_NAME).and_then(Value::str).unwrap_or_default();
+                    let message = values.first().and_then(Value::str).unwrap_or_default();
+                    bail!("{member} failed: {name}: {message}");
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Message> {
+        let mut fixed = [0; 16];
+        self.stream.read_exact(&mut fixed)?;
+        let big_endian = match fixed[0] {
+            b'l' => false,
+            b'B' => true,
+            e => bail!("Invalid endianness {e:#x}"),
+        };
+        let u32_at = |pos: usize| {
+            let bytes = [fixed[pos], fixed[pos + 1], fixed[pos + 2], fixed[pos + 3]];
+            match big_endian {
+                true => u32::from_be_bytes(bytes),
+                false => u32::from_le_bytes(bytes),
+            }
+        };
+        let (body_len, fields_len) = (u32_at(4) as usize, u32_at(12) as usize);
+        let mut rest = vec![0; fields_len.div_ceil(8) * 8 + body_len];
+        self.stream.read_exact(&mut rest)?;
+        let mut header = fixed.to_vec();
+        header.extend(&rest[..fields_len]);
+        let mut reader = Reader::new(&header, 12, big_endian);
+        let fields = (reader
+            .value(&mut "a(yv)".chars().peekable())?
+            .items()
+            .iter())
+        .filter_map(|field| match field.items() {
+            [Value::UInt(code), Value::Variant(value)] => Some((*code as u8, (**value).clone())),
+            _ => None,
+        })
+        .collect();
+        Ok(Message {
+            kind: fixed[1],
+            fields,
+            body: rest.split_off(rest.len() - body_len),
+            big_endian,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn align(&mut self, n: usize) {
+        while self.0.len() % n != 0 {
+            self.0.push(0);
+        }
+    }
+    fn u32(&mut self, n: u32) {
+        self.align(4);
+        self.0.extend(n.to_le_bytes());
+    }
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.0.extend(s.as_bytes());
+        self.0.push(0);
+    }
+    fn sig(&mut self, s: &str) {
+        self.0.push(s.len() as u8);
+        self.0.extend(s.as_bytes());
+        self.0.push(0);
+    }
+}
+
+struct Reader<'b> {
+    buf: &'b [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+type Signature<'s> = std::iter::Peekable<std::str::Chars<'s>>;
+
+impl<'b> Reader<'b> {
+    fn new(buf: &'b [u8], pos: usize, big_endian: bool) -> Self {
+        Reader {
+            buf,
+            pos,
+            big_endian,
+        }
+    }
+
+    fn values(&mut self, signature: &str) -> Result<Vec<Value>> {
+        let mut signature = signature.chars().peekable();
+        let mut ret = Vec::new();
+        while signature.peek().is_some() {
+            ret.push(self.value(&mut signature)?);
+        }
+        Ok(ret)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        self.pos = self.pos.div_ceil(N) * N;
+        let bytes = (self.buf.get(self.pos..self.pos + N)).context("Message truncated")?;
+        self.pos += N;
+        let mut ret: [u8; N] = bytes.try_into()?;
+        if self.big_endian != cfg!(target_endian = "big") {
+            ret.reverse();
+        }
+        Ok(ret)
+    }
+
+    /// A string of the given length and its terminating nul byte
+    fn string(&mut self, len: usize) -> Result<String> {
+        let bytes = (self.buf.get(self.pos..self.pos + len)).context("Message truncated")?;
+        self.pos += len + 1;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn value(&mut self, signature: &mut Signature) -> Result<Value> {
+        let kind = signature.next().context("Signature truncated")?;
+        Ok(match kind {
+            'y' => Value::UInt(self.bytes::<1>()?[0].into()),
+            'b' => Value::Bool(u32::from_ne_bytes(self.bytes()?) != 0),
+            'n' => Value::Int(i16::from_ne_bytes(self.bytes()?).into()),
+            'q' => Value::UInt(u16::from_ne_bytes(self.bytes()?).into()),
+            'i' => Value::Int(i32::from_ne_bytes(self.bytes()?).into()),
+            'u' | 'h' => Value::UInt(u32::from_ne_bytes(self.bytes()?).into()),
+            'x' => Value::Int(i64::from_ne_bytes(self.bytes()?)),
+            't' => Value::UInt(u64::from_ne_bytes(self.bytes()?)),
+            'd' => Value::Double(f64::from_ne_bytes(self.bytes()?)),
+            's' | 'o' => {
+                let len = u32::from_ne_bytes(self.bytes()?);
+                Value::Str(self.string(len as usize)?)
+            }
+            'g' => {
+                let len = self.bytes::<1>()?[0];
+                Value::Str(self.string(len.into())?)
+            }
+            'v' => {
+                let len = self.bytes::<1>()?[0];
+                let inner = self.string(len.into())?;
+                Value::Variant(Box::new(self.value(&mut inner.chars().peekable())?))
+            }
+            'a' => {
+                let len = u32::from_ne_bytes(self.bytes()?) as usize;
+                // Padding to the element alignment isn't part of the length
+                if matches!(signature.peek(), Some('(' | '{' | 'x' | 't' | 'd')) {
+                    self.pos = self.pos.div_ceil(8) * 8;
+                }
+                let end = self.pos + len;
+                let element = signature.clone();
+                skip(signature)?;
+                let mut items = Vec::new();
+                while self.pos < end {
+                    items.push(self.value(&mut element.clone())?);
+                }
+                Value::Array(items)
+            }
+            '(' | '{' => {
+                self.pos = self.pos.div_ceil(8) * 8;
+                let mut fields = Vec::new();
+                while !matches!(signature.peek(), Some(')' | '}') | None) {
+                    fields.push(self.value(signature)?);
+                }
+                signature.next();
+                Value::Struct(fields)
+            }
+            k => bail!("Unsupported type {k:?}"),
+        })
+    }
+}
+
+/// Advance past one complete type
+fn skip(signature: &mut Signature) -> Result<()> {
+    match signature.next().context("Signature truncated")? {
+        'a' => skip(signature),
+        '(' | '{' => {
+            while !matches!(signature.peek(), Some(')' | '}') | None) {
+                skip(signature)?;
+            }
+            signature.next();
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Reader, Value, Writer};
+
+    #[test]
+    fn values() {
+        // {"ports": <[("22", "tcp")]>}, as getServiceSettings2 returns it
+        let mut msg = Writer::default();
+        msg.u32(40);
+        msg.align(8);
+        msg.str("ports");
+        msg.sig("a(ss)");
+        msg.u32(16);
+        msg.align(8);
+        msg.str("22");
+        msg.str("tcp");
+        let values = Reader::new(&msg.0, 0, false).values("a{sv}").unwrap();
+        let ports = values[0].get("ports").unwrap();
+        assert_eq!(
+            ports.items()[0].items(),
+            [Value::Str("22".into()), Value::Str("tcp".into())]
+        );
+        assert!(Reader::new(&msg.0[..44], 0, false).values("a{sv}").is_err());
+        // Empty arrays are still padded to their element alignment
+        let mut msg = Writer::default();
+        msg.u32(0);
+        msg.align(8);
+        msg.str("public");
+        let values = Reader::new(&msg.0, 0, false).values("a(ss)s").unwrap();
+        assert_eq!(values, [Value::Array(vec![]), Value::Str("public".into())]);
+    }
+}
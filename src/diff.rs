@@ -0,0 +1,84 @@
+//! --diff: the listeners added and removed since a --json snapshot, e.g. to review a deployment
+
+use crate::{
+    netlink::sock::Protocol,
+    options::{Column, Filters},
+    rows::{rows, Row},
+    IfaceInfo, Listing,
+};
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use std::{collections::BTreeSet, io::Write};
+
+/// What identifies a listener between runs. Pids, inodes and file descriptors change whenever
+/// its process restarts, so they don't.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Listener {
+    port: u16,
+    protocol: Protocol,
+    addr: String,
+    owner: String,
+    /// Empty for AF_UNIX sockets, the ethertype for AF_PACKET sockets
+    port_label: String,
+}
+
+impl Listener {
+    fn new(row: &Row) -> Self {
+        Listener {
+            port: row.sock.port,
+            protocol: row.sock.protocol,
+            addr: row.get(Column::Addr),
+            owner: row.get(Column::Cmd),
+            port_label: match row.get(Column::Port) {
+                port if port.parse::<u16>().is_ok() => format!(":{port}"),
+                port => port,
+            },
+        }
+    }
+}
+
+/// The snapshot with the filters that the current listing got while it was collected
+pub fn filter(snapshot: &mut Listing, filters: &Filters) {
+    snapshot.processes.retain(|pd| filters.accept_process(pd));
+    if !filters.accept_wg() {
+        snapshot.interfaces.clear();
+        snapshot.unknown.clear();
+    }
+    snapshot
+        .unknown
+        .retain(|&(uid, _)| filters.accept_user(uid));
+}
+
+/// Print + for listeners that are new since the snapshot, - for those that are gone, and
+/// whether there were any
+pub fn write(
+    listing: &Listing,
+    iface_info: &IfaceInfo,
+    snapshot: &Listing,
+    snapshot_ifaces: &IfaceInfo,
+    filters: &Filters,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let now = (rows(listing, iface_info, filters).map(|row| Listener::new(&row)))
+        .collect::<BTreeSet<_>>();
+    let then = (rows(snapshot, snapshot_ifaces, filters).map(|row| Listener::new(&row)))
+        .collect::<BTreeSet<_>>();
+    let removed = then.difference(&now).map(|l| ('-', l));
+    let added = now.difference(&then).map(|l| ('+', l));
+    let mut changes = removed.chain(added).collect::<Vec<_>>();
+    changes.sort_by_key(|&(_, l)| l);
+    // As the collapsed tree: process / :port protocol / address
+    for (sign, l) in &changes {
+        let owner = match l.owner.as_str() {
+            "" => "???",
+            owner => owner,
+        };
+        let port = format!("{} {}", l.port_label, l.protocol);
+        let line = [owner, port.trim_start(), &l.addr]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .join(" / ");
+        writeln!(out, "{sign} {line}").context("Write output")?;
+    }
+    Ok(!changes.is_empty())
+}
@@ -0,0 +1,281 @@
+//! Services that announce themselves on the network through mDNS (DNS-SD) or SSDP, for --discover
+//!
+//! Both protocols answer multicast queries. Answers from this machine's own addresses name the
+//! local ports that are advertised to everyone on the link.
+
+use crate::{
+    log,
+    netlink::sock::{Protocol, SockInfo},
+    Ino,
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+const MDNS: (Ipv4Addr, u16) = (Ipv4Addr::new(224, 0, 0, 251), 5353);
+const SSDP: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 1900);
+const SERVICES: &str = "_services._dns-sd._udp.local";
+const PTR: u16 = 12;
+const SRV: u16 = 33;
+
+/// How each local port is announced, e.g. mDNS _http._tcp
+pub type Announcements = BTreeMap<(u16, Protocol), BTreeSet<String>>;
+
+/// Query mDNS and SSDP, and collect what local addresses answer for the given time
+pub fn discover(local: &[IpAddr], time: Duration) -> Result<Announcements> {
+    let deadline = Instant::now() + time;
+    let mdns = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).context("Bind mDNS socket")?;
+    let ssdp = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).context("Bind SSDP socket")?;
+    for socket in [&mdns, &ssdp] {
+        // Responders on this machine only see queries that are looped back
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_nonblocking(true)?;
+    }
+    let search = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+                  MAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n\r\n";
+    ssdp.send_to(search.as_bytes(), SSDP)
+        .context("Send SSDP search")?;
+    mdns.send_to(&query(&[(SERVICES, PTR)]), MDNS)
+        .context("Send mDNS query")?;
+    let mut ret = Announcements::new();
+    let mut asked = BTreeSet::from([SERVICES.to_owned()]);
+    let mut instances = BTreeSet::new();
+    let mut buf = [0; 9000];
+    while Instant::now() < deadline {
+        let mut idle = true;
+        while let Ok((len, from)) = ssdp.recv_from(&mut buf) {
+            idle = false;
+            let port = ssdp_location(&String::from_utf8_lossy(&buf[..len]));
+            if let Some(port) = port.filter(|_| is_local(local, from)) {
+                let labels = ret.entry((port, Protocol::TCP)).or_default();
+                labels.insert("SSDP".into());
+            }
+        }
+        while let Ok((len, from)) = mdns.recv_from(&mut buf) {
+            idle = false;
+            let Some(records) = records(&buf[..len]) else {
+                continue;
+            };
+            let mut questions = Vec::new();
+            for record in records {
+                match record {
+                    Record::Ptr(owner, target) if owner == SERVICES => {
+                        if asked.insert(target.clone()) {
+                            questions.push((target, PTR));
+                        }
+                    }
+                    Record::Ptr(_, instance) => {
+                        if !instances.contains(&instance) && asked.insert(instance.clone()) {
+                            questions.push((instance, SRV));
+                        }
+                    }
+                    Record::Srv(instance, port) => {
+                        instances.insert(instance.clone());
+                        if let (Some((proto, service)), true) =
+                            (service(&instance), is_local(local, from))
+                        {
+                            ret.entry((port, proto))
+                                .or_default()
+                                .insert(format!("mDNS {service}"));
+                        }
+                    }
+                }
+            }
+            if !questions.is_empty() {
+                let questions = (questions.iter()).map(|(n, t)| (n.as_str(), *t));
+                mdns.send_to(&query(&questions.collect::<Vec<_>>()), MDNS)
+                    .context("Send mDNS query")?;
+            }
+        }
+        if idle {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+    Ok(ret)
+}
+
+fn is_local(local: &[IpAddr], from: SocketAddr) -> bool {
+    local.contains(&from.ip())
+}
+
+/// Set how the sockets' ports are announced, and mention announced ports that nobody listens on
+pub fn annotate(socks: &mut HashMap<Ino, SockInfo>, announcements: &Announcements) {
+    for ((port, proto), labels) in announcements {
+        let mut listening = false;
+        for sock in socks.values_mut() {
+            if sock.port == *port && sock.protocol == *proto {
+                sock.announced = labels.iter().cloned().collect();
+                listening = true;
+            }
+        }
+        if !listening {
+            let labels = labels.iter().cloned().collect::<Vec<_>>().join(", ");
+            log::info!("Port :{port} {proto} is announced ({labels}), but nothing listens on it");
+        }
+    }
+}
+
+/// DNS query with the given names and types, asking for unicast responses
+fn query(questions: &[(&str, u16)]) -> Vec<u8> {
+    let mut msg = vec![0; 12];
+    msg[4..6].copy_from_slice(&(questions.len() as u16).to_be_bytes());
+    for (name, kind) in questions {
+        for label in name.split('.') {
+            msg.push(label.len() as u8);
+            msg.extend(label.as_bytes());
+        }
+        msg.push(0);
+        msg.extend(kind.to_be_bytes());
+        // Class IN with the unicast-response bit
+        msg.extend(0x8001u16.to_be_bytes());
+    }
+    msg
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Record {
+    /// Owner and target name
+    Ptr(String, String),
+    /// Instance name and port
+    Srv(String, u16),
+}
+
+/// PTR and SRV records out of all sections of a DNS response
+fn records(msg: &[u8]) -> Option<Vec<Record>> {
+    let u16_at = |pos: usize| Some(u16::from_be_bytes([*msg.get(pos)?, *msg.get(pos + 1)?]));
+    if u16_at(2)? & 0x8000 == 0 {
+        // A query, e.g. our own looped back
+        return Some(Vec::new());
+    }
+    let questions = u16_at(4)?;
+    let records = u16_at(6)? as usize + u16_at(8)? as usize + u16_at(10)? as usize;
+    let mut pos = 12;
+    for _ in 0..questions {
+        name(msg, &mut pos)?;
+        pos += 4;
+    }
+    let mut ret = Vec::new();
+    for _ in 0..records {
+        let owner = name(msg, &mut pos)?;
+        let kind = u16_at(pos)?;
+        let len = u16_at(pos + 8)? as usize;
+        let mut data = pos + 10;
+        pos = data + len;
+        match kind {
+            PTR => ret.push(Record::Ptr(owner, name(msg, &mut data)?)),
+            SRV => ret.push(Record::Srv(owner, u16_at(data + 4)?)),
+            _ => (),
+        }
+    }
+    Some(ret)
+}
+
+/// A possibly compressed domain name, advancing pos past it
+fn name(msg: &[u8], pos: &mut usize) -> Option<String> {
+    let mut labels = Vec::new();
+    let mut at = *pos;
+    let mut jumped = false;
+    // Pointers only go backwards, but a bound keeps malicious loops finite
+    for _ in 0..128 {
+        let len = *msg.get(at)? as usize;
+        match len {
+            0 => {
+                if !jumped {
+                    *pos = at + 1;
+                }
+                return Some(labels.join("."));
+            }
+            0xc0.. => {
+                if !jumped {
+                    *pos = at + 2;
+                }
+                jumped = true;
+                at = (len & 0x3f) << 8 | *msg.get(at + 1)? as usize;
+            }
+            _ => {
+                labels.push(String::from_utf8_lossy(msg.get(at + 1..at + 1 + len)?).into_owned());
+                at += 1 + len;
+            }
+        }
+    }
+    None
+}
+
+/// Protocol and service type of an instance name like My Printer._ipp._tcp.local
+fn service(instance: &str) -> Option<(Protocol, &str)> {
+    let local = instance.strip_suffix(".local")?;
+    let (rest, proto) = local.rsplit_once('.')?;
+    let protocol = match proto {
+        "_tcp" => Protocol::TCP,
+        "_udp" => Protocol::UDP,
+        _ => return None,
+    };
+    let (_, kind) = rest.rsplit_once('.')?;
+    Some((
+        protocol,
+        &local[local.len() - kind.len() - 1 - proto.len()..],
+    ))
+}
+
+/// Value of a header of an SSDP response
+pub fn ssdp_header<'r>(response: &'r str, key: &str) -> Option<&'r str> {
+    response.lines().find_map(|line| {
+        let (k, value) = line.split_once(':')?;
+        k.trim().eq_ignore_ascii_case(key).then(|| value.trim())
+    })
+}
+
+/// Port of the LOCATION URL of an SSDP response
+fn ssdp_location(response: &str) -> Option<u16> {
+    let location = ssdp_header(response, "location")?;
+    let authority = location.split_once("://")?.1.split('/').next()?;
+    match authority.rsplit_once(':') {
+        Some((_, port)) if !port.ends_with(']') => port.parse().ok(),
+        _ => location.starts_with("http:").then_some(80),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{name, query, records, service, ssdp_location, Record, PTR};
+    use crate::netlink::sock::Protocol;
+
+    #[test]
+    fn responses() {
+        let q = query(&[("_http._tcp.local", PTR)]);
+        assert_eq!(name(&q, &mut 12).as_deref(), Some("_http._tcp.local"));
+        // Answer: _http._tcp.local PTR web._http._tcp.local, additional: SRV port 8080
+        let mut msg = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 1];
+        msg.extend(&q[12..q.len() - 4]);
+        msg.extend([
+            0, 12, 0, 1, 0, 0, 0, 120, 0, 6, 3, b'w', b'e', b'b', 0xc0, 12,
+        ]);
+        msg.extend([
+            0xc0, 40, 0, 33, 0, 1, 0, 0, 0, 120, 0, 8, 0, 0, 0, 0, 0x1f, 0x90, 0xc0, 12,
+        ]);
+        assert_eq!(
+            records(&msg).unwrap(),
+            [
+                Record::Ptr("_http._tcp.local".into(), "web._http._tcp.local".into()),
+                Record::Srv("web._http._tcp.local".into(), 8080),
+            ]
+        );
+        assert_eq!(records(&q).unwrap(), []);
+        assert!(records(&msg[..msg.len() - 4]).is_none());
+        assert_eq!(
+            service("My Printer._ipp._tcp.local"),
+            Some((Protocol::TCP, "_ipp._tcp"))
+        );
+        assert_eq!(service("_http._tcp.local"), None);
+        let ssdp = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\n\
+                    Location: http://192.0.2.2:49152/rootDesc.xml\r\nST: upnp:rootdevice\r\n\r\n";
+        assert_eq!(ssdp_location(ssdp), Some(49152));
+        assert_eq!(
+            ssdp_location("LOCATION: http://[fd00::2]/d.xml\r\n"),
+            Some(80)
+        );
+    }
+}
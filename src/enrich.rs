@@ -0,0 +1,138 @@
+//! Add fields from an external command to the JSON output, for --enrich
+//!
+//! The command gets one --json flat socket record per line on stdin, and answers with one JSON
+//! object (or null) per line on stdout, in the same order. Its fields are added to the socket.
+//! A command that hasn't answered and exited within [TIMEOUT] is killed.
+
+use crate::Ino;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    os::unix::process::CommandExt,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Fields to add to sockets, by inode
+pub type Enrichment = HashMap<Ino, Map<String, Value>>;
+
+pub struct Enricher {
+    cmd: String,
+    child: Child,
+}
+
+impl Enricher {
+    /// Start cmd, once the command line is known to be valid, but before the sandbox forbids
+    /// executing programs
+    pub fn spawn(cmd: &str) -> Result<Enricher> {
+        let mut command = Command::new("/bin/sh");
+        command
+            .args(["-c", cmd])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        // The sandbox doesn't allow killing it, so it goes when lls exits
+        // SAFETY: prctl is async-signal-safe
+        unsafe {
+            command.pre_exec(
+                || match libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) {
+                    0 => Ok(()),
+                    _ => Err(std::io::Error::last_os_error()),
+                },
+            )
+        };
+        let child = command
+            .spawn()
+            .with_context(|| format!("Start enrichment command {cmd:?}"))?;
+        Ok(Enricher {
+            cmd: cmd.into(),
+            child,
+        })
+    }
+
+    /// Pass records through the command
+    pub fn run(mut self, records: Vec<Value>) -> Result<Enrichment> {
+        let deadline = Instant::now() + TIMEOUT;
+        let result = self.exchange(records, deadline);
+        if result.is_err() {
+            // Don't leave it running, e.g. when it timed out, unless the sandbox forbids it
+            if self.child.kill().is_ok() {
+                self.child.wait().ok();
+            }
+        }
+        result
+    }
+
+    fn exchange(&mut self, records: Vec<Value>, deadline: Instant) -> Result<Enrichment> {
+        let cmd = &self.cmd;
+        let stdin = self.child.stdin.take().expect("Piped stdin");
+        let stdout = self.child.stdout.take().expect("Piped stdout");
+        let inodes = (records.iter())
+            .map(|r| r["inode"].as_u64().expect("Sockets have inodes"))
+            .collect::<Vec<_>>();
+        // Write from another thread, the command may answer before it has read everything
+        let writer = std::thread::spawn(move || -> std::io::Result<()> {
+            let mut stdin = BufWriter::new(stdin);
+            for record in records {
+                serde_json::to_writer(&mut stdin, &record)?;
+                stdin.write_all(b"\n")?;
+            }
+            stdin.flush()
+        });
+        // Read from another thread too, so a command that hangs can be given up on
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let lines = BufReader::new(stdout).lines();
+            tx.send(lines.collect::<Result<Vec<_>, _>>())
+        });
+        let timed_out = || anyhow!("Enrichment command {cmd:?} timed out after {TIMEOUT:?}");
+        let answers = rx
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .map_err(|_| timed_out())?
+            .with_context(|| format!("Read from enrichment command {cmd:?}"))?;
+        let status = wait(&mut self.child, deadline)
+            .context("Wait for enrichment command")?
+            .ok_or_else(timed_out)?;
+        ensure!(
+            status.success(),
+            "Enrichment command {cmd:?} failed: {status}"
+        );
+        match writer.join().expect("Enrichment writer panicked") {
+            // Commands that know enough without reading all records may exit early
+            Err(e) if e.kind() != ErrorKind::BrokenPipe => {
+                Err(e).with_context(|| format!("Write to enrichment command {cmd:?}"))?
+            }
+            _ => (),
+        }
+        ensure!(
+            answers.len() == inodes.len(),
+            "Enrichment command {cmd:?} answered {} lines for {} sockets",
+            answers.len(),
+            inodes.len()
+        );
+        let mut ret = Enrichment::new();
+        for (ino, line) in inodes.into_iter().zip(answers) {
+            match serde_json::from_str(&line) {
+                Ok(Value::Object(fields)) => ret.entry(ino).or_default().extend(fields),
+                Ok(Value::Null) => (),
+                _ => bail!("Enrichment command {cmd:?} answered {line:?}, expected a JSON object"),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// The exit status of child, or None if it is still running at the deadline
+fn wait(child: &mut Child, deadline: Instant) -> std::io::Result<Option<ExitStatus>> {
+    loop {
+        match child.try_wait()? {
+            Some(status) => return Ok(Some(status)),
+            None if Instant::now() >= deadline => return Ok(None),
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Exit codes and the --errors json stream, for wrappers that react to failures
+
+use crate::log::quote;
+use itertools::Itertools;
+use std::{
+    fmt,
+    io::{self, stderr, Write},
+    sync::atomic::{AtomicBool, Ordering::Relaxed},
+};
+
+/// Documented in help.txt, don't renumber
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Some sockets were shown
+    Matches = 0,
+    /// Nothing matched the filters
+    NoMatches = 1,
+    /// Bad command line
+    Usage = 2,
+    /// Output was produced (or suppressed by --strict), but data is missing
+    Partial = 3,
+    /// No output
+    Failure = 4,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Matches => "matches",
+            Status::NoMatches => "no_matches",
+            Status::Usage => "usage",
+            Status::Partial => "partial",
+            Status::Failure => "failure",
+        }
+    }
+}
+
+/// An error in the command line rather than in collecting data
+#[derive(Debug)]
+pub struct Usage(pub anyhow::Error);
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only the outermost message, the rest of the chain is the source
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Usage {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// A command line error as [Usage], unless it is an I/O failure, e.g. to create a file it names
+pub fn usage(e: anyhow::Error) -> anyhow::Error {
+    match e.chain().any(|e| e.is::<io::Error>()) {
+        true => e,
+        false => Usage(e).into(),
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json(json: bool) {
+    JSON.store(json, Relaxed);
+}
+
+/// An object per line, from keys and their values as JSON
+fn record(fields: &[(&str, String)]) {
+    let fields = (fields.iter()).format_with(",", |(key, value), f| {
+        f(&format_args!("{}:{value}", quote(key)))
+    });
+    writeln!(stderr(), "{{{fields}}}").ok();
+}
+
+/// Report a problem that left the data incomplete, for --errors json
+pub fn partial(e: &anyhow::Error) {
+    if JSON.load(Relaxed) {
+        record(&[
+            ("error", quote(Status::Partial.name())),
+            ("message", quote(&format!("{e:#}"))),
+        ]);
+    }
+}
+
+fn broken_pipe(e: &anyhow::Error) -> bool {
+    (e.chain()).any(|e| {
+        e.downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+    })
+}
+
+/// Report the outcome and exit with its code
+pub fn exit(result: anyhow::Result<Status>) -> ! {
+    let status = match result {
+        Ok(status) => status,
+        // The reader, e.g. head, has seen enough
+        Err(e) if broken_pipe(&e) => Status::Matches,
+        Err(e) => {
+            let status = match e.downcast_ref::<Usage>() {
+                Some(_) => Status::Usage,
+                None => Status::Failure,
+            };
+            match JSON.load(Relaxed) {
+                true => record(&[
+                    ("error", quote(status.name())),
+                    ("message", quote(&format!("{e:#}"))),
+                ]),
+                false => eprintln!("Error: {e:?}"),
+            }
+            status
+        }
+    };
+    if JSON.load(Relaxed) {
+        record(&[
+            ("exit", (status as i32).to_string()),
+            ("status", quote(status.name())),
+        ]);
+    }
+    std::process::exit(status as i32)
+}
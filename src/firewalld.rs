@@ -0,0 +1,115 @@
+//! Zones and services of firewalld that open ports, for --firewalld
+//!
+//! firewalld's runtime configuration, which may differ from the files in /etc/firewalld, is only
+//! available over D-Bus. Active zones are those with interfaces or sources.
+
+use crate::{
+    dbus::{Bus, Value},
+    log,
+    netlink::sock::{Protocol, SockInfo},
+    Ino,
+};
+use anyhow::Result;
+use std::{collections::HashMap, ops::RangeInclusive};
+
+const NAME: &str = "org.fedoraproject.FirewallD1";
+const PATH: &str = "/org/fedoraproject/FirewallD1";
+
+/// Ports that a zone opens, e.g. labeled public: ssh or public: 8080/tcp
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub ports: RangeInclusive<u16>,
+    pub proto: Protocol,
+    pub label: String,
+}
+
+/// Rules of all active zones
+pub fn rules() -> Result<Vec<Rule>> {
+    let mut bus = Bus::system()?;
+    let mut call = |interface: &str, method: &str, args: &[&str]| {
+        bus.call(NAME, PATH, interface, method, args)
+    };
+    let zone_interface = format!("{NAME}.zone");
+    let active = call(&zone_interface, "getActiveZones", &[])?;
+    let zones = (active.first().map_or(&[][..], Value::items).iter())
+        .filter_map(|entry| entry.items().first()?.str().map(String::from))
+        .collect::<Vec<_>>();
+    let mut ret = Vec::new();
+    let mut services = HashMap::new();
+    for zone in zones {
+        let ports = call(&zone_interface, "getPorts", &[&zone])?;
+        for port in ports.first().map_or(&[][..], Value::items) {
+            if let [port, proto] = port.items() {
+                let (Some(port), Some(proto)) = (port.str(), proto.str()) else {
+                    continue;
+                };
+                let label = format!("{zone}: {port}/{proto}");
+                ret.extend(rule(port, proto, label));
+            }
+        }
+        for service in call(&zone_interface, "getServices", &[&zone])? {
+            for service in service.items().iter().filter_map(Value::str) {
+                if !services.contains_key(service) {
+                    // A broken definition shouldn't hide the others
+                    let ports = service_ports(&mut call, service).unwrap_or_else(|e| {
+                        log::warn!("firewalld service {service}: {e:#}");
+                        Vec::new()
+                    });
+                    services.insert(service.to_owned(), ports);
+                }
+                for (port, proto) in &services[service] {
+                    ret.extend(rule(port, proto, format!("{zone}: {service}")));
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Port and protocol pairs of a service definition
+fn service_ports(
+    call: &mut impl FnMut(&str, &str, &[&str]) -> Result<Vec<Value>>,
+    service: &str,
+) -> Result<Vec<(String, String)>> {
+    let pairs = |ports: &Value| {
+        (ports.items().iter())
+            .filter_map(|pair| match pair.items() {
+                [port, proto] => Some((port.str()?.to_owned(), proto.str()?.to_owned())),
+                _ => None,
+            })
+            .collect()
+    };
+    // a{sv} since firewalld 0.9, before that a struct with the ports as fourth field
+    match call(NAME, "getServiceSettings2", &[service]) {
+        Ok(settings) => Ok(settings
+            .first()
+            .and_then(|s| s.get("ports"))
+            .map(pairs)
+            .unwrap_or_default()),
+        Err(_) => {
+            let settings = call(NAME, "getServiceSettings", &[service])?;
+            let ports = settings.first().and_then(|s| s.items().get(3));
+            Ok(ports.map(pairs).unwrap_or_default())
+        }
+    }
+}
+
+/// A port or range like 8000-8100, for protocols lls knows
+fn rule(port: &str, proto: &str, label: String) -> Option<Rule> {
+    let (start, end) = port.split_once('-').unwrap_or((port, port));
+    Some(Rule {
+        ports: start.parse().ok()?..=end.parse().ok()?,
+        proto: proto.parse().ok()?,
+        label,
+    })
+}
+
+/// Set the rules that open the ports of sockets reachable from other machines
+pub fn annotate(socks: &mut HashMap<Ino, SockInfo>, rules: &[Rule]) {
+    for sock in socks.values_mut().filter(|s| !s.addr.is_loopback()) {
+        sock.firewalld = (rules.iter())
+            .filter(|r| r.ports.contains(&sock.port) && r.proto == sock.protocol)
+            .map(|r| r.label.clone())
+            .collect();
+    }
+}
@@ -0,0 +1,232 @@
+//! Port mappings that the router forwards to this machine, for --gateway
+//!
+//! UPnP internet gateway devices answer SSDP searches with the URL of their description, which
+//! names the control URL of their WAN connection service. Its GetGenericPortMappingEntry action
+//! lists the mappings one by one. NAT-PMP and PCP can only create mappings, not list them.
+
+use crate::{
+    discovery, http, log,
+    netlink::sock::{Protocol, SockInfo},
+    Ino,
+};
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+const SSDP: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 1900);
+const DEVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:1",
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:2",
+];
+const SERVICES: [&str; 2] = ["WANIPConnection", "WANPPPConnection"];
+/// Routers have a few dozen at most, this just ends the loop for broken ones
+const MAX_MAPPINGS: usize = 1024;
+
+/// A port forwarded by the router to a local address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub client: IpAddr,
+    pub port: u16,
+    pub proto: Protocol,
+    /// e.g. 203.0.113.7:8080, or :8080 if the router doesn't tell its address
+    pub external: String,
+}
+
+/// Search for gateways for the given time, and list their mappings to the local addresses
+pub fn mappings(local: &[IpAddr], time: Duration) -> Result<Vec<Mapping>> {
+    let locations = search(time)?;
+    if locations.is_empty() {
+        log::info!("No UPnP internet gateway answered, port mappings are unknown");
+    }
+    let mut ret = Vec::new();
+    for location in locations {
+        let mappings = gateway(&location).with_context(|| format!("Query gateway {location}"))?;
+        ret.extend(mappings.into_iter().filter(|m| local.contains(&m.client)));
+    }
+    Ok(ret)
+}
+
+/// Description URLs of the gateways that answer an SSDP search
+fn search(time: Duration) -> Result<Vec<String>> {
+    let deadline = Instant::now() + time;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).context("Bind SSDP socket")?;
+    socket.set_nonblocking(true)?;
+    for device in DEVICES {
+        let search = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+             MAN: \"ssdp:discover\"\r\nMX: 1\r\nST: {device}\r\n\r\n"
+        );
+        (socket.send_to(search.as_bytes(), SSDP)).context("Send SSDP search")?;
+    }
+    let mut ret = Vec::new();
+    let mut buf = [0; 9000];
+    while Instant::now() < deadline {
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                let response = String::from_utf8_lossy(&buf[..len]);
+                if let Some(location) = discovery::ssdp_header(&response, "location") {
+                    if !ret.iter().any(|l| l == location) {
+                        ret.push(location.to_owned());
+                    }
+                }
+            }
+            Err(_) => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+    Ok(ret)
+}
+
+/// Mappings of the gateway with the given description URL
+fn gateway(location: &str) -> Result<Vec<Mapping>> {
+    let (host, _) = http::split_url(location)?;
+    let description = http::request("GET", location, &[], "")?;
+    let Some((service, control)) = wan_service(&description) else {
+        log::info!("Gateway {location} has no WAN connection service");
+        return Ok(Vec::new());
+    };
+    let control = match control.contains("://") {
+        true => control,
+        false => format!("http://{host}/{}", control.trim_start_matches('/')),
+    };
+    let soap = |action: &str, args: &str| {
+        let body = format!(
+            "<?xml version=\"1.0\"?>\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+             s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body>\
+             <u:{action} xmlns:u=\"{service}\">{args}</u:{action}></s:Body></s:Envelope>"
+        );
+        let action = format!("\"{service}#{action}\"");
+        let headers = [
+            ("Content-Type", "text/xml; charset=\"utf-8\""),
+            ("SOAPAction", &action),
+        ];
+        http::request("POST", &control, &headers, &body)
+    };
+    let external = (soap("GetExternalIPAddress", ""))
+        .ok()
+        .and_then(|response| tag(&response, "NewExternalIPAddress"))
+        .unwrap_or_default();
+    let mut ret = Vec::new();
+    for index in 0..MAX_MAPPINGS {
+        let args = format!("<NewPortMappingIndex>{index}</NewPortMappingIndex>");
+        // Gateways answer with a SOAP fault after the last entry
+        let Ok(response) = soap("GetGenericPortMappingEntry", &args) else {
+            break;
+        };
+        if let Some(mapping) = mapping(&response, &external) {
+            ret.push(mapping);
+        }
+    }
+    Ok(ret)
+}
+
+/// Service type and control URL of the first WAN connection service in a device description
+fn wan_service(description: &str) -> Option<(String, String)> {
+    description.split("<service>").skip(1).find_map(|service| {
+        let kind =
+            tag(service, "serviceType").filter(|k| SERVICES.iter().any(|s| k.contains(s)))?;
+        Some((kind, tag(service, "controlURL")?))
+    })
+}
+
+/// An enabled mapping out of a GetGenericPortMappingEntry response
+fn mapping(response: &str, external: &str) -> Option<Mapping> {
+    if tag(response, "NewEnabled").as_deref() == Some("0") {
+        return None;
+    }
+    let external_port = tag(response, "NewExternalPort")?;
+    Some(Mapping {
+        client: tag(response, "NewInternalClient")?.parse().ok()?,
+        port: tag(response, "NewInternalPort")?.parse().ok()?,
+        proto: tag(response, "NewProtocol")?.parse().ok()?,
+        external: match external.parse::<IpAddr>() {
+            Ok(IpAddr::V6(addr)) => format!("[{addr}]:{external_port}"),
+            Ok(addr) => format!("{addr}:{external_port}"),
+            Err(_) => format!(":{external_port}"),
+        },
+    })
+}
+
+/// Text of the first element with the given name, without namespace prefix
+fn tag(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{name}>"))? + name.len() + 2;
+    let len = xml[start..].find(&format!("</{name}>"))?;
+    let text = xml[start..start + len].trim();
+    Some(
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+/// Set how sockets are exposed through the router, and mention mappings to closed ports
+pub fn annotate(socks: &mut HashMap<Ino, SockInfo>, mappings: &[Mapping]) {
+    for mapping in mappings {
+        let mut listening = false;
+        for sock in socks.values_mut() {
+            if sock.port == mapping.port
+                && sock.protocol == mapping.proto
+                && (sock.addr.is_unspecified() || sock.addr == mapping.client)
+            {
+                sock.exposed.push(mapping.external.clone());
+                listening = true;
+            }
+        }
+        if !listening {
+            log::info!(
+                "The router forwards {} {} to {}, but nothing listens on it",
+                mapping.external,
+                mapping.proto,
+                SocketAddr::new(mapping.client, mapping.port),
+            );
+        }
+    }
+    for sock in socks.values_mut() {
+        sock.exposed = sock.exposed.drain(..).unique().collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{mapping, wan_service, Mapping};
+    use crate::netlink::sock::Protocol;
+
+    #[test]
+    fn responses() {
+        let description = "<root><device><serviceList>\
+            <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+            <controlURL>/ctl/L3F</controlURL></service>\
+            <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+            <controlURL>/ctl/IPConn</controlURL></service>\
+            </serviceList></device></root>";
+        assert_eq!(
+            wan_service(description),
+            Some((
+                "urn:schemas-upnp-org:service:WANIPConnection:1".into(),
+                "/ctl/IPConn".into()
+            ))
+        );
+        let entry = "<NewRemoteHost></NewRemoteHost><NewExternalPort>8443</NewExternalPort>\
+            <NewProtocol>TCP</NewProtocol><NewInternalPort>443</NewInternalPort>\
+            <NewInternalClient>192.0.2.2</NewInternalClient><NewEnabled>1</NewEnabled>\
+            <NewPortMappingDescription>web &amp; co</NewPortMappingDescription>";
+        assert_eq!(
+            mapping(entry, "203.0.113.7"),
+            Some(Mapping {
+                client: [192, 0, 2, 2].into(),
+                port: 443,
+                proto: Protocol::TCP,
+                external: "203.0.113.7:8443".into(),
+            })
+        );
+        assert_eq!(mapping(entry, "").unwrap().external, ":8443");
+        let disabled = entry.replace("<NewEnabled>1", "<NewEnabled>0");
+        assert_eq!(mapping(&disabled, ""), None);
+    }
+}
@@ -0,0 +1,96 @@
+//! Just enough HTTP/1.1 for talking to routers and cloud metadata services
+
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Host with port, and path of an http URL
+pub fn split_url(url: &str) -> Result<(&str, &str)> {
+    let Some(rest) = url.strip_prefix("http://") else {
+        bail!("Only http URLs are supported, not {url:?}");
+    };
+    Ok(match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    })
+}
+
+/// Body of the response to a request, if its status is 200
+pub fn request(method: &str, url: &str, headers: &[(&str, &str)], body: &str) -> Result<String> {
+    let (host, path) = split_url(url)?;
+    let port = match host.rsplit_once(':') {
+        Some((_, port)) if !port.ends_with(']') => "",
+        _ => ":80",
+    };
+    let addr = (format!("{host}{port}").to_socket_addrs())
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .with_context(|| format!("Resolve {host}"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT).context("Connect")?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut request = format!("{method} {path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n");
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if method != "GET" {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    stream
+        .write_all(request.as_bytes())
+        .context("Send request")?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).context("Read response")?;
+    response_body(&String::from_utf8_lossy(&response))
+}
+
+/// Body of a 200 response, dechunked if necessary
+fn response_body(response: &str) -> Result<String> {
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .context("Incomplete response")?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    ensure!(status == "200", "HTTP status {status}");
+    let chunked = head.lines().skip(1).any(|line| {
+        line.split_once(':').is_some_and(|(k, v)| {
+            k.trim().eq_ignore_ascii_case("transfer-encoding") && v.trim() == "chunked"
+        })
+    });
+    if !chunked {
+        return Ok(body.into());
+    }
+    let mut ret = String::new();
+    let mut rest = body;
+    loop {
+        let (size, data) = rest.split_once("\r\n").context("Incomplete chunk")?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).context("Invalid chunk size")?;
+        if size == 0 {
+            return Ok(ret);
+        }
+        ret.push_str(data.get(..size).context("Incomplete chunk")?);
+        rest = data[size..].trim_start_matches("\r\n");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::response_body;
+
+    #[test]
+    fn responses() {
+        let response = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\n<u:Ge\r\n19;ext\r\ntGenericPortMappingEntry>\r\n0\r\n\r\n";
+        assert_eq!(
+            response_body(response).unwrap(),
+            "<u:GetGenericPortMappingEntry>"
+        );
+        assert!(response_body("HTTP/1.1 500 Internal Server Error\r\n\r\n<s:Fault/>").is_err());
+    }
+}
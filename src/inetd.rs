@@ -0,0 +1,183 @@
+//! Services that inetd or xinetd start for connections to their sockets
+//!
+//! Both listen on behalf of programs that only run once a connection comes in. Their
+//! configuration tells which program is behind which port.
+
+use crate::{netlink::sock::Protocol, procs::ProcDesc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::read_to_string,
+    path::Path,
+};
+
+/// Program names by port and protocol
+pub type Services = BTreeMap<(u16, Protocol), String>;
+
+/// Set the spawned programs of inetd and xinetd processes
+pub fn resolve(processes: &mut [ProcDesc]) {
+    let is = |pd: &ProcDesc, name| pd.info.comm.as_deref() == Some(name);
+    if !processes
+        .iter()
+        .any(|pd| is(pd, "inetd") || is(pd, "xinetd"))
+    {
+        return;
+    }
+    let ports = read_to_string("/etc/services").unwrap_or_default();
+    let ports = |name: &str, proto: Protocol| port(&ports, name, proto);
+    let inetd = inetd_conf(
+        &read_to_string("/etc/inetd.conf").unwrap_or_default(),
+        &ports,
+    );
+    let xinetd = xinetd_conf(&xinetd_config(), &ports);
+    for pd in processes {
+        if is(pd, "inetd") {
+            pd.spawns = inetd.clone();
+        } else if is(pd, "xinetd") {
+            pd.spawns = xinetd.clone();
+        }
+    }
+}
+
+/// Port of a numeric or named service, according to /etc/services
+fn port(services: &str, name: &str, proto: Protocol) -> Option<u16> {
+    if let Ok(port) = name.parse() {
+        return Some(port);
+    }
+    services.lines().find_map(|line| {
+        let mut words = line.split('#').next()?.split_whitespace();
+        let (service, port) = (words.next()?, words.next()?);
+        let (port, p) = port.split_once('/')?;
+        let named = service == name || words.any(|alias| alias == name);
+        (named && p.parse() == Ok(proto)).then(|| port.parse().ok())?
+    })
+}
+
+/// Lines of service, socket type, protocol, wait, user, program, and its arguments
+fn inetd_conf(conf: &str, ports: &impl Fn(&str, Protocol) -> Option<u16>) -> Services {
+    let mut ret = Services::new();
+    for line in conf.lines().filter(|l| !l.trim_start().starts_with('#')) {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let [service, _, proto, _, _, program, args @ ..] = &words[..] else {
+            continue;
+        };
+        // host:service binds to one address, the tree shows which
+        let service = service.rsplit(':').next().unwrap_or(service);
+        let Ok(proto) = proto.trim_end_matches(['4', '6']).parse() else {
+            // e.g. rpc/tcp, ports from rpcbind
+            continue;
+        };
+        let program = match *program {
+            // TCP wrappers, the actual program is its first argument
+            p if p.ends_with("/tcpd") => args.first().copied().unwrap_or(p),
+            p => p,
+        };
+        if let Some(port) = ports(service, proto) {
+            ret.insert((port, proto), basename(program).into());
+        }
+    }
+    ret
+}
+
+/// /etc/xinetd.conf and the files it includes
+fn xinetd_config() -> String {
+    let mut conf = read_to_string("/etc/xinetd.conf").unwrap_or_default();
+    let dirs = (conf.lines())
+        .filter_map(|l| l.trim().strip_prefix("includedir"))
+        .map(|dir| dir.trim().to_owned())
+        .collect::<Vec<_>>();
+    for dir in dirs {
+        let files = std::fs::read_dir(Path::new(&dir)).into_iter().flatten();
+        for file in files.flatten() {
+            // xinetd skips files with a dot or tilde, e.g. backups
+            let name = file.file_name();
+            let name = name.to_string_lossy();
+            if !name.contains(['.', '~']) {
+                conf.push('\n');
+                conf.push_str(&read_to_string(file.path()).unwrap_or_default());
+            }
+        }
+    }
+    conf
+}
+
+/// Blocks like service telnet { socket_type = stream server = /usr/sbin/in.telnetd … }
+fn xinetd_conf(conf: &str, ports: &impl Fn(&str, Protocol) -> Option<u16>) -> Services {
+    let mut ret = Services::new();
+    let mut service = None;
+    let mut attrs = HashMap::new();
+    for line in conf
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or_default().trim())
+    {
+        if let Some(name) = line.strip_prefix("service") {
+            service = Some(name.trim().to_owned());
+            attrs.clear();
+        } else if line == "}" {
+            let Some(name) = service.take() else {
+                continue;
+            };
+            let attr = |key| attrs.get(key).map(String::as_str);
+            if attr("disable") == Some("yes") {
+                continue;
+            }
+            let proto = match (attr("protocol"), attr("socket_type")) {
+                (Some(proto), _) => proto.parse().ok(),
+                (None, Some("dgram")) => Some(Protocol::UDP),
+                (None, _) => Some(Protocol::TCP),
+            };
+            let Some(proto) = proto else {
+                continue;
+            };
+            let port = match attr("port") {
+                Some(port) => port.parse().ok(),
+                None => ports(&name, proto),
+            };
+            let program = match attr("type") {
+                Some(t) if t.contains("INTERNAL") => Some("internal"),
+                _ => attr("server").map(basename),
+            };
+            if let (Some(port), Some(program)) = (port, program) {
+                ret.insert((port, proto), program.into());
+            }
+        } else if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim_end_matches(['+', '-']).trim();
+            attrs.insert(key.to_owned(), value.trim().to_owned());
+        }
+    }
+    ret
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{inetd_conf, port, xinetd_conf};
+    use crate::netlink::sock::Protocol;
+
+    #[test]
+    fn configs() {
+        let services = "ftp\t\t21/tcp\ntelnet\t\t23/tcp\ntftp\t\t69/udp\n";
+        let ports = |name: &str, proto| port(services, name, proto);
+        let inetd = inetd_conf(
+            "# comment\n\
+             telnet stream tcp6 nowait root /usr/sbin/tcpd /usr/sbin/in.telnetd\n\
+             127.0.0.1:ftp stream tcp nowait root /usr/sbin/in.ftpd in.ftpd -l\n\
+             mountd/1 dgram rpc/udp wait root /usr/sbin/rpc.mountd\n",
+            &ports,
+        );
+        assert_eq!(inetd[&(23, Protocol::TCP)], "in.telnetd");
+        assert_eq!(inetd[&(21, Protocol::TCP)], "in.ftpd");
+        assert_eq!(inetd.len(), 2);
+        let xinetd = xinetd_conf(
+            "service tftp\n{\n\tsocket_type = dgram\n\tserver = /usr/sbin/in.tftpd\n}\n\
+             service telnet\n{\n\tdisable = yes\n\tserver = /usr/sbin/in.telnetd\n}\n\
+             service echo-alt\n{\n\ttype = INTERNAL UNLISTED\n\tport = 7007\n}\n",
+            &ports,
+        );
+        assert_eq!(xinetd[&(69, Protocol::UDP)], "in.tftpd");
+        assert_eq!(xinetd[&(7007, Protocol::TCP)], "internal");
+        assert_eq!(xinetd.len(), 2);
+    }
+}
@@ -0,0 +1,50 @@
+//! One shared copy of strings that many processes or sockets have in common, like user names,
+//! executables of forked workers, or the congestion control algorithm
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+#[derive(Default)]
+pub struct Interner {
+    strs: HashSet<Rc<str>>,
+    paths: HashSet<Rc<Path>>,
+}
+
+impl Interner {
+    pub fn str(&mut self, s: &str) -> Rc<str> {
+        if let Some(interned) = self.strs.get(s) {
+            return interned.clone();
+        }
+        let interned = Rc::<str>::from(s);
+        self.strs.insert(interned.clone());
+        interned
+    }
+
+    pub fn path(&mut self, path: PathBuf) -> Rc<Path> {
+        if let Some(interned) = self.paths.get(path.as_path()) {
+            return interned.clone();
+        }
+        let interned = Rc::<Path>::from(path);
+        self.paths.insert(interned.clone());
+        interned
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Interner;
+    use std::{path::PathBuf, rc::Rc};
+
+    #[test]
+    fn shared() {
+        let mut names = Interner::default();
+        let root = names.str("root");
+        assert!(Rc::ptr_eq(&root, &names.str(&String::from("root"))));
+        assert!(!Rc::ptr_eq(&root, &names.str("nobody")));
+        let nginx = names.path(PathBuf::from("/usr/sbin/nginx"));
+        assert!(Rc::ptr_eq(&nginx, &names.path("/usr/sbin/nginx".into())));
+    }
+}
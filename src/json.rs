@@ -0,0 +1,248 @@
+use crate::{
+    enrich::Enrichment,
+    netlink::sock::{ethertype, Family, Iface, SockInfo, UnixName, UNKNOWN_UID},
+    options::{Filters, JsonShape},
+    procs::{self, ProcDesc},
+    IfaceInfo, Listing,
+};
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, io::Write, net::IpAddr};
+
+/// Version of the machine-readable output format.
+///
+/// Compatibility rules: New fields may appear in any release, consumers must ignore fields they don't know.
+/// Removing or renaming a field, or changing its type or meaning, increments the schema version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub fn write(
+    listing: &Listing,
+    iface_info: &IfaceInfo,
+    filters: &Filters,
+    shape: JsonShape,
+    enrichment: &Enrichment,
+    out: &mut impl Write,
+) -> Result<()> {
+    let doc = match shape {
+        JsonShape::Tree => json!({
+            "schema_version": SCHEMA_VERSION,
+            "groups": groups(listing, iface_info, filters, enrichment).collect::<Vec<_>>(),
+        }),
+        JsonShape::Flat => json!({
+            "schema_version": SCHEMA_VERSION,
+            "sockets": flat(listing, iface_info, filters, enrichment).collect::<Vec<_>>(),
+        }),
+    };
+    serde_json::to_writer(&mut *out, &doc).context("Write JSON")?;
+    out.write_all(b"\n").context("Write JSON")?;
+    Ok(())
+}
+
+/// With streamed, the processes have already been written by write_process
+pub fn write_lines(
+    listing: &Listing,
+    iface_info: &IfaceInfo,
+    filters: &Filters,
+    shape: JsonShape,
+    enrichment: &Enrichment,
+    streamed: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut groups = groups(listing, iface_info, filters, enrichment)
+        .filter(|group| !streamed || group["kind"] != "process");
+    match shape {
+        JsonShape::Tree => groups.try_for_each(|group| write_line(group, out)),
+        JsonShape::Flat => groups
+            .flat_map(flatten)
+            .try_for_each(|socket| write_line(socket, out)),
+    }
+}
+
+/// One process as a line of write_lines, as soon as it is known
+pub fn write_process(
+    pd: &ProcDesc,
+    filters: &Filters,
+    shape: JsonShape,
+    out: &mut impl Write,
+) -> Result<()> {
+    let group = process_group(pd, filters, &Default::default());
+    if group["sockets"].as_array().is_some_and(|s| s.is_empty()) {
+        return Ok(());
+    }
+    match shape {
+        JsonShape::Tree => write_line(group, out)?,
+        JsonShape::Flat => flatten(group).try_for_each(|socket| write_line(socket, out))?,
+    }
+    out.flush().context("Write JSON line")
+}
+
+fn write_line(mut value: Value, out: &mut impl Write) -> Result<()> {
+    value["schema_version"] = SCHEMA_VERSION.into();
+    serde_json::to_writer(&mut *out, &value).context("Write JSON line")?;
+    out.write_all(b"\n").context("Write JSON line")
+}
+
+fn process_group(pd: &ProcDesc, filters: &Filters, enrichment: &Enrichment) -> Value {
+    let mut group = process(pd);
+    group["sockets"] = sockets(&pd.sockets, filters, enrichment);
+    group
+}
+
+fn groups<'a>(
+    listing: &'a Listing,
+    iface_info: &'a IfaceInfo,
+    filters: &'a Filters,
+    enrichment: &'a Enrichment,
+) -> impl Iterator<Item = Value> + 'a {
+    let processes = (listing.processes.iter()).map(|pd| process_group(pd, filters, enrichment));
+    let interfaces = listing.interfaces.iter().map(|(if_id, socks)| {
+        json!({
+            "kind": "interface",
+            "ifindex": if_id,
+            "interface": iface_info.id2name.get(if_id),
+            "sockets": sockets(socks, filters, enrichment),
+        })
+    });
+    let unknown = listing.unknown.iter().map(|(uid, socks)| {
+        json!({
+            "kind": "unknown",
+            "uid": (*uid != UNKNOWN_UID).then_some(uid),
+            "sockets": sockets(socks, filters, enrichment),
+        })
+    });
+    processes
+        .chain(interfaces)
+        .chain(unknown)
+        .filter(|group| group["sockets"].as_array().is_some_and(|s| !s.is_empty()))
+}
+
+/// Sockets with the group they belong to embedded as "owner"
+pub fn flat<'a>(
+    listing: &'a Listing,
+    iface_info: &'a IfaceInfo,
+    filters: &'a Filters,
+    enrichment: &'a Enrichment,
+) -> impl Iterator<Item = Value> + 'a {
+    groups(listing, iface_info, filters, enrichment).flat_map(flatten)
+}
+
+fn flatten(mut group: Value) -> impl Iterator<Item = Value> {
+    let sockets = match group["sockets"].take() {
+        Value::Array(sockets) => sockets,
+        _ => Vec::new(),
+    };
+    if let Value::Object(group) = &mut group {
+        group.remove("sockets");
+    }
+    sockets.into_iter().map(move |mut socket| {
+        socket["owner"] = group.clone();
+        socket
+    })
+}
+
+pub fn process(pd: &ProcDesc) -> Value {
+    json!({
+        "kind": "process",
+        "pid": pd.pid,
+        "pids": pd.pids().collect::<Vec<_>>(),
+        "name": pd.name,
+        "user": *pd.user,
+        "uid": pd.uid,
+        "ns_uid": pd.ns_user.as_ref().map(|(uid, _)| uid),
+        "ns_user": pd.ns_user.as_ref().and_then(|(_, name)| name.as_ref()),
+        "real_uid": pd.real_user.as_ref().map(|(uid, _)| uid),
+        "real_user": pd.real_user.as_ref().map(|(_, name)| &**name),
+        "gid": pd.gid,
+        "group": pd.group.as_deref(),
+        "groups": pd.groups.iter().map(|g| &**g).collect::<Vec<_>>(),
+        "comm": pd.info.comm,
+        "exe": pd.info.exe.as_ref().map(|exe| exe.to_string_lossy()),
+        "exe_deleted": pd.exe_deleted(),
+        "cmdline": pd.info.cmdline,
+        "package": pd.package,
+        "image": pd.image,
+        "ppid": pd.ppid,
+        "cgroup": pd.cgroup,
+        "started": pd.started.map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, false)),
+        "may_bind_privileged": pd.may_bind_privileged,
+        "container_target": pd.container_target(),
+        "rss": pd.resources.map(|r| r.rss),
+        "cpu_percent": pd.resources.map(|r| r.cpu_permille as f64 / 10.),
+        "env": pd.env,
+        "spawns": (pd.spawns.iter())
+            .map(|((port, proto), program)| (format!("{port}/{proto}"), program))
+            .collect::<BTreeMap<_, _>>(),
+    })
+}
+
+fn sockets(socks: &[SockInfo], filters: &Filters, enrichment: &Enrichment) -> Value {
+    socks
+        .iter()
+        .filter(|s| filters.accept_sock(s))
+        .map(|s| {
+            let mut value = socket(s);
+            if let (Value::Object(value), Some(extra)) = (&mut value, enrichment.get(&s.ino)) {
+                for (k, v) in extra {
+                    // Never replace lls' own fields
+                    value.entry(k).or_insert_with(|| v.clone());
+                }
+            }
+            value
+        })
+        .collect()
+}
+
+pub fn socket(sock: &SockInfo) -> Value {
+    // Still as the IPv6 address it is bound to, which schema version 1 promised
+    let (family, addr) = match sock.addr {
+        IpAddr::V4(v4) if sock.v4_mapped() => (Family::V6, IpAddr::V6(v4.to_ipv6_mapped())),
+        addr => (sock.family, addr),
+    };
+    json!({
+        "port": sock.port,
+        "protocol": sock.protocol.to_string(),
+        "family": match family {
+            Family::V4 => "v4",
+            Family::V6 => "v6",
+            Family::Both => "dual",
+            Family::Unix => "unix",
+            Family::Packet => "packet",
+        },
+        "addr": sock.is_inet().then_some(addr),
+        "v4_mapped": sock.is_inet().then(|| sock.v4_mapped()),
+        "ethertype": (sock.family == Family::Packet).then(|| ethertype(sock.port)),
+        "path": match &sock.unix {
+            Some(UnixName::Path(path)) => Some(path),
+            _ => None,
+        },
+        "abstract": match &sock.unix {
+            Some(UnixName::Abstract(name)) => Some(name),
+            _ => None,
+        },
+        "iface": sock.iface.map(Iface::name),
+        "iface_bound": sock.iface.map(|i| matches!(i, Iface::Bound(_))),
+        "uid": (sock.uid != UNKNOWN_UID).then_some(sock.uid),
+        "inode": sock.ino,
+        "cookie": sock.cookie,
+        "drops": sock.drops,
+        "tos": sock.tos,
+        "priority": sock.priority,
+        "congestion": sock.congestion.as_deref(),
+        "backlog": sock.backlog,
+        "conns": sock.conns,
+        "cgroup_id": sock.cgroup,
+        "cgroup": sock.cgroup.and_then(procs::cgroup_path),
+        "rpc": sock.rpc,
+        "announced": sock.announced,
+        "exposed": sock.exposed,
+        "firewalld": sock.firewalld,
+        "cloud": sock.cloud.as_ref().map(|cloud| json!({
+            "public": cloud.public,
+            "groups": cloud.groups,
+        })),
+        "netns": sock.netns,
+        "fallback_inode": sock.fallback,
+        "fds": sock.fds,
+    })
+}
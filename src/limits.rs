@@ -0,0 +1,69 @@
+//! Bounds on how much work the process scan does, for --max-procs and --timeout
+//!
+//! Reads from /proc can block indefinitely, e.g. for processes stuck on a hung NFS mount, which
+//! no check between processes can interrupt. A watchdog thread ends lls if the scan is still
+//! busy shortly after the timeout.
+
+use crate::exit;
+use std::{
+    io::{stdout, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+static MAX_PROCS: OnceLock<usize> = OnceLock::new();
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+static DEADLINE: OnceLock<Instant> = OnceLock::new();
+static SCANNING: AtomicBool = AtomicBool::new(false);
+/// For the process being inspected when the timeout hits
+const GRACE: Duration = Duration::from_secs(1);
+
+pub fn set(max_procs: Option<usize>, timeout: Option<Duration>) {
+    if let Some(max_procs) = max_procs {
+        MAX_PROCS.set(max_procs).ok();
+    }
+    if let Some(timeout) = timeout {
+        TIMEOUT.set(timeout).ok();
+    }
+}
+
+/// Start the timeout, the waits of --discover and --gateway before don't count
+pub fn start() {
+    SCANNING.store(true, Relaxed);
+    let Some(&timeout) = TIMEOUT.get() else {
+        return;
+    };
+    if DEADLINE.set(Instant::now() + timeout).is_err() {
+        return;
+    }
+    std::thread::spawn(move || {
+        std::thread::sleep(timeout + GRACE);
+        if !SCANNING.load(Relaxed) {
+            return;
+        }
+        // Batches that --stream and --ndjson printed already
+        stdout().flush().ok();
+        exit::exit(Err(anyhow::anyhow!(
+            "Timed out after {:.1}s, likely blocked reading /proc",
+            (timeout + GRACE).as_secs_f64()
+        )));
+    });
+}
+
+/// The scan is done, writing the output isn't bounded
+pub fn finish() {
+    SCANNING.store(false, Relaxed);
+}
+
+/// Why no more processes are inspected after the given number, if so
+pub fn exceeded(inspected: usize) -> Option<String> {
+    if let Some(max) = MAX_PROCS.get().filter(|&&max| inspected >= max) {
+        return Some(format!("--max-procs {max}"));
+    }
+    (DEADLINE.get())
+        .filter(|&&deadline| Instant::now() >= deadline)
+        .map(|_| "--timeout".into())
+}
@@ -0,0 +1,125 @@
+use std::{
+    fmt::Arguments,
+    io::{stderr, Write},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering::Relaxed},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARNING",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn verbose() {
+    let level = LEVEL.load(Relaxed);
+    LEVEL.store((level + 1).min(Level::Trace as u8), Relaxed);
+}
+
+pub fn quiet() {
+    LEVEL.store(Level::Error as u8, Relaxed);
+}
+
+pub fn set_json(json: bool) {
+    JSON.store(json, Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Relaxed)
+}
+
+pub fn log(level: Level, args: Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let line = match JSON.load(Relaxed) {
+        true => format!(
+            "{{\"level\":{},\"message\":{}}}",
+            quote(level.name()),
+            quote(&args.to_string())
+        ),
+        false => format!("{}: {args}", level.prefix()),
+    };
+    writeln!(stderr(), "{line}").ok();
+}
+
+/// A JSON string, so that --log-format json and --errors json don't need the json build feature
+pub fn quote(s: &str) -> String {
+    let mut ret = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\t' => ret.push_str("\\t"),
+            c if c < ' ' => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+// Named differently and re-exported, because warn is also a builtin attribute
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Trace, format_args!($($arg)*)) };
+}
+#[allow(unused_imports)]
+pub(crate) use {debug, error, info, trace, warning as warn};
+
+#[cfg(test)]
+mod test {
+    use super::quote;
+
+    #[test]
+    fn json_strings() {
+        for s in [
+            "plain",
+            "say \"hi\"",
+            "C:\\",
+            "two\nlines\tand\u{1b}[0m",
+            "ünï",
+        ] {
+            assert_eq!(serde_json::from_str::<String>(&quote(s)).unwrap(), s);
+        }
+    }
+}
@@ -1,77 +1,533 @@
+mod apps;
+#[cfg(feature = "netlink")]
+mod bench;
+#[cfg(not(feature = "netlink"))]
+mod bench {
+    pub fn run(_: impl Iterator<Item = String>) -> anyhow::Result<crate::exit::Status> {
+        anyhow::bail!("lls was built without netlink support, which lls bench measures")
+    }
+}
+#[cfg(feature = "bundle")]
+mod bundle;
+#[cfg(not(feature = "bundle"))]
+mod bundle {
+    use crate::{
+        enrich::Enrichment,
+        options::{Capture, Filters},
+        procs::ProcDesc,
+        termtree::Tree,
+        IfaceInfo, Listing,
+    };
+    use anyhow::{bail, Result};
+
+    #[cfg(feature = "json")]
+    pub fn capture(_: &[u8]) -> Result<String> {
+        bail!("lls was built without bundle support for lls capture archives")
+    }
+
+    pub enum Bundle {}
+    impl Bundle {
+        pub fn create(_: &Capture) -> Result<Bundle> {
+            bail!("lls was built without bundle support for lls capture")
+        }
+        pub fn processes(&mut self, _: &[ProcDesc]) {
+            match *self {}
+        }
+        pub fn finish(
+            self,
+            _: &Listing,
+            _: &IfaceInfo,
+            _: &Filters,
+            _: &Enrichment,
+            _: &Tree,
+        ) -> Result<()> {
+            match self {}
+        }
+    }
+}
+mod cloud;
+mod conflicts;
+#[cfg(feature = "json")]
+mod containers;
+#[cfg(not(feature = "json"))]
+mod containers {
+    /// The runtimes' metadata is JSON, images stay unknown
+    pub fn resolve(_: &mut [crate::procs::ProcDesc]) {
+        crate::log::warn!("lls was built without JSON support, which container images need");
+    }
+}
+mod csv;
+mod dbus;
+mod diff;
+mod discovery;
+#[cfg(feature = "json")]
+mod enrich;
+#[cfg(not(feature = "json"))]
+mod enrich {
+    use anyhow::{bail, Result};
+
+    #[derive(Default)]
+    pub struct Enrichment;
+    pub enum Enricher {}
+    impl Enricher {
+        pub fn spawn(_: &str) -> Result<Enricher> {
+            bail!("lls was built without JSON support for --enrich")
+        }
+        pub fn run(self, _: Vec<()>) -> Result<Enrichment> {
+            match self {}
+        }
+    }
+}
+mod exit;
+mod firewalld;
+mod gateway;
+mod http;
+mod inetd;
+mod intern;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(not(feature = "json"))]
+mod json {
+    use crate::{
+        enrich::Enrichment,
+        options::{Filters, JsonShape},
+        procs::ProcDesc,
+        IfaceInfo, Listing,
+    };
+    use anyhow::{bail, Result};
+    use std::io::Write;
+
+    pub fn write(
+        _: &Listing,
+        _: &IfaceInfo,
+        _: &Filters,
+        _: JsonShape,
+        _: &Enrichment,
+        _: &mut impl Write,
+    ) -> Result<()> {
+        bail!("lls was built without JSON support for --json")
+    }
+    #[allow(clippy::too_many_arguments)]
+    pub fn write_lines(
+        _: &Listing,
+        _: &IfaceInfo,
+        _: &Filters,
+        _: JsonShape,
+        _: &Enrichment,
+        _: bool,
+        _: &mut impl Write,
+    ) -> Result<()> {
+        bail!("lls was built without JSON support for --ndjson")
+    }
+    pub fn write_process(
+        _: &ProcDesc,
+        _: &Filters,
+        _: JsonShape,
+        _: &mut impl Write,
+    ) -> Result<()> {
+        bail!("lls was built without JSON support for --ndjson")
+    }
+    pub fn flat(_: &Listing, _: &IfaceInfo, _: &Filters, _: &Enrichment) -> std::iter::Empty<()> {
+        std::iter::empty()
+    }
+}
+mod limits;
+mod log;
 mod netlink;
 mod options;
+mod packages;
+#[cfg(feature = "lua")]
+mod plugin;
+#[cfg(not(feature = "lua"))]
+mod plugin {
+    use crate::{netlink::sock::SockInfo, procs::ProcDesc, termtree::Tree};
+    use anyhow::{bail, Result};
+    use std::path::Path;
+
+    pub enum Plugin {}
+    impl Plugin {
+        pub fn load(_: &Path) -> Result<Plugin> {
+            bail!("lls was built without Lua support for --plugin")
+        }
+        pub fn socket(&self, _: &SockInfo) -> Result<bool> {
+            match *self {}
+        }
+        pub fn process(&self, _: &mut ProcDesc) -> Result<()> {
+            match *self {}
+        }
+        pub fn render(&self, _: &mut Tree) -> Result<()> {
+            match *self {}
+        }
+    }
+}
 mod procs;
+#[cfg(feature = "json")]
+mod replay;
+#[cfg(not(feature = "json"))]
+mod replay {
+    use crate::{netlink::sock::SockInfo, procs::ProcDesc, IfaceInfo, Ino, Listing};
+    use anyhow::{bail, Result};
+    use std::{collections::HashMap, path::Path};
+
+    /// There are no captures to read without JSON
+    pub enum Group {}
+    pub fn load(_: &Path) -> Result<Vec<Group>> {
+        bail!("lls was built without JSON support for --from-file and --diff")
+    }
+    pub fn interfaces(_: &[Group]) -> IfaceInfo {
+        IfaceInfo::default()
+    }
+    #[allow(clippy::type_complexity)]
+    pub fn sockets<'i>(
+        _: &[Group],
+        _: &'i IfaceInfo,
+    ) -> Result<(HashMap<Ino, SockInfo<'i>>, Vec<ProcDesc<'i>>)> {
+        bail!("lls was built without JSON support for --from-file")
+    }
+    pub fn listing<'i>(_: &[Group], _: &'i IfaceInfo) -> Result<Listing<'i>> {
+        bail!("lls was built without JSON support for --diff")
+    }
+}
+mod rows;
+mod rpc;
+#[cfg(feature = "sandbox")]
+mod sandbox;
+#[cfg(not(feature = "sandbox"))]
+mod sandbox {
+    pub fn syscalls() {}
+    pub fn filesystem() {}
+}
+mod siem;
 mod sockets_procfs;
+#[cfg(feature = "netlink")]
+mod sockets_rds;
+mod summary;
+mod table;
+mod template;
+mod terminal;
 mod termtree;
+mod timings;
+mod users;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use itertools::Itertools;
-use netlink::{
-    sock::{Family, SockInfo},
-    wg::wireguards,
-};
-use procfs::process::all_processes;
+use netlink::route::RouteTables;
+use netlink::sock::{Extension, Family, Iface, Protocol, SockInfo, UNKNOWN_UID};
+#[cfg(feature = "wireguard")]
+use netlink::wg::wireguards;
+use options::{Column, Columns, SortKey};
+use procfs::process::all_processes_with_root;
 use std::{
-    collections::{BTreeMap, HashMap},
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
     env::var_os,
     io::{stdout, BufWriter, Write},
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     ops::Deref,
+    path::Path,
 };
-use uzers::UsersCache;
+use termtree::Tree;
+use users::{Users, UsersCache};
 
 pub type Ino = u64;
 
-fn main() -> Result<()> {
+fn main() {
+    exit::exit(run())
+}
+
+fn run() -> Result<exit::Status> {
+    if std::env::args().nth(1).as_deref() == Some("bench") {
+        return bench::run(std::env::args().skip(2));
+    }
+    let options::EarlyOptions {
+        fast,
+        from_file,
+        capture: bundle_to,
+        enrich,
+        plugin,
+        route_tables,
+        diff,
+    } = options::parse_early_args().map_err(exit::usage)?;
+    let plugin = plugin.as_deref().map(plugin::Plugin::load).transpose()?;
+    // Before anything is queried, to record the netlink traffic
+    if bundle_to.is_some() {
+        netlink::start_recording();
+    }
     let users_cache = UsersCache::new();
-    let iface_info = interfaces_routes();
+    // Partial failures, only fatal in --strict mode
+    let mut incomplete = Vec::<anyhow::Error>::new();
+    let capture = from_file.as_deref().map(replay::load).transpose()?;
+    let snapshot = diff.as_deref().map(replay::load).transpose()?;
+    let snapshot_ifaces = snapshot.as_deref().map(replay::interfaces);
+    let iface_info = match &capture {
+        Some(groups) => replay::interfaces(groups),
+        // The running system's interfaces and routes say nothing about the foreign one
+        None if procs::foreign_root() => IfaceInfo::default(),
+        None => interfaces_routes(&mut incomplete, fast, &route_tables),
+    };
 
-    let filters = options::parse_args(&iface_info, &users_cache)?;
+    let options::Options {
+        filters,
+        format,
+        strict,
+        sandbox,
+        columns,
+        sort,
+        expand,
+        expand_wildcard,
+        stable,
+        stream,
+        full_cmdline,
+        group_apps,
+        icons,
+        orphans,
+        extensions,
+        discover,
+        gateway,
+        cloud,
+        firewalld,
+        max_procs,
+        timeout,
+    } = options::parse_args(&iface_info, &users_cache).map_err(exit::usage)?;
+    limits::set(max_procs, timeout);
+    if enrich.is_some()
+        && !matches!(
+            format,
+            options::Format::Json(_) | options::Format::JsonLines(_)
+        )
+    {
+        Err(exit::Usage(anyhow::anyhow!(
+            "--enrich only works with --json and --ndjson"
+        )))?;
+    }
+    if snapshot.is_some() && (!matches!(format, options::Format::Tree) || stream) {
+        Err(exit::Usage(anyhow::anyhow!(
+            "--diff prints its own output, it doesn't combine with other formats or --stream"
+        )))?;
+    }
+    let mut bundle = bundle_to.as_ref().map(bundle::Bundle::create).transpose()?;
+    let enricher = enrich.as_deref().map(enrich::Enricher::spawn).transpose()?;
+    if sandbox {
+        sandbox::syscalls();
+    }
 
-    let socks = netlink::sock::all_sockets(&iface_info); // TODO no clone, pass filters
-    let mut socks = match socks {
-        Ok(socks) => socks,
-        Err(netlink_err) => match sockets_procfs::all_sockets(&iface_info) {
-            Ok(socks) => socks,
-            Err(proc_err) => {
-                eprintln!(
-                    "{}",
-                    netlink_err.context("Get listening sockets from netlink")
-                );
-                eprintln!("{}", proc_err.context("Get listening sockets from netlink"));
-                anyhow::bail!("Failed to get socket data");
+    let stdout = &mut BufWriter::new(stdout());
+    // Rename processes and hide sockets, before any output
+    let prepare = |pd: &mut procs::ProcDesc| -> Result<()> {
+        if full_cmdline {
+            pd.name = procs::full_cmdline(&pd.info).or(pd.name.take());
+        }
+        if let Some(plugin) = &plugin {
+            plugin.process(pd)?;
+            let mut hidden = HashSet::new();
+            for s in &pd.sockets {
+                if !plugin.socket(s)? {
+                    hidden.insert(s.ino);
+                }
             }
-        },
+            pd.sockets.retain(|s| !hidden.contains(&s.ino));
+        }
+        pd.sockets.sort();
+        Ok(())
+    };
+    let icons = icons.map(terminal::icons);
+    let width = match stable {
+        true => None,
+        false => terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w.into()),
+    };
+    let theme = terminal::theme(width.is_some() && var_os("NO_COLOR").is_none());
+    // --ndjson, and the tree with --stream, print batches of processes as soon as they're
+    // inspected, unless they need all of them first
+    let streaming = capture.is_none()
+        && bundle.is_none()
+        && !strict
+        && enricher.is_none()
+        && sort == options::Sort::default()
+        && !stable
+        && !columns.shows(Column::Pkg)
+        && orphans != options::Orphans::Only
+        && match format {
+            options::Format::JsonLines(_) => true,
+            // Applications, and the alignment of sockets without their process, span batches
+            options::Format::Tree => stream && !group_apps && columns.process_level(),
+            _ => false,
+        };
+    let mut stream = |batch: &mut Vec<_>| -> Result<()> {
+        for pd in batch.iter_mut() {
+            prepare(pd)?;
+            if expand_wildcard {
+                expand_wildcards(&mut pd.sockets, &iface_info);
+            }
+        }
+        batch.retain(|pd| filters.accept_process(pd));
+        batch.sort();
+        match format {
+            options::Format::JsonLines(shape) => {
+                // Whole lines, in case the --timeout watchdog ends lls during the next batch
+                (batch.iter())
+                    .try_for_each(|pd| json::write_process(pd, &filters, shape, stdout))?;
+                stdout.flush().context("Flush output")
+            }
+            _ => {
+                let mut output =
+                    processes_tree(batch, &filters, &columns, expand, false, icons.as_ref());
+                if let Some(plugin) = &plugin {
+                    plugin.render(&mut output)?;
+                }
+                let mut written = Ok(());
+                output.render(width, &theme, &mut |s| {
+                    if written.is_ok() {
+                        written = stdout.write_all(s);
+                    }
+                });
+                written.context("Write output")?;
+                stdout.flush().context("Flush output")
+            }
+        }
     };
-    let mut output = termtree::Tree::new();
-    let self_user_ns = procs::get_user_ns(&procs::ourself()?).ok();
 
-    // output known processes/sockets
-    let mut lps = all_processes()?
-        .filter_map(|p| procs::ProcDesc::inspect_ps(p, &mut socks, &users_cache, self_user_ns).ok())
-        .filter(|p| !p.sockets.is_empty())
-        .collect::<Vec<_>>();
-    lps.iter_mut().for_each(|p| p.sockets.sort());
-    lps.sort();
-    for pd in lps {
-        if filters.accept_process(&pd) {
-            output.node(
-                if let Some(name) = pd.name {
-                    format!("{name} (pid {} user {})", pd.pid, pd.user,)
-                } else {
-                    format!("pid {} user {}", pd.pid, pd.user,)
-                },
-                sockets_tree(&pd.sockets, &filters),
-            );
+    let announcements = match discover.filter(|_| capture.is_none() && !procs::foreign_root()) {
+        Some(time) => {
+            let local = (iface_info.addresses.iter().map(|&(_, addr)| addr))
+                .chain([IpAddr::V4(Ipv4Addr::LOCALHOST)])
+                .collect::<Vec<_>>();
+            timings::time("discovery", || discovery::discover(&local, time))
+                .context("Discover mDNS and SSDP announcements")?
+        }
+        None => Default::default(),
+    };
+    let mappings = match gateway.filter(|_| capture.is_none() && !procs::foreign_root()) {
+        Some(time) => {
+            let local = (iface_info.addresses.iter())
+                .map(|&(_, addr)| addr)
+                .collect::<Vec<_>>();
+            timings::time("gateway", || gateway::mappings(&local, time)).unwrap_or_else(|e| {
+                let e = e.context("Get port mappings of the router");
+                log::warn!("{e:#}");
+                incomplete.push(e);
+                Vec::new()
+            })
         }
+        None => Vec::new(),
+    };
+    let nics = match cloud && capture.is_none() && !procs::foreign_root() {
+        true => timings::time("cloud", cloud::instance)
+            .map_err(|e| {
+                let e = e.context("Get public addresses of the cloud instance");
+                log::warn!("{e:#}");
+                incomplete.push(e);
+            })
+            .ok(),
+        false => None,
+    };
+
+    let firewall_rules = match firewalld && capture.is_none() && !procs::foreign_root() {
+        true => timings::time("firewalld", firewalld::rules).unwrap_or_else(|e| {
+            let e = e.context("Get firewalld zones");
+            log::warn!("{e:#}");
+            incomplete.push(e);
+            Vec::new()
+        }),
+        false => Vec::new(),
+    };
+
+    // Android restricts /proc and sock_diag, show what's there instead of failing
+    let android = is_android();
+    let unix = filters.proto.contains(&Protocol::Unix);
+    let (mut socks, mut lps, uninspectable) = match &capture {
+        Some(groups) => {
+            let (socks, lps) = replay::sockets(groups, &iface_info)?;
+            (socks, lps, Default::default())
+        }
+        None => scan(
+            &iface_info,
+            extensions.as_deref(),
+            &columns,
+            fast,
+            android,
+            &users_cache,
+            &announcements,
+            &mappings,
+            nics.as_deref(),
+            &firewall_rules,
+            unix,
+            streaming.then_some(&mut stream),
+            &mut incomplete,
+        )?,
+    };
+    if let Some(bundle) = bundle.as_mut().filter(|_| capture.is_none()) {
+        bundle.processes(&lps);
+    }
+    if sandbox {
+        sandbox::filesystem();
+    }
+    if !streaming {
+        lps.iter_mut().try_for_each(prepare)?;
+    }
+    if let Some(plugin) = &plugin {
+        timings::time("plugin", || -> Result<()> {
+            let mut hidden = HashSet::new();
+            for s in socks.values() {
+                if !plugin.socket(s)? {
+                    hidden.insert(s.ino);
+                }
+            }
+            socks.retain(|ino, _| !hidden.contains(ino));
+            Ok(())
+        })?;
+    }
+    lps.sort();
+    if stable {
+        // pids differ between runs, names don't
+        lps.sort_by(|a, b| (&a.sockets, &a.name).cmp(&(&b.sockets, &b.name)));
+    }
+    lps.retain(|pd| filters.accept_process(pd));
+    match sort.key {
+        SortKey::Port => (),
+        SortKey::Pid => lps.sort_by_key(|pd| pd.pid),
+        SortKey::Cmd => lps.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortKey::Sockets => lps.sort_by_cached_key(|pd| {
+            Reverse(pd.sockets.iter().filter(|s| filters.accept_sock(s)).count())
+        }),
+    }
+    if sort.reverse {
+        lps.reverse();
+    }
+    for conflict in conflicts::find(&lps) {
+        let (wpd, wsock) = conflict.wildcard;
+        let (spd, ssock) = conflict.specific;
+        let spd_name = spd.name.as_deref().unwrap_or("???");
+        log::warn!(
+            "Port conflict on :{} {}: {} ({}) listens on {}, {spd_name} ({}) on {}, connections to {} go to {spd_name}",
+            wsock.port,
+            wsock.protocol,
+            wpd.name.as_deref().unwrap_or("???"),
+            pid_label(wpd),
+            wsock.addr,
+            pid_label(spd),
+            ssock.addr,
+            ssock.addr,
+        );
+    }
+    let lowered = lps
+        .iter()
+        .flat_map(|pd| &pd.sockets)
+        .filter(|s| filters.accept_sock(s) && s.formerly_privileged_port())
+        .map(|s| s.port)
+        .min();
+    if let Some(port) = lowered {
+        log::warn!(
+            "net.ipv4.ip_unprivileged_port_start is lowered to {}, so low ports like :{port} don't imply a privileged process",
+            procs::unprivileged_port_start()
+        );
     }
 
-    // output wireguards
+    // wireguards
     let mut interface_sockets = HashMap::<_, Vec<_>>::new();
     socks.retain(|_sockid, sockinfo| {
         let mut retain = true;
         for &(if_id, port) in &iface_info.interface_ports {
-            if port == sockinfo.port {
+            if port == sockinfo.port && sockinfo.is_inet() {
                 retain = false;
                 interface_sockets
                     .entry(if_id)
@@ -81,48 +537,765 @@ fn main() -> Result<()> {
         }
         retain
     });
-    for (if_id, socks) in &interface_sockets {
-        if filters.accept_wg() {
-            let name = match iface_info.id2name.get(if_id) {
-                Some(ifname) => format!("[network interface {ifname}]"),
-                None => format!("[network interface #{if_id}]"),
-            };
-            output.node(name, sockets_tree(socks, &filters));
+    if !filters.accept_wg() {
+        interface_sockets.clear();
+    }
+    // Sockets that the filters hide anyway don't make the output incomplete
+    let unmatched = (socks.values())
+        .filter(|s| filters.accept_sock(s) && filters.accept_user(s.uid))
+        .count();
+    // Kernel sockets and other users' processes are hidden on most hosts, so they only count as
+    // missing data in --strict mode
+    if strict {
+        if unmatched > 0 {
+            incomplete.push(anyhow::anyhow!(
+                "{unmatched} sockets could not be matched to a process"
+            ));
+        }
+        let denied = uninspectable.denied.values().sum::<usize>();
+        if denied > 0 {
+            incomplete.push(anyhow::anyhow!(
+                "{denied} processes could not be inspected: permission denied"
+            ));
         }
     }
+    if unmatched > 0 && capture.is_none() {
+        explain_unmatched(unmatched, &uninspectable, &users_cache);
+    }
 
-    // output unknown sockets
-    let mut socks = socks
-        .values()
+    // unknown sockets
+    let mut unknown = socks
+        .into_values()
         .into_group_map_by(|s| s.uid)
         .into_iter()
         .collect::<Vec<_>>();
-    socks.iter_mut().for_each(|(_, x)| x.sort());
-    socks.sort_by_cached_key(|t| t.1.clone());
+    unknown.iter_mut().for_each(|(_, x)| x.sort());
+    unknown.sort_by_cached_key(|t| (t.1.clone(), t.0));
+    let mut interfaces = interface_sockets.into_iter().collect::<Vec<_>>();
+    interfaces.iter_mut().for_each(|(_, x)| x.sort());
+    interfaces.sort_by_key(|t| t.0);
     match filters.cmd.is_empty() && filters.pid.is_empty() {
-        true => {
-            for (uid, socks) in socks {
-                if filters.accept_user(uid) {
-                    output.node(format!("??? (user {uid})",), sockets_tree(socks, &filters));
+        true => unknown.retain(|&(uid, _)| filters.accept_user(uid)),
+        false => {
+            if !unknown.is_empty() {
+                log::warn!("Some listening sockets hidden: Not all sockets could not be matched to a process, process-based filtering not fully possible.");
+            }
+            unknown.clear();
+        }
+    }
+
+    incomplete.iter().for_each(exit::partial);
+    if strict && !incomplete.is_empty() {
+        for e in &incomplete {
+            log::error!("{e:#}");
+        }
+        log::error!("Incomplete data, not producing output in strict mode");
+        return Ok(exit::Status::Partial);
+    }
+    if android {
+        for e in &incomplete {
+            log::warn!("Incomplete: {e:#}");
+        }
+    }
+
+    match orphans {
+        options::Orphans::Show => (),
+        options::Orphans::Only => {
+            lps.clear();
+            interfaces.clear();
+        }
+        options::Orphans::Hide => unknown.clear(),
+    }
+    let mut listing = Listing {
+        processes: lps,
+        interfaces,
+        unknown,
+    };
+    if expand_wildcard {
+        listing.expand_wildcards(&iface_info);
+    }
+    let snapshot = match (&snapshot, &snapshot_ifaces) {
+        (Some(groups), Some(ifaces)) => {
+            let mut snapshot = replay::listing(groups, ifaces)?;
+            snapshot.processes.iter_mut().try_for_each(prepare)?;
+            diff::filter(&mut snapshot, &filters);
+            Some((snapshot, ifaces))
+        }
+        _ => None,
+    };
+    let mut changed = false;
+    let enrichment = match enricher {
+        Some(enricher) => timings::time("enrich", || {
+            enricher.run(json::flat(&listing, &iface_info, &filters, &Default::default()).collect())
+        })?,
+        None => Default::default(),
+    };
+    timings::time("render", || -> Result<()> {
+        if let Some((snapshot, ifaces)) = &snapshot {
+            changed = diff::write(&listing, &iface_info, snapshot, ifaces, &filters, stdout)?;
+            return stdout.flush().context("Flush output");
+        }
+        match format {
+            options::Format::Tree => {
+                let mut output = match streaming {
+                    // The processes were printed during the scan
+                    true => listing.other_groups_tree(
+                        &iface_info,
+                        &filters,
+                        &columns,
+                        expand,
+                        icons.as_ref(),
+                    ),
+                    false => listing.tree(
+                        &iface_info,
+                        &filters,
+                        &columns,
+                        expand,
+                        group_apps,
+                        icons.as_ref(),
+                    ),
+                };
+                if let Some(plugin) = &plugin {
+                    plugin.render(&mut output)?;
+                }
+                let mut written = Ok(());
+                output.render(width, &theme, &mut |s| {
+                    if written.is_ok() {
+                        written = stdout.write_all(s);
+                    }
+                });
+                written.context("Write output")?;
+            }
+            options::Format::Template(template) => {
+                for row in rows::rows(&listing, &iface_info, &filters) {
+                    writeln!(stdout, "{}", template.render(&row)).context("Write output")?;
                 }
             }
+            options::Format::Json(shape) => {
+                json::write(&listing, &iface_info, &filters, shape, &enrichment, stdout)?
+            }
+            options::Format::JsonLines(shape) => json::write_lines(
+                &listing,
+                &iface_info,
+                &filters,
+                shape,
+                &enrichment,
+                streaming,
+                stdout,
+            )?,
+            options::Format::SummaryByUser => {
+                summary::write(&listing, &iface_info, &filters, &users_cache, stdout)?
+            }
+            options::Format::Siem(siem) => {
+                siem::write(&listing, &iface_info, &filters, siem, stdout)?
+            }
+            options::Format::Table => table::write(&listing, &iface_info, &filters, stdout)?,
+            options::Format::Csv(delimiter) => {
+                csv::write(&listing, &iface_info, &filters, &columns, delimiter, stdout)?
+            }
         }
-        false => {
-            if !socks.is_empty() {
-                eprintln!("WARNING: Some listening sockets hidden:");
-                eprintln!("Not all sockets could not be matched to a process, process-based filtering not fully possible.");
+        stdout.flush().context("Flush output")
+    })?;
+    if let Some(bundle) = bundle {
+        let tree = listing.tree(
+            &iface_info,
+            &filters,
+            &columns,
+            expand,
+            group_apps,
+            icons.as_ref(),
+        );
+        bundle.finish(&listing, &iface_info, &filters, &enrichment, &tree)?;
+    }
+    timings::report();
+
+    // Whether anything changed is what --diff reports, missing data was already reported on stderr
+    let (matched, complete) = match snapshot {
+        Some(_) => (changed, true),
+        None => (
+            rows::rows(&listing, &iface_info, &filters).next().is_some(),
+            incomplete.is_empty(),
+        ),
+    };
+    Ok(match (complete, matched) {
+        (false, _) => exit::Status::Partial,
+        (true, true) => exit::Status::Matches,
+        (true, false) => exit::Status::NoMatches,
+    })
+}
+
+/// Tell how many sockets and processes are hidden from us, and what would reveal them
+fn explain_unmatched(
+    unmatched: usize,
+    uninspectable: &procs::Uninspectable,
+    users_cache: &UsersCache,
+) {
+    let denied = uninspectable.denied.values().sum::<usize>();
+    if denied > 0 {
+        let owners = (uninspectable.denied.keys())
+            .map(|&uid| match uid {
+                u32::MAX => "unknown".into(),
+                uid => (users_cache.get_user_by_uid(uid))
+                    .map_or_else(|| uid.to_string(), |u| u.name().to_string_lossy().into()),
+            })
+            .join(", ");
+        let fix = match users::get_effective_uid() {
+            0 => "lls lacks CAP_SYS_PTRACE, e.g. in a container, grant it to see them",
+            _ => {
+                "rerun with --sudo or --pkexec, or grant lls CAP_SYS_PTRACE and CAP_DAC_READ_SEARCH"
+            }
+        };
+        log::warn!(
+            "{unmatched} sockets could not be matched to a process: the fd tables of {denied} processes were unreadable (permission denied, owned by {owners}); {fix}"
+        );
+    } else if users::get_effective_uid() != 0 && procs::hidepid() {
+        log::warn!(
+            "{unmatched} sockets could not be matched to a process: /proc is mounted with hidepid, other users' processes are invisible; rerun with --sudo or --pkexec, or add lls' user to the gid= group of the /proc mount"
+        );
+    } else {
+        log::info!(
+            "{unmatched} sockets could not be matched to a process: they belong to processes in other PID namespaces or to the kernel, e.g. nfsd"
+        );
+    }
+    if uninspectable.other > 0 {
+        log::info!(
+            "{} processes could not be inspected for other reasons",
+            uninspectable.other
+        );
+    }
+}
+
+/// Called with each batch of processes as soon as they are inspected, and may drop some
+type Stream<'s, 'i> = &'s mut dyn FnMut(&mut Vec<procs::ProcDesc<'i>>) -> Result<()>;
+/// Processes per batch of --ndjson and --stream, which are sorted among themselves
+const STREAM_BATCH: usize = 64;
+
+/// Listening sockets of the running system, and the processes they belong to
+#[allow(clippy::too_many_arguments)]
+fn scan<'i>(
+    iface_info: &'i IfaceInfo,
+    extensions: Option<&[Extension]>,
+    columns: &Columns,
+    fast: bool,
+    android: bool,
+    users_cache: &UsersCache,
+    announcements: &discovery::Announcements,
+    mappings: &[gateway::Mapping],
+    nics: Option<&[cloud::Nic]>,
+    firewall_rules: &[firewalld::Rule],
+    unix: bool,
+    mut stream: Option<Stream<'_, 'i>>,
+    incomplete: &mut Vec<anyhow::Error>,
+) -> Result<(
+    HashMap<Ino, SockInfo<'i>>,
+    Vec<procs::ProcDesc<'i>>,
+    procs::Uninspectable,
+)> {
+    let socks = match procs::foreign_root() {
+        // netlink only knows the running system's sockets
+        true => Err(anyhow::anyhow!("--proc-root given")),
+        false => netlink::sock::all_sockets(iface_info, extensions), // TODO no clone, pass filters
+    };
+    let mut socks = match socks {
+        Ok(socks) => socks,
+        Err(netlink_err) => {
+            let netlink_err = netlink_err.context("Get listening sockets from netlink");
+            log::info!("{netlink_err:#}");
+            match timings::time("procfs sockets", || sockets_procfs::all_sockets(iface_info)) {
+                Ok(socks) => {
+                    if cfg!(feature = "netlink") && !procs::foreign_root() {
+                        incomplete.push(netlink_err);
+                    }
+                    socks
+                }
+                Err(proc_err) if android => {
+                    let proc_err = proc_err.context("Get listening sockets from procfs");
+                    log::warn!(
+                        "Android restricts access to socket information, no sockets can be shown"
+                    );
+                    incomplete.extend([netlink_err, proc_err]);
+                    HashMap::new()
+                }
+                Err(proc_err) => {
+                    if !procs::foreign_root() {
+                        log::error!("{netlink_err:#}");
+                    }
+                    log::error!(
+                        "{:#}",
+                        proc_err.context("Get listening sockets from procfs")
+                    );
+                    anyhow::bail!("Failed to get socket data");
+                }
+            }
+        }
+    };
+    #[cfg(feature = "netlink")]
+    if columns.shows(Column::Conns) && !procs::foreign_root() {
+        if let Err(e) = netlink::sock::count_connections(&mut socks) {
+            let e = e.context("Count established connections");
+            log::warn!("{e:#}");
+            incomplete.push(e);
+        }
+    }
+    #[cfg(feature = "netlink")]
+    if !procs::foreign_root() {
+        socks.extend(timings::time("rds sockets", || {
+            sockets_rds::all_sockets(iface_info)
+        }));
+    }
+    if unix {
+        let socks_unix = match procs::foreign_root() {
+            true => Err(anyhow::anyhow!("--proc-root given")),
+            false => netlink::sock::unix_sockets(),
+        };
+        match socks_unix {
+            Ok(socks_unix) => socks.extend(socks_unix),
+            Err(e) => {
+                let e = e.context("Get listening unix sockets from netlink");
+                log::warn!("{e:#}");
+                incomplete.push(e);
             }
         }
     }
+    // rpcbind only knows the running system's ports. The names are a nicety, so its absence
+    // or failure doesn't make the data incomplete.
+    if !procs::foreign_root() && !fast {
+        if let Err(e) = timings::time("rpcbind", || rpc::resolve(&mut socks)) {
+            log::debug!("{e:#}");
+        }
+    }
+    discovery::annotate(&mut socks, announcements);
+    gateway::annotate(&mut socks, mappings);
+    if let Some(nics) = nics {
+        cloud::annotate(&mut socks, nics);
+    }
+    firewalld::annotate(&mut socks, firewall_rules);
+    let netns = procs::netns();
+    socks.values_mut().for_each(|s| s.netns = netns);
+    let self_user_ns = procs::get_user_ns(&procs::ourself()?).ok();
 
-    let stdout = &mut BufWriter::new(stdout());
-    let size = terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w.into());
-    let color = size.is_some() && var_os("NO_COLOR").is_none();
-    output.render(size, color, &mut |s| {
-        stdout.write_all(s).expect("stdout shut")
+    // known processes/sockets
+    let mut uninspectable = procs::Uninspectable::default();
+    let mut claimed = HashMap::new();
+    let mut interner = intern::Interner::default();
+    let (mut inspected, mut stopped) = (0, None);
+    let mut lps = Vec::new();
+    limits::start();
+    let scanned = timings::time("process scan", || -> Result<_> {
+        let mut processes = all_processes_with_root(procs::root())?
+            .take_while(|_| {
+                stopped = limits::exceeded(inspected);
+                inspected += 1;
+                stopped.is_none()
+            })
+            .filter_map(|p| {
+                let uid = p.as_ref().ok().and_then(|p| p.uid().ok());
+                procs::ProcDesc::inspect_ps(
+                    p,
+                    &mut socks,
+                    &mut claimed,
+                    (!fast).then_some(users_cache),
+                    self_user_ns,
+                    &mut interner,
+                )
+                .map_err(|e| {
+                    uninspectable.add(&e, uid);
+                    log::trace!("Skipping process: {e:#}")
+                })
+                .ok()
+            })
+            .filter(|p| !p.sockets.is_empty());
+        let Some(stream) = &mut stream else {
+            lps.extend(processes);
+            return Ok(());
+        };
+        loop {
+            let mut batch = processes.by_ref().take(STREAM_BATCH).collect::<Vec<_>>();
+            if batch.is_empty() {
+                return Ok(());
+            }
+            // Processes sharing the sockets may still come, so pids are incomplete
+            details(&mut batch, columns, incomplete);
+            stream(&mut batch)?;
+            lps.append(&mut batch);
+        }
     });
+    limits::finish();
+    scanned?;
+    if let Some(limit) = stopped {
+        let e = anyhow::anyhow!(
+            "Stopped after inspecting {} processes because of {limit}, \
+             the sockets of the others are shown as unknown",
+            inspected - 1
+        );
+        log::warn!("{e}");
+        incomplete.push(e);
+    }
+    for pd in &mut lps {
+        pd.sharing = (pd.sockets.iter())
+            .flat_map(|s| &claimed[&s.ino])
+            .copied()
+            .sorted()
+            .dedup()
+            .collect();
+    }
+    if stream.is_none() {
+        details(&mut lps, columns, incomplete);
+    }
+    if uninspectable.other > 0 {
+        incomplete.push(anyhow::anyhow!(
+            "{} processes could not be inspected",
+            uninspectable.other
+        ));
+    }
+    if uninspectable.vanished > 0 {
+        log::debug!(
+            "{} processes exited before they were inspected",
+            uninspectable.vanished
+        );
+    }
+    Ok((socks, lps, uninspectable))
+}
+
+/// Look up what the columns need beyond the sockets
+fn details(lps: &mut [procs::ProcDesc], columns: &Columns, incomplete: &mut Vec<anyhow::Error>) {
+    for pd in lps.iter_mut() {
+        for sock in &mut pd.sockets {
+            // RDS sockets, and Unix sockets before Linux 5.3, don't know their owner
+            if sock.uid == UNKNOWN_UID {
+                sock.uid = pd.uid;
+            }
+        }
+    }
+    if columns.resources() {
+        timings::time("resources", || {
+            for pd in lps.iter_mut() {
+                match procs::Resources::of(pd.pids()) {
+                    Ok(r) => pd.resources = Some(r),
+                    Err(e) => incomplete.push(e.context("Resource usage")),
+                }
+            }
+        });
+    }
+    if columns.shows(Column::Image) {
+        timings::time("containers", || containers::resolve(lps));
+    }
+    if columns.shows(Column::Env) {
+        timings::time("environment", || procs::read_env(lps));
+    }
+    // The configuration is only the running system's
+    if !procs::foreign_root() {
+        inetd::resolve(lps);
+    }
+    if columns.shows(Column::Pkg) {
+        timings::time("packages", || {
+            let exes = lps.iter().filter_map(|pd| pd.info.exe.as_deref());
+            let owners = packages::owners(exes);
+            for pd in lps.iter_mut() {
+                pd.package = (pd.info.exe.as_deref()).and_then(|exe| owners.get(exe).cloned());
+            }
+        });
+    }
+}
+
+/// Everything that made it past the process-level filters, grouped by owner
+pub struct Listing<'a> {
+    pub processes: Vec<procs::ProcDesc<'a>>,
+    pub interfaces: Vec<(u32, Vec<SockInfo<'a>>)>,
+    pub unknown: Vec<(u32, Vec<SockInfo<'a>>)>,
+}
+
+impl<'a> Listing<'a> {
+    /// Replace wildcard listeners by one socket per interface address they can be reached on
+    fn expand_wildcards(&mut self, iface_info: &'a IfaceInfo) {
+        self.processes
+            .iter_mut()
+            .for_each(|pd| expand_wildcards(&mut pd.sockets, iface_info));
+        (self.interfaces.iter_mut())
+            .chain(&mut self.unknown)
+            .for_each(|(_, sockets)| expand_wildcards(sockets, iface_info));
+    }
+
+    fn tree(
+        &self,
+        iface_info: &IfaceInfo,
+        filters: &options::Filters,
+        columns: &Columns,
+        expand: bool,
+        group_apps: bool,
+        icons: Option<&terminal::Icons>,
+    ) -> Tree {
+        let mut output =
+            processes_tree(&self.processes, filters, columns, expand, group_apps, icons);
+        output.extend(self.other_groups_tree(iface_info, filters, columns, expand, icons));
+        output
+    }
 
-    Ok(())
+    /// The interface and unknown groups of tree
+    fn other_groups_tree(
+        &self,
+        iface_info: &IfaceInfo,
+        filters: &options::Filters,
+        columns: &Columns,
+        expand: bool,
+        icons: Option<&terminal::Icons>,
+    ) -> Tree {
+        let mut output = Tree::new();
+        for (if_id, socks) in &self.interfaces {
+            let name = match iface_info.id2name.get(if_id) {
+                Some(ifname) => format!("[network interface {ifname}]"),
+                None => format!("[network interface #{if_id}]"),
+            };
+            group(
+                &mut output,
+                columns,
+                name,
+                sockets_tree(
+                    socks,
+                    filters,
+                    columns,
+                    expand,
+                    None,
+                    &HashMap::new(),
+                    icons,
+                ),
+                false,
+            );
+        }
+        for (uid, socks) in &self.unknown {
+            let label = match columns.shows(Column::User) && *uid != UNKNOWN_UID {
+                true => format!("??? (user {uid})"),
+                false => "???".into(),
+            };
+            group(
+                &mut output,
+                columns,
+                label,
+                sockets_tree(
+                    socks,
+                    filters,
+                    columns,
+                    expand,
+                    None,
+                    &HashMap::new(),
+                    icons,
+                ),
+                false,
+            );
+        }
+        output
+    }
+}
+
+/// The processes of Listing::tree, or of one batch printed during the scan
+fn processes_tree(
+    processes: &[procs::ProcDesc],
+    filters: &options::Filters,
+    columns: &Columns,
+    expand: bool,
+    group_apps: bool,
+    icons: Option<&terminal::Icons>,
+) -> Tree {
+    let mut shadowing = HashMap::<Ino, Vec<String>>::new();
+    for conflict in conflicts::find(processes) {
+        let (wpd, wsock) = conflict.wildcard;
+        let (spd, ssock) = conflict.specific;
+        (shadowing.entry(wsock.ino).or_default()).push(format!(
+            "shadowed by {} for {}",
+            pid_label(spd),
+            ssock.addr
+        ));
+        (shadowing.entry(ssock.ino).or_default()).push(format!(
+            "shadows {} on {}",
+            pid_label(wpd),
+            wsock.addr
+        ));
+    }
+    let mut output = Tree::new();
+    let process = |pd: &procs::ProcDesc| {
+        let groups = match pd.groups.is_empty() {
+            true => None,
+            false => Some(format!("groups {}", pd.groups.join(","))),
+        };
+        let details = [
+            (Column::Pid, Some(pid_label(pd))),
+            (Column::User, Some(user_label(pd))),
+            (
+                Column::Group,
+                pd.group.as_ref().map(|g| format!("group {g}")),
+            ),
+            (Column::Group, groups),
+            (
+                Column::Since,
+                pd.started
+                    .map(|t| format!("since {}", t.format(SINCE_FORMAT))),
+            ),
+            (Column::Pkg, pd.package.as_ref().map(|p| format!("pkg {p}"))),
+            (
+                Column::Image,
+                pd.image.as_ref().map(|i| format!("image {i}")),
+            ),
+            (Column::Env, env_hints(pd)),
+            (
+                Column::Rss,
+                pd.resources.map(|r| format!("rss {}", human_bytes(r.rss))),
+            ),
+            (
+                Column::Cpu,
+                pd.resources
+                    .map(|r| format!("cpu {}", percent(r.cpu_permille))),
+            ),
+        ]
+        .into_iter()
+        .filter(|(c, _)| columns.shows(*c))
+        .filter_map(|(_, d)| d)
+        .join(" ");
+        let label = match &pd.name {
+            Some(name) if columns.shows(Column::Cmd) && details.is_empty() => name.clone(),
+            Some(name) if columns.shows(Column::Cmd) => format!("{name} ({details})"),
+            _ => details,
+        };
+        let label = match pd.container_target() {
+            Some(target) => format!("{label} → container {target}"),
+            None => label,
+        };
+        let label = match pd.exe_deleted() {
+            true => format!("{label} (exe deleted)"),
+            false => label,
+        };
+        let label = match icons {
+            Some(icons) if pd.image.is_some() || pd.container_target().is_some() => {
+                format!("{} {label}", icons.container)
+            }
+            _ => label,
+        };
+        let sockets = sockets_tree(
+            &pd.sockets,
+            filters,
+            columns,
+            expand,
+            Some(pd),
+            &shadowing,
+            icons,
+        );
+        (label, sockets, pd.exe_deleted())
+    };
+    let apps = match group_apps && columns.process_level() {
+        true => apps::group(processes),
+        false => (0..processes.len())
+            .map(|i| apps::App {
+                name: None,
+                members: vec![i],
+            })
+            .collect(),
+    };
+    for app in apps {
+        let members = (app.members.iter())
+            .map(|&i| &processes[i])
+            .filter(|pd| pd.sockets.iter().any(|s| filters.accept_sock(s)))
+            .collect::<Vec<_>>();
+        match members[..] {
+            [] => (),
+            [pd] => {
+                let (label, sockets, highlight) = process(pd);
+                group(&mut output, columns, label, sockets, highlight);
+            }
+            _ => {
+                let mut processes = Tree::new();
+                for pd in &members {
+                    let (label, sockets, highlight) = process(pd);
+                    let start = processes.len();
+                    processes.node(label, sockets);
+                    if highlight {
+                        processes.highlight_since(start);
+                    }
+                }
+                // Forked workers also share the sockets of their parent
+                let count = members.iter().flat_map(|pd| pd.pids()).unique().count();
+                let name = app.name.as_deref().unwrap_or("???");
+                let label = format!("{name} ({count} processes)");
+                group(&mut output, columns, label, processes, false);
+            }
+        }
+    }
+    output
+}
+
+/// A top level node, or only its sockets if no process-level columns are shown
+fn group(output: &mut Tree, columns: &Columns, label: String, sockets: Tree, highlight: bool) {
+    let start = output.len();
+    match columns.process_level() {
+        true => output.node(label, sockets),
+        false => output.extend(sockets),
+    };
+    if highlight {
+        output.highlight_since(start);
+    }
+}
+
+const SINCE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+fn user_label(pd: &procs::ProcDesc) -> String {
+    let user = match &pd.real_user {
+        Some((_, real)) => format!("user {} (real {real})", pd.user),
+        None => format!("user {}", pd.user),
+    };
+    match &pd.ns_user {
+        None => user,
+        Some((_, Some(name))) => format!("{user}, {name} in userns"),
+        Some((uid, None)) => format!("{user}, {uid} in userns"),
+    }
+}
+
+/// How the process got its sockets, according to its environment
+fn env_hints(pd: &procs::ProcDesc) -> Option<String> {
+    let env = |key: &str| pd.env.get(key).map(String::as_str);
+    let mut hints = Vec::new();
+    // Children inherit the variables, they only apply to LISTEN_PID
+    let activated = env("LISTEN_PID").map_or(true, |pid| pid == pd.pid.to_string());
+    if let Some(fds) = env("LISTEN_FDS").filter(|_| activated) {
+        hints.push(match env("LISTEN_FDNAMES") {
+            Some(names) => format!("socket-activated {}", names.replace(':', ",")),
+            None => format!("socket-activated, {fds} fds"),
+        });
+    }
+    if let Some(port) = env("PORT") {
+        let listening = (pd.sockets.iter()).any(|s| port.parse() == Ok(s.port));
+        hints.push(match listening {
+            true => format!("$PORT={port}"),
+            false => format!("$PORT={port} unused"),
+        });
+    }
+    (!hints.is_empty()).then(|| hints.join(", "))
+}
+
+fn pid_label(pd: &procs::ProcDesc) -> String {
+    match pd.sharing.len() {
+        0 => format!("pid {}", pd.pid),
+        n => format!("{} pids {}", n + 1, procs::pid_ranges(pd.pids())),
+    }
+}
+
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024. && unit + 1 < units.len() {
+        value /= 1024.;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes}{}", units[0]),
+        _ => format!("{value:.1}{}", units[unit]),
+    }
+}
+
+fn percent(permille: u64) -> String {
+    format!("{}.{}%", permille / 10, permille % 10)
+}
+
+fn is_android() -> bool {
+    var_os("ANDROID_ROOT").is_some() || Path::new("/system/build.prop").exists()
 }
 
 #[derive(Default)]
@@ -130,61 +1303,437 @@ struct IfaceInfo {
     id2name: HashMap<u32, String>,
     interface_ports: Vec<(u32, u16)>,
     local_routes: netlink::route::Rtbl,
+    /// Interface index and address
+    addresses: Vec<(u32, IpAddr)>,
+}
+
+#[cfg(not(feature = "netlink"))]
+fn interfaces_routes(_: &mut Vec<anyhow::Error>, _: bool, _: &RouteTables) -> IfaceInfo {
+    log::debug!("Built without netlink support, no interface information");
+    Default::default()
 }
 
-fn interfaces_routes() -> IfaceInfo {
-    let Ok(ref route_socket) = netlink::route::socket() else {
-        return Default::default();
+#[cfg(all(feature = "netlink", not(feature = "wireguard")))]
+fn wireguards(_: &[u32]) -> Result<Vec<(u32, u16)>> {
+    Ok(Vec::new())
+}
+
+/// With fast, only what's needed for interface filters: interface names and local routes
+#[cfg(feature = "netlink")]
+fn interfaces_routes(
+    incomplete: &mut Vec<anyhow::Error>,
+    fast: bool,
+    route_tables: &RouteTables,
+) -> IfaceInfo {
+    let mut partial = |e: anyhow::Error| {
+        log::debug!("{e:#}");
+        incomplete.push(e);
+    };
+    let route_socket = match netlink::route::socket() {
+        Ok(route_socket) => route_socket,
+        Err(e) => {
+            partial(e);
+            return Default::default();
+        }
     };
+    let route_socket = &route_socket;
     let netlink::route::Interfaces {
         id2name,
         wireguard_ids,
         vxlan_ports,
-    } = netlink::route::interface_names(route_socket).unwrap_or_default();
-    let local_routes = netlink::route::local_routes(route_socket).unwrap_or_default();
-    let wireguard_ports = wireguards(&wireguard_ids).unwrap_or_default();
+    } = timings::time("rtnetlink links", || {
+        netlink::route::interface_names(route_socket)
+    })
+    .map_err(&mut partial)
+    .unwrap_or_default();
+    let local_routes = timings::time("rtnetlink routes", || {
+        netlink::route::local_routes(route_socket, route_tables)
+    })
+    .map_err(&mut partial)
+    .unwrap_or_default();
+    if fast {
+        return IfaceInfo {
+            id2name,
+            interface_ports: vxlan_ports,
+            local_routes,
+            ..Default::default()
+        };
+    }
+    let addresses = timings::time("rtnetlink addresses", || {
+        netlink::route::addresses(route_socket)
+    })
+    .map_err(&mut partial)
+    .unwrap_or_default();
+    let wireguard_ports = timings::time("wireguard", || wireguards(&wireguard_ids))
+        .map_err(&mut partial)
+        .unwrap_or_default();
     IfaceInfo {
         id2name,
-        interface_ports: wireguard_ports
-            .into_iter()
-            .chain(vxlan_ports.into_iter())
-            .collect(),
+        interface_ports: wireguard_ports.into_iter().chain(vxlan_ports).collect(),
         local_routes,
+        addresses,
     }
 }
 
+/// Sockets that are already specific are kept, so this can be applied repeatedly
+fn expand_wildcards<'a>(sockets: &mut Vec<SockInfo<'a>>, iface_info: &'a IfaceInfo) {
+    *sockets = (sockets.drain(..))
+        .flat_map(|sock| wildcard_addresses(sock, iface_info))
+        .sorted()
+        .collect();
+}
+
+/// The addresses of all interfaces (or the bound one) of the socket's family,
+/// or just the socket itself if it isn't a wildcard listener or no addresses are known
+fn wildcard_addresses<'a>(sock: SockInfo<'a>, iface_info: &'a IfaceInfo) -> Vec<SockInfo<'a>> {
+    if !sock.addr.is_unspecified() || !sock.is_inet() {
+        return vec![sock];
+    }
+    let bound = match sock.iface {
+        Some(Iface::Bound(name)) => Some(name),
+        _ => None,
+    };
+    let expanded = (iface_info.addresses.iter())
+        .filter_map(|&(idx, addr)| {
+            let name = iface_info.id2name.get(&idx).map(|n| &**n);
+            let family = match addr {
+                IpAddr::V4(_) => Family::V4,
+                IpAddr::V6(_) => Family::V6,
+            };
+            let fits = match sock.addr {
+                IpAddr::V4(_) => family == Family::V4,
+                IpAddr::V6(_) => sock.family == Family::Both || family == Family::V6,
+            };
+            (fits && (bound.is_none() || bound == name)).then(|| SockInfo {
+                family,
+                addr,
+                iface: name.map(|n| match bound {
+                    Some(_) => Iface::Bound(n),
+                    None => Iface::Route(n),
+                }),
+                ..sock.clone()
+            })
+        })
+        .collect::<Vec<_>>();
+    match expanded.is_empty() {
+        true => vec![sock],
+        false => expanded,
+    }
+}
+
+/// Per port, show at most this many addresses unless --expand is given
+const FOLD_ADDRESSES: usize = 4;
+
 fn sockets_tree<'a>(
     sockets: impl IntoIterator<Item = impl Deref<Target = SockInfo<'a>>>,
     filter: &options::Filters,
-) -> termtree::Tree {
-    let mut pout = termtree::Tree::new();
+    columns: &Columns,
+    expand: bool,
+    pd: Option<&procs::ProcDesc>,
+    shadowing: &HashMap<Ino, Vec<String>>,
+    icons: Option<&terminal::Icons>,
+) -> Tree {
+    let mut pout = Tree::new();
     let mut groups = BTreeMap::<_, Vec<_>>::new();
     for s in sockets {
         groups.entry((s.port, s.protocol)).or_default().push(s);
     }
+    // Interface and address filters include wildcard listeners, say why they're there
+    let wildcard_note = match (filter.ifaces.len(), filter.pfxs.len()) {
+        (0, 0) => None,
+        (1..=3, _) => Some(format!(
+            "wildcard, reachable via {}",
+            filter.ifaces.join(", ")
+        )),
+        (0, 1..=3) => Some(format!(
+            "wildcard, reachable on {}",
+            filter.pfxs.iter().join(", ")
+        )),
+        (0, n) => Some(format!("wildcard, reachable on {n} prefixes")),
+        (n, _) => Some(format!("wildcard, reachable via {n} interfaces")),
+    };
     for ((port, proto), socks) in groups {
-        let mut sout = termtree::Tree::new();
-        if socks.iter().map(|s| s.addr).sorted().collect::<Vec<_>>()
-            == [
-                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
-            ]
+        let mut sout = Tree::new();
+        if columns.shows(Column::Addr)
+            && !columns.per_socket_details()
+            && !socks.iter().any(|s| has_details(s, columns))
+            && socks.iter().all(|s| !shadowing.contains_key(&s.ino))
+            && socks.iter().all(|s| filter.accept_addr(s))
+            && socks.iter().map(|s| s.addr).sorted().collect::<Vec<_>>()
+                == [
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                ]
+        {
+            let icon = icons.map(|icons| format!("{} ", icons.network));
+            let note = wildcard_note.as_ref().map(|note| format!(" ({note})"));
+            sout.leaf(format!(
+                "{}0.0.0.0 + ::{}",
+                icon.unwrap_or_default(),
+                note.unwrap_or_default()
+            ));
+        } else if !expand
+            && !columns.per_socket_details()
+            && !socks.iter().any(|s| has_details(s, columns))
+            && socks.iter().all(|s| !shadowing.contains_key(&s.ino))
+            && socks.iter().filter(|s| filter.accept_addr(s)).count() > FOLD_ADDRESSES
         {
-            sout.leaf("0.0.0.0 + ::".into());
+            let socks = socks.iter().filter(|s| filter.accept_addr(s));
+            let addrs = socks.clone().map(|s| s.addr).unique().count();
+            let ifaces = socks
+                .filter_map(|s| s.iface.map(Iface::name))
+                .unique()
+                .count();
+            sout.leaf(match ifaces {
+                0 => format!("{addrs} addresses"),
+                _ => format!("{addrs} addresses on {ifaces} interfaces"),
+            });
         } else {
-            for sock in socks {
-                if filter.accept_addr(sock.addr) {
-                    match (sock.family, sock.iface) {
-                        (Family::Both, _) => sout.leaf("*".into()),
-                        (_, Some(ifname)) => sout.leaf(format!("{} ({ifname})", sock.addr)),
-                        _ => sout.leaf(format!("{}", sock.addr)),
-                    };
-                }
-            }
+            socks
+                .iter()
+                .filter(|sock| filter.accept_addr(sock))
+                .map(|sock| {
+                    let shadows = shadowing.get(&sock.ino);
+                    let wildcard = (wildcard_note.as_ref()).filter(|_| {
+                        sock.addr.is_unspecified() && !matches!(sock.iface, Some(Iface::Bound(_)))
+                    });
+                    let notes = (shadows.into_iter().flatten().cloned())
+                        .chain(cloud_note(sock))
+                        .chain(wildcard.cloned())
+                        .chain(sock.v4_mapped().then(|| "dual-stack".into()))
+                        .join(", ");
+                    let label = addr_label(sock, columns, icons);
+                    match notes.is_empty() {
+                        true => (label, false),
+                        false => (format!("{label} ({notes})"), shadows.is_some()),
+                    }
+                })
+                .unique()
+                .for_each(|(label, shadowing)| {
+                    sout.leaf(label);
+                    if shadowing {
+                        sout.highlight_since(sout.len() - 1);
+                    }
+                });
         }
         if filter.accept_port(port) && filter.accept_proto(proto) {
-            pout.node(format!(":{port} {proto}"), sout);
+            let flagged = pd.is_some_and(|pd| socks.iter().any(|s| pd.unexpectedly_privileged(s)));
+            let spawns = pd.and_then(|pd| pd.spawns.get(&(port, proto)));
+            let rpc = socks.iter().flat_map(|s| &s.rpc).unique().join(", ");
+            let announced = socks.iter().flat_map(|s| &s.announced).unique().join(", ");
+            let exposed = socks.iter().flat_map(|s| &s.exposed).unique().join(", ");
+            let firewalld = socks.iter().flat_map(|s| &s.firewalld).unique().join(", ");
+            // Expanded wildcard listeners appear once per address, but have accepted them once
+            let conns = (socks.iter().unique_by(|s| s.ino))
+                .filter_map(|s| s.conns)
+                .reduce(|a, b| a + b)
+                .filter(|_| columns.shows(Column::Conns));
+            // Port and protocol are aligned with those of the sibling sockets
+            let port = match proto {
+                Protocol::Packet => netlink::sock::ethertype(port),
+                _ => format!(":{port}"),
+            };
+            let label = [(Column::Port, port), (Column::Proto, format!("{proto}"))]
+                .into_iter()
+                .filter(|(c, _)| {
+                    columns.shows(*c) && !(*c == Column::Port && proto == Protocol::Unix)
+                })
+                .map(|(_, l)| l)
+                .chain([[
+                    spawns.map(|program| format!("→ {program}")),
+                    (!rpc.is_empty()).then(|| format!("(rpc {rpc})")),
+                    (!announced.is_empty()).then(|| format!("(announced via {announced})")),
+                    (!exposed.is_empty()).then(|| format!("(exposed via router as {exposed})")),
+                    (!firewalld.is_empty()).then(|| format!("(firewalld {firewalld})")),
+                    flagged.then(|| "(no CAP_NET_BIND_SERVICE)".into()),
+                    conns.map(|n| match n {
+                        1 => "(1 conn)".into(),
+                        n => format!("({n} conns)"),
+                    }),
+                ]
+                .into_iter()
+                .flatten()
+                .join(" ")])
+                .join(&termtree::CELL.to_string());
+            let start = pout.len();
+            match (columns.port_level(), columns.addr_level()) {
+                (true, true) => pout.node(label, sout),
+                (true, false) if !sout.is_empty() => pout.leaf(label),
+                (true, false) => &mut pout,
+                (false, _) => pout.extend(sout),
+            };
+            if columns.port_level() {
+                let widest = socks.iter().map(|s| reach(s)).max();
+                let reach = widest.unwrap_or(Reach::Network).class();
+                pout.classify_since(start, &[&proto.to_string(), reach]);
+            }
+            if flagged {
+                pout.highlight_since(start);
+            }
         }
     }
     pout
 }
+
+/// Who can connect to a socket, by icon and theme class, ordered from least to most exposed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Reach {
+    Loopback,
+    /// Not from the internet, according to --cloud
+    Private,
+    Network,
+}
+
+impl Reach {
+    fn class(self) -> &'static str {
+        match self {
+            Reach::Loopback => "loopback",
+            Reach::Private => "private",
+            Reach::Network => "network",
+        }
+    }
+}
+
+fn reach(sock: &SockInfo) -> Reach {
+    match sock.addr.is_loopback() {
+        true => Reach::Loopback,
+        false if sock.cloud.as_ref().is_some_and(|c| c.public.is_empty()) => Reach::Private,
+        false => Reach::Network,
+    }
+}
+
+/// Only worth mentioning if anything was actually dropped
+fn dropped(sock: &SockInfo) -> Option<u32> {
+    sock.drops.filter(|&d| d > 0)
+}
+
+/// Whether a summary line for several sockets would hide something about this one
+fn has_details(sock: &SockInfo, columns: &Columns) -> bool {
+    dropped(sock).is_some()
+        || (columns.shows(Column::Qos) && qos(sock).is_some())
+        || (columns.shows(Column::Cong) && sock.congestion.is_some())
+        || (columns.shows(Column::Backlog) && sock.backlog.is_some())
+        || (columns.shows(Column::Cgroup) && sock.cgroup.is_some())
+        || netns(sock, columns).is_some()
+        || sock.cloud.is_some()
+}
+
+/// Public addresses that the cloud maps to the socket, e.g. public 203.0.113.7, groups web
+fn cloud_note(sock: &SockInfo) -> Option<String> {
+    let cloud = sock.cloud.as_ref()?;
+    if cloud.public.is_empty() {
+        return Some("no public IP".into());
+    }
+    let groups = (!cloud.groups.is_empty()).then(|| format!("groups {}", cloud.groups.join(" + ")));
+    Some(
+        [
+            Some(format!("public {}", cloud.public.iter().join(" + "))),
+            groups,
+        ]
+        .into_iter()
+        .flatten()
+        .join(", "),
+    )
+}
+
+/// Network namespace, if asked for, not the one lls runs in, or the scope of an abstract name
+fn netns(sock: &SockInfo, columns: &Columns) -> Option<u64> {
+    let scoped = matches!(sock.unix, Some(netlink::sock::UnixName::Abstract(_)));
+    (sock.netns)
+        .filter(|&ns| scoped || columns.shows(Column::Netns) || Some(ns) != procs::own_netns())
+}
+
+/// TOS/DSCP and priority, if they differ from the defaults
+fn qos(sock: &SockInfo) -> Option<String> {
+    let tos = (sock.tos.filter(|&t| t != 0)).map(|t| format!("tos {t:#04x} (dscp {})", t >> 2));
+    let prio = (sock.priority.filter(|&p| p != 0)).map(|p| format!("prio {p}"));
+    match (tos, prio) {
+        (None, None) => None,
+        (tos, prio) => Some(tos.into_iter().chain(prio).join(" ")),
+    }
+}
+
+fn addr_label(sock: &SockInfo, columns: &Columns, icons: Option<&terminal::Icons>) -> String {
+    // Extended output spells out whether the interface is a restriction or a guess
+    let iface = sock
+        .iface
+        .map(|iface| match (iface, columns.per_socket_details()) {
+            (Iface::Bound(name), true) => format!("bound to {name}"),
+            (Iface::Route(name), true) => format!("via {name}"),
+            (iface, false) => iface.name().into(),
+        });
+    let addr = match (columns.shows(Column::Addr), columns.shows(Column::Iface)) {
+        (true, show_iface) => match (sock.family, iface) {
+            (Family::Unix, _) => sock.unix.as_ref().map_or("?".into(), ToString::to_string),
+            (Family::Both | Family::Packet, Some(iface)) if show_iface && !sock.v4_mapped() => {
+                format!("* ({iface})")
+            }
+            (Family::Both | Family::Packet, _) if !sock.v4_mapped() => "*".into(),
+            (_, Some(iface)) if show_iface => format!("{} ({iface})", sock.addr),
+            _ => format!("{}", sock.addr),
+        },
+        (false, true) => match iface {
+            _ if sock.family == Family::Unix => String::new(),
+            Some(iface) => iface,
+            None if sock.addr.is_unspecified() => "*".into(),
+            None => "?".into(),
+        },
+        (false, false) => String::new(),
+    };
+    let fds = match sock.fds.as_slice() {
+        [] => None,
+        [fd] => Some(format!("fd {fd}")),
+        fds => Some(format!("fds {}", fds.iter().join(","))),
+    };
+    let details = [
+        (Column::Fd, fds),
+        (
+            Column::Inode,
+            Some(match sock.fallback {
+                Some(tcp) => format!("ino {} (fallback tcp ino {tcp})", sock.ino),
+                None => format!("ino {}", sock.ino),
+            }),
+        ),
+        (
+            Column::Cookie,
+            sock.cookie.map(|c| format!("cookie {c:#x}")),
+        ),
+        (Column::Drops, dropped(sock).map(|d| format!("drops {d}"))),
+        (Column::Qos, qos(sock)),
+        (
+            Column::Cong,
+            (sock.congestion.as_ref()).map(|cc| format!("cc {cc}")),
+        ),
+        (
+            Column::Backlog,
+            sock.backlog.map(|b| match sock.backlog_clamped() {
+                true => format!("backlog {b} (net.core.somaxconn, may be clamped)"),
+                false => format!("backlog {b}"),
+            }),
+        ),
+        (
+            Column::Cgroup,
+            sock.cgroup.map(|id| match procs::cgroup_path(id) {
+                Some(path) => format!("cgroup {path}"),
+                None => format!("cgroup #{id}"),
+            }),
+        ),
+    ];
+    let icon = (icons.filter(|_| !addr.is_empty())).map(|icons| match reach(sock) {
+        Reach::Loopback => icons.loopback,
+        Reach::Private => icons.private,
+        Reach::Network => icons.network,
+    });
+    icon.into_iter()
+        .map(Into::into)
+        .chain([addr])
+        .chain(
+            details
+                .into_iter()
+                .filter(|(c, _)| columns.shows(*c))
+                .filter_map(|(_, d)| d),
+        )
+        .chain(netns(sock, columns).map(|ns| format!("netns {ns}")))
+        .filter(|l| !l.is_empty())
+        .join(" ")
+}
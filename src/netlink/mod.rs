@@ -1,13 +1,27 @@
+#[cfg(feature = "netlink")]
+mod packet;
 pub mod route;
+#[cfg(feature = "netlink")]
+mod smc;
 pub mod sock;
+#[cfg(feature = "netlink")]
+mod unix;
+#[cfg(feature = "wireguard")]
 pub mod wg;
 
-use anyhow::{Context, Result};
+#[cfg(feature = "netlink")]
+use anyhow::{bail, Context, Result};
+#[cfg(feature = "netlink")]
+use itertools::Itertools;
+#[cfg(feature = "netlink")]
 use netlink_packet_core::{
     NetlinkDeserializable, NetlinkHeader, NetlinkMessage, NetlinkPayload, NetlinkSerializable,
 };
+#[cfg(feature = "netlink")]
 use netlink_sys::Socket;
+use std::{io::Write, sync::Mutex};
 
+#[cfg(feature = "netlink")]
 fn drive_req<T>(
     mut packet: NetlinkMessage<T>,
     socket: &Socket,
@@ -21,6 +35,8 @@ where
     let mut buf = vec![0; packet.header.length as usize];
     assert!(buf.len() == packet.buffer_len());
     packet.serialize(&mut buf[..]);
+    debug_dump("send", &buf);
+    record(socket, PACKET_OUTGOING, &buf);
     socket.send(&buf[..], 0).context("Netlink send error")?;
     let mut receive_buffer = vec![0; 4096];
     let mut offset = 0;
@@ -28,6 +44,8 @@ where
         let size = socket
             .recv(&mut &mut receive_buffer[..], 0)
             .context("Netlink receive failure")?;
+        debug_dump("recv", &receive_buffer[..size]);
+        record(socket, PACKET_HOST, &receive_buffer[..size]);
 
         loop {
             let bytes = &receive_buffer[offset..];
@@ -40,7 +58,7 @@ where
                     Some(_) => return Err(err.to_io()).context("Netlink error"),
                     None => return Ok(()),
                 },
-                p => todo!("Unexpected netlink payload {:?}", p.message_type()),
+                p => bail!("Unexpected netlink payload {:?}", p.message_type()),
             }
 
             offset += rx_packet.header.length as usize;
@@ -52,8 +70,99 @@ where
     }
 }
 
+#[cfg(feature = "netlink")]
 pub fn nl_hdr_flags(flags: u16) -> NetlinkHeader {
     let mut header = NetlinkHeader::default();
     header.flags = flags;
     header
 }
+
+static DEBUG: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+/// Hex dump all sent and received netlink messages to out
+pub fn debug_to(out: Box<dyn Write + Send>) {
+    *DEBUG.lock().expect("Netlink debug output poisoned") = Some(out);
+}
+
+static RECORDING: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Keep a copy of all sent and received netlink messages, until [recording] takes it
+pub fn start_recording() {
+    // pcap header: version 2.4, the usual snap length, LINKTYPE_NETLINK
+    let mut pcap = Vec::new();
+    pcap.extend(0xa1b2c3d4u32.to_le_bytes());
+    pcap.extend(2u16.to_le_bytes());
+    pcap.extend(4u16.to_le_bytes());
+    pcap.extend([0; 8]);
+    pcap.extend(262144u32.to_le_bytes());
+    pcap.extend(253u32.to_le_bytes());
+    *RECORDING.lock().expect("Netlink recording poisoned") = Some(pcap);
+}
+
+/// The netlink traffic since [start_recording] as pcap, in the format of nlmon captures
+#[cfg(feature = "bundle")]
+pub fn recording() -> Option<Vec<u8>> {
+    RECORDING.lock().expect("Netlink recording poisoned").take()
+}
+
+#[cfg(feature = "netlink")]
+const PACKET_HOST: u16 = 0;
+#[cfg(feature = "netlink")]
+const PACKET_OUTGOING: u16 = 4;
+
+#[cfg(feature = "netlink")]
+fn record(socket: &Socket, direction: u16, bytes: &[u8]) {
+    use std::os::fd::AsRawFd;
+    let mut recording = RECORDING.lock().expect("Netlink recording poisoned");
+    let Some(pcap) = recording.as_mut() else {
+        return;
+    };
+    let mut protocol: libc::c_int = 0;
+    let mut len = std::mem::size_of_val(&protocol) as libc::socklen_t;
+    unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PROTOCOL,
+            (&mut protocol as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    let time = (std::time::SystemTime::now())
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let size = (16 + bytes.len()) as u32;
+    pcap.extend((time.as_secs() as u32).to_le_bytes());
+    pcap.extend(time.subsec_micros().to_le_bytes());
+    pcap.extend(size.to_le_bytes());
+    pcap.extend(size.to_le_bytes());
+    // Linux cooked header as nlmon has it: direction, ARPHRD_NETLINK, no address, netlink family
+    pcap.extend(direction.to_be_bytes());
+    pcap.extend(824u16.to_be_bytes());
+    pcap.extend([0; 10]);
+    pcap.extend((protocol as u16).to_be_bytes());
+    pcap.extend(bytes);
+}
+
+#[cfg(feature = "netlink")]
+fn debug_dump(direction: &str, bytes: &[u8]) {
+    let mut debug = DEBUG.lock().expect("Netlink debug output poisoned");
+    let Some(out) = debug.as_mut() else {
+        return;
+    };
+    let mut dump = format!("netlink {direction} {} bytes\n", bytes.len());
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex = chunk.iter().map(|b| format!("{b:02x}")).join(" ");
+        let ascii = chunk
+            .iter()
+            .map(|&b| match b.is_ascii_graphic() {
+                true => b as char,
+                false => '.',
+            })
+            .collect::<String>();
+        dump.push_str(&format!("{:08x}  {hex:<47}  |{ascii}|\n", line * 16));
+    }
+    out.write_all(dump.as_bytes())
+        .and_then(|()| out.flush())
+        .ok();
+}
@@ -0,0 +1,135 @@
+//! AF_PACKET sockets via packet_diag, which netlink-packet-sock-diag doesn't know
+//!
+//! Packet sockets receive frames before the IP stack sees them, e.g. for tcpdump, dhclient or
+//! lldpd, so they're listening in a sense, on an interface and for an ethertype.
+
+use super::{
+    drive_req, nl_hdr_flags,
+    sock::{Family, Iface, Protocol, SockInfo},
+};
+use crate::{log, timings, Ino};
+use netlink_packet_core::{
+    NetlinkDeserializable, NetlinkHeader, NetlinkMessage, NetlinkPayload, NetlinkSerializable,
+    NLM_F_DUMP, NLM_F_REQUEST,
+};
+use netlink_packet_sock_diag::constants::{AF_PACKET, SOCK_DIAG_BY_FAMILY};
+use netlink_packet_utils::nla::NlasIterator;
+use netlink_sys::Socket;
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr},
+};
+
+const PACKET_SHOW_INFO: u32 = 1;
+const PACKET_DIAG_INFO: u16 = 0;
+const PACKET_DIAG_UID: u16 = 5;
+/// struct packet_diag_req: family, protocol, pad, inode, show, cookie
+const REQUEST_LEN: usize = 20;
+/// struct packet_diag_msg: family, type, ethertype, inode, cookie
+const RESPONSE_LEN: usize = 16;
+
+enum PacketDiag {
+    Request,
+    Response(Response),
+}
+
+struct Response {
+    ethertype: u16,
+    inode: u32,
+    cookie: [u8; 8],
+    /// 0 for all interfaces
+    ifindex: Option<u32>,
+    uid: Option<u32>,
+}
+
+impl NetlinkSerializable for PacketDiag {
+    fn message_type(&self) -> u16 {
+        SOCK_DIAG_BY_FAMILY
+    }
+
+    fn buffer_len(&self) -> usize {
+        REQUEST_LEN
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) {
+        buffer.fill(0);
+        buffer[0] = AF_PACKET;
+        buffer[8..12].copy_from_slice(&PACKET_SHOW_INFO.to_ne_bytes());
+    }
+}
+
+impl NetlinkDeserializable for PacketDiag {
+    type Error = io::Error;
+
+    fn deserialize(_: &NetlinkHeader, payload: &[u8]) -> Result<Self, Self::Error> {
+        let Some(msg) = payload.get(..RESPONSE_LEN) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Short packet_diag_msg of {} bytes", payload.len()),
+            ));
+        };
+        let u32_at = |bytes: &[u8], at: usize| {
+            let bytes = bytes.get(at..at + 4)?;
+            Some(u32::from_ne_bytes(
+                bytes.try_into().expect("Checked length"),
+            ))
+        };
+        let mut response = Response {
+            // Unlike for socket() and bind(), in host byte order
+            ethertype: u16::from_ne_bytes([msg[2], msg[3]]),
+            inode: u32_at(msg, 4).expect("Checked length"),
+            cookie: msg[8..16].try_into().expect("Checked length"),
+            ifindex: None,
+            uid: None,
+        };
+        for nla in NlasIterator::new(&payload[RESPONSE_LEN..]) {
+            let nla = nla.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            match nla.kind() {
+                PACKET_DIAG_INFO => response.ifindex = u32_at(nla.value(), 0),
+                PACKET_DIAG_UID => response.uid = u32_at(nla.value(), 0),
+                _ => (),
+            }
+        }
+        Ok(PacketDiag::Response(response))
+    }
+}
+
+/// Add packet sockets that receive frames to sockets
+///
+/// Sockets with ethertype 0 only send, they're left out.
+pub fn add_listeners<'i>(
+    socket: &Socket,
+    sockets: &mut HashMap<Ino, SockInfo<'i>>,
+    interfaces: &'i HashMap<u32, String>,
+) {
+    let mut listeners = Vec::new();
+    let packet = NetlinkMessage::new(
+        nl_hdr_flags(NLM_F_REQUEST | NLM_F_DUMP),
+        NetlinkPayload::InnerMessage(PacketDiag::Request),
+    );
+    let res = timings::time("sock_diag packet", || {
+        drive_req(packet, socket, |inner| match inner {
+            PacketDiag::Response(r) if r.ethertype != 0 => listeners.push(r),
+            _ => (),
+        })
+    });
+    if let Err(e) = res {
+        // Without the packet_diag module, the kernel answers ENOENT
+        log::info!("Can't list packet sockets: {e:#}");
+        return;
+    }
+    for r in listeners {
+        let ino = Ino::from(r.inode);
+        let any = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        let uid = r.uid.unwrap_or_default();
+        let info = SockInfo {
+            cookie: Some(super::sock::cookie(r.cookie)),
+            iface: (r.ifindex)
+                .and_then(|idx| interfaces.get(&idx))
+                .map(|name| Iface::Bound(name)),
+            ..SockInfo::new(Family::Packet, Protocol::Packet, r.ethertype, any, uid, ino)
+        };
+        sockets.insert(ino, info);
+    }
+}
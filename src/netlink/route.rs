@@ -1,16 +1,26 @@
+#[cfg(feature = "netlink")]
 use super::{drive_req, nl_hdr_flags};
-use anyhow::{Context, Result};
+use anyhow::Context;
+#[cfg(feature = "netlink")]
+use anyhow::Result;
+#[cfg(feature = "netlink")]
 use netlink_packet_core::{
     NetlinkHeader, NetlinkMessage, NetlinkPayload, NLM_F_DUMP, NLM_F_REQUEST,
 };
+#[cfg(feature = "netlink")]
 use netlink_packet_route::{
+    address::{AddressAttribute, AddressMessage},
     link::{InfoData, InfoKind, InfoVxlan, LinkAttribute, LinkExtentMask, LinkInfo, LinkMessage},
     route::{RouteAddress, RouteAttribute, RouteMessage, RouteType},
     RouteNetlinkMessage,
 };
+#[cfg(feature = "netlink")]
 use netlink_sys::{protocols::NETLINK_ROUTE, Socket, SocketAddr};
-use std::{cmp::Reverse, collections::HashMap, net::IpAddr};
+use std::net::IpAddr;
+#[cfg(feature = "netlink")]
+use std::{cmp::Reverse, collections::HashMap};
 
+#[cfg(feature = "netlink")]
 #[derive(Default)]
 pub struct Interfaces {
     pub id2name: HashMap<u32, String>,
@@ -18,6 +28,7 @@ pub struct Interfaces {
     pub vxlan_ports: Vec<(u32, u16)>,
 }
 
+#[cfg(feature = "netlink")]
 pub fn interface_names(socket: &Socket) -> Result<Interfaces> {
     let mut get_link = LinkMessage::default();
     get_link
@@ -72,6 +83,35 @@ pub fn interface_names(socket: &Socket) -> Result<Interfaces> {
     })
 }
 
+/// Addresses configured on interfaces, by interface index
+#[cfg(feature = "netlink")]
+pub fn addresses(socket: &Socket) -> Result<Vec<(u32, IpAddr)>> {
+    let packet = NetlinkMessage::new(
+        nl_hdr_flags(NLM_F_REQUEST | NLM_F_DUMP),
+        NetlinkPayload::from(RouteNetlinkMessage::GetAddress(AddressMessage::default())),
+    );
+    let mut ret = Vec::new();
+    drive_req(packet, socket, |inner| {
+        if let RouteNetlinkMessage::NewAddress(addr) = inner {
+            // On point-to-point links, IFA_ADDRESS is the peer's, IFA_LOCAL ours
+            let local = addr.attributes.iter().find_map(|nla| match nla {
+                AddressAttribute::Local(a) => Some(*a),
+                _ => None,
+            });
+            let address = addr.attributes.iter().find_map(|nla| match nla {
+                AddressAttribute::Address(a) => Some(*a),
+                _ => None,
+            });
+            if let Some(a) = local.or(address) {
+                ret.push((addr.header.index, a));
+            }
+        }
+    })
+    .context("Get interface addresses")?;
+    Ok(ret)
+}
+
+#[cfg(feature = "netlink")]
 pub fn socket() -> Result<Socket> {
     let mut socket = Socket::new(NETLINK_ROUTE).context("Construct netlink route socket")?;
     socket.bind_auto().context("Bind netlink route socket")?;
@@ -138,8 +178,35 @@ struct Route {
 #[derive(Default)]
 pub struct Rtbl(Vec<Route>);
 
+/// Routing tables to take local routes from, for --route-table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteTables {
+    /// The local table, and these others, e.g. of VRFs, which keep their local routes apart
+    Local(Vec<u32>),
+    All,
+}
+
+pub const RT_TABLE_LOCAL: u32 = 255;
+
+impl Default for RouteTables {
+    fn default() -> Self {
+        RouteTables::Local(Vec::new())
+    }
+}
+
+#[cfg(feature = "netlink")]
+impl RouteTables {
+    pub fn contains(&self, table: u32) -> bool {
+        match self {
+            RouteTables::Local(others) => table == RT_TABLE_LOCAL || others.contains(&table),
+            RouteTables::All => true,
+        }
+    }
+}
+
 // Dirty longest prefix implementation based on sorting, without even splitting v4/v6 (and just checking in order)
 impl Rtbl {
+    #[cfg(feature = "netlink")]
     fn new(mut routes: Vec<Route>) -> Rtbl {
         routes.sort_by_key(|r| Reverse(r.pfx.bits)); // Normally, you'd also sort by metric, but
         Self(routes)
@@ -160,10 +227,15 @@ impl Rtbl {
     }
 }
 
-pub fn local_routes(socket: &Socket) -> Result<Rtbl> {
-    const RT_TABLE_LOCAL: u8 = 0;
+/// Routes to the machine's own addresses, which tell the interface an address belongs to
+#[cfg(feature = "netlink")]
+pub fn local_routes(socket: &Socket, tables: &RouteTables) -> Result<Rtbl> {
     let mut route_message = RouteMessage::default();
-    route_message.header.table = RT_TABLE_LOCAL; // This is respected
+    // This is respected, tables above 255 can only be found in a dump of all (0)
+    route_message.header.table = match tables {
+        RouteTables::Local(others) if others.is_empty() => RT_TABLE_LOCAL as u8,
+        _ => 0,
+    };
     route_message.header.kind = RouteType::Local; // This is not respected
     let packet = NetlinkMessage::new(
         nl_hdr_flags(NLM_F_REQUEST | NLM_F_DUMP),
@@ -173,7 +245,12 @@ pub fn local_routes(socket: &Socket) -> Result<Rtbl> {
     let mut ret = Vec::new();
     drive_req(packet, socket, |inner| {
         if let RouteNetlinkMessage::NewRoute(route) = inner {
-            if route.header.table == RT_TABLE_LOCAL && route.header.kind == RouteType::Local {
+            let table = route.attributes.iter().find_map(|nla| match nla {
+                RouteAttribute::Table(table) => Some(*table),
+                _ => None,
+            });
+            let table = table.unwrap_or(route.header.table.into());
+            if tables.contains(table) && route.header.kind == RouteType::Local {
                 let iface = route.attributes.iter().find_map(|nla| match nla {
                     RouteAttribute::Oif(ifc) => Some(ifc),
                     _ => None,
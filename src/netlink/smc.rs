@@ -0,0 +1,147 @@
+//! SMC-R/SMC-D listeners via smc_diag, which netlink-packet-sock-diag doesn't know
+
+use super::{
+    drive_req, nl_hdr_flags,
+    sock::{Family, Protocol, SockInfo},
+};
+use crate::{log, timings, Ino};
+use itertools::Itertools;
+use netlink_packet_core::{
+    NetlinkDeserializable, NetlinkHeader, NetlinkMessage, NetlinkPayload, NetlinkSerializable,
+    NLM_F_DUMP, NLM_F_REQUEST,
+};
+use netlink_packet_sock_diag::constants::SOCK_DIAG_BY_FAMILY;
+use netlink_sys::Socket;
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+const AF_SMC: u8 = 43;
+const SMC_LISTEN: u8 = 10;
+/// struct smc_diag_req: family, 2 pad bytes, extensions, struct inet_diag_sockid
+const REQUEST_LEN: usize = 4 + 48;
+/// struct smc_diag_msg: family, state, mode, shutdown, inet_diag_sockid, uid, 64 bit inode
+const RESPONSE_LEN: usize = 4 + 48 + 4 + 8;
+
+enum SmcDiag {
+    Request,
+    Response(Response),
+}
+
+struct Response {
+    state: u8,
+    port: u16,
+    /// The kernel only fills the first 4 bytes for IPv4
+    addr: [u8; 16],
+    uid: u32,
+    inode: Ino,
+    cookie: [u8; 8],
+}
+
+impl NetlinkSerializable for SmcDiag {
+    fn message_type(&self) -> u16 {
+        SOCK_DIAG_BY_FAMILY
+    }
+
+    fn buffer_len(&self) -> usize {
+        REQUEST_LEN
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) {
+        buffer.fill(0);
+        buffer[0] = AF_SMC;
+    }
+}
+
+impl NetlinkDeserializable for SmcDiag {
+    type Error = io::Error;
+
+    fn deserialize(_: &NetlinkHeader, payload: &[u8]) -> Result<Self, Self::Error> {
+        let Some(msg) = payload.get(..RESPONSE_LEN) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Short smc_diag_msg of {} bytes", payload.len()),
+            ));
+        };
+        let bytes = |at: usize, len: usize| &msg[at..at + len];
+        Ok(SmcDiag::Response(Response {
+            state: msg[1],
+            port: u16::from_be_bytes([msg[4], msg[5]]),
+            addr: bytes(8, 16).try_into().expect("Checked length"),
+            cookie: bytes(44, 8).try_into().expect("Checked length"),
+            uid: u32::from_ne_bytes(bytes(52, 4).try_into().expect("Checked length")),
+            inode: u64::from_ne_bytes(bytes(56, 8).try_into().expect("Checked length")),
+        }))
+    }
+}
+
+/// Add listening SMC sockets to the inet sockets in sockets
+///
+/// Each SMC socket has an internal TCP socket for the connection handshake and as the fallback
+/// if the peer can't do SMC. It isn't part of any process, so it's removed from sockets and
+/// noted on the SMC socket instead.
+pub fn add_listeners(socket: &Socket, sockets: &mut HashMap<Ino, SockInfo>) {
+    let mut listeners = Vec::new();
+    let packet = NetlinkMessage::new(
+        nl_hdr_flags(NLM_F_REQUEST | NLM_F_DUMP),
+        NetlinkPayload::InnerMessage(SmcDiag::Request),
+    );
+    let res = timings::time("sock_diag smc", || {
+        drive_req(packet, socket, |inner| match inner {
+            SmcDiag::Response(r) if r.state == SMC_LISTEN => listeners.push(r),
+            _ => (),
+        })
+    });
+    if let Err(e) = res {
+        // Without the smc_diag module (or SMC support at all), the kernel answers ENOENT
+        log::debug!("No SMC sockets: {e:#}");
+        return;
+    }
+    for r in listeners {
+        let v4 = IpAddr::V4(Ipv4Addr::new(r.addr[0], r.addr[1], r.addr[2], r.addr[3]));
+        let v6 = IpAddr::V6(Ipv6Addr::from(r.addr));
+        // smc_diag_msg has AF_SMC as family, so the address can be read either way, e.g. as
+        // 0.0.0.0 or ::. The kernel creates the TCP socket along with the SMC socket, so it has
+        // the same owner. If more than one fits, some are processes' own, and none is taken.
+        let fallback = (sockets.values())
+            .filter(|s| {
+                s.protocol == Protocol::TCP
+                    && s.port == r.port
+                    && s.uid == r.uid
+                    && match s.family {
+                        Family::V4 => s.addr == v4,
+                        Family::V6 | Family::Both => s.addr == v6,
+                        _ => false,
+                    }
+            })
+            .map(|s| s.ino)
+            .exactly_one()
+            .ok();
+        let info = match fallback.and_then(|ino| sockets.remove(&ino)) {
+            Some(tcp) => SockInfo {
+                protocol: Protocol::SMC,
+                uid: r.uid,
+                ino: r.inode,
+                cookie: Some(super::sock::cookie(r.cookie)),
+                drops: None,
+                fallback: Some(tcp.ino),
+                ..tcp
+            },
+            None => {
+                // Only an IPv6 address can have bytes beyond the first 4
+                let is_v6 = r.addr[4..].iter().any(|&b| b != 0);
+                let (family, addr) = match is_v6 {
+                    true => (Family::V6, v6),
+                    false => (Family::V4, v4),
+                };
+                SockInfo {
+                    cookie: Some(super::sock::cookie(r.cookie)),
+                    ..SockInfo::new(family, Protocol::SMC, r.port, addr, r.uid, r.inode)
+                }
+            }
+        };
+        sockets.insert(info.ino, info);
+    }
+}
@@ -1,32 +1,49 @@
+#[cfg(feature = "netlink")]
 use super::{drive_req, nl_hdr_flags, route::Rtbl};
-use crate::{IfaceInfo, Ino};
-use anyhow::{Context, Result};
+#[cfg(feature = "netlink")]
+use crate::{intern::Interner, log, timings};
+use crate::{
+    procs::{somaxconn, unprivileged_port_start, PRIVILEGED_PORTS},
+    IfaceInfo, Ino,
+};
+#[cfg(feature = "netlink")]
+use anyhow::Context;
+use anyhow::Result;
+#[cfg(feature = "netlink")]
 use netlink_packet_core::{NetlinkMessage, NLM_F_DUMP, NLM_F_REQUEST};
+#[cfg(feature = "netlink")]
 use netlink_packet_sock_diag::{
     constants::*,
     inet::{nlas::Nla, ExtensionFlags, InetRequest, InetResponse, SocketId, StateFlags},
     SockDiagMessage,
 };
+#[cfg(feature = "netlink")]
+use netlink_packet_utils::nla::Nla as _;
+#[cfg(feature = "netlink")]
 use netlink_sys::{protocols::NETLINK_SOCK_DIAG, Socket, SocketAddr};
-use std::{collections::HashMap, fmt::Display, net::IpAddr};
+use std::{collections::HashMap, fmt::Display, net::IpAddr, rc::Rc};
+
+/// Missing from netlink-packet-sock-diag
+#[cfg(feature = "netlink")]
+const INET_DIAG_CGROUP_ID: u16 = 21;
 
+/// With extensions, request exactly those instead of the ones lls shows for each protocol
+#[cfg(feature = "netlink")]
 pub fn all_sockets<'i>(
     IfaceInfo {
         id2name: interfaces,
         local_routes,
         ..
     }: &'i IfaceInfo,
+    extensions: Option<&[Extension]>,
 ) -> Result<HashMap<Ino, SockInfo<'i>>> {
-    let mut socket =
-        Socket::new(NETLINK_SOCK_DIAG).context("Construct netlink socket information socket")?;
-    socket
-        .bind_auto()
-        .context("Bind netlink socket information socket")?;
-    socket
-        .connect(&SocketAddr::new(0, 0))
-        .context("Connect netlink socket information socket")?;
-
+    let socket = diag_socket()?;
     let mut ret = HashMap::new();
+    let mut interner = Interner::default();
+    let mut missing = (extensions.unwrap_or_default().iter())
+        .filter(|ext| Extension::UNASKED.contains(ext))
+        .copied()
+        .collect::<Vec<_>>();
 
     let protocols = [
         Protocol::TCP,
@@ -46,47 +63,170 @@ pub fn all_sockets<'i>(
                     family: family.proto_const(),
                     protocol: protocol.proto_const(),
                     socket_id: family.proto_socket_id(),
-                    extensions: ExtensionFlags::empty(),
+                    extensions: match (extensions, protocol) {
+                        (Some(extensions), _) => (extensions.iter())
+                            .fold(ExtensionFlags::empty(), |flags, ext| flags | ext.flag()),
+                        (None, Protocol::UDP | Protocol::UDPlite) => {
+                            ExtensionFlags::SKMEMINFO | ExtensionFlags::TOS | ExtensionFlags::TCLASS
+                        }
+                        (None, Protocol::TCP) => {
+                            ExtensionFlags::CONG | ExtensionFlags::TOS | ExtensionFlags::TCLASS
+                        }
+                        (None, _) => ExtensionFlags::TOS | ExtensionFlags::TCLASS,
+                    },
                     states: StateFlags::all(),
                 })
                 .into(),
             );
-            drive_req(packet, &socket, |inner| match inner {
-                SockDiagMessage::InetResponse(response) => {
-                    if response.header.socket_id.destination_port == 0 {
-                        ret.insert(
-                            response.header.inode.into(),
-                            SockInfo::new(family, protocol, *response, interfaces, local_routes),
-                        );
+            timings::time(format!("sock_diag {protocol} {family}"), || {
+                drive_req(packet, &socket, |inner| match inner {
+                    SockDiagMessage::InetResponse(response) => {
+                        if response.header.socket_id.destination_port == 0 {
+                            missing.retain(|ext| !ext.sent(&response.nlas));
+                            ret.insert(
+                                response.header.inode.into(),
+                                SockInfo::from_inet(
+                                    family,
+                                    protocol,
+                                    *response,
+                                    interfaces,
+                                    local_routes,
+                                    &mut interner,
+                                ),
+                            );
+                        }
                     }
-                }
-                _ => unreachable!("We made an InetRequest, we get an InetResponse, yeah?"),
+                    _ => unreachable!("We made an InetRequest, we get an InetResponse, yeah?"),
+                })
             })
             .context("Read listening sockets")?;
         }
     }
+    if !ret.is_empty() {
+        for ext in missing {
+            let (name, why) = match ext {
+                Extension::Mark => ("mark", "it is only sent to CAP_NET_ADMIN"),
+                Extension::Cgroup => ("cgroup", "it needs Linux 5.9"),
+                _ => ("class_id", "it needs a newer kernel"),
+            };
+            log::warn!("The kernel sent no {name} extension, {why}; skipping it");
+        }
+    }
+    super::smc::add_listeners(&socket, &mut ret);
+    super::packet::add_listeners(&socket, &mut ret, interfaces);
     Ok(ret)
 }
 
+/// Listening AF_UNIX sockets, only on request because there are many and they aren't reachable
+/// over the network
+#[cfg(feature = "netlink")]
+pub fn unix_sockets() -> Result<HashMap<Ino, SockInfo<'static>>> {
+    let socket = diag_socket()?;
+    timings::time("sock_diag unix", || super::unix::listeners(&socket))
+        .context("Read listening unix sockets")
+}
+
+/// Count established TCP connections for the listener that accepted them: the one on their
+/// local address and port, or else the wildcard listener on the port. Needs a second dump,
+/// of connections only.
+#[cfg(feature = "netlink")]
+pub fn count_connections(socks: &mut HashMap<Ino, SockInfo>) -> Result<()> {
+    let socket = diag_socket()?;
+    let mut local = HashMap::<u16, HashMap<IpAddr, u32>>::new();
+    for family in [Family::V4, Family::V6] {
+        let packet = NetlinkMessage::new(
+            nl_hdr_flags(NLM_F_REQUEST | NLM_F_DUMP),
+            SockDiagMessage::InetRequest(InetRequest {
+                family: family.proto_const(),
+                protocol: Protocol::TCP.proto_const(),
+                socket_id: family.proto_socket_id(),
+                extensions: ExtensionFlags::empty(),
+                states: StateFlags::ESTABLISHED,
+            })
+            .into(),
+        );
+        timings::time(format!("sock_diag connections {family}"), || {
+            drive_req(packet, &socket, |inner| match inner {
+                SockDiagMessage::InetResponse(response) => {
+                    let id = response.header.socket_id;
+                    let (_, addr) = unmap(family, id.source_address);
+                    let port = local.entry(id.source_port).or_default();
+                    *port.entry(addr).or_default() += 1;
+                }
+                _ => unreachable!("We made an InetRequest, we get an InetResponse, yeah?"),
+            })
+        })
+        .context("Read established TCP connections")?;
+    }
+    // Only listening TCP sockets have a backlog
+    let listening = |s: &SockInfo| s.protocol == Protocol::TCP && s.backlog.is_some();
+    let specific = (socks.values())
+        .filter(|s| listening(s) && !s.addr.is_unspecified())
+        .map(|s| (s.port, s.addr))
+        .collect::<std::collections::HashSet<_>>();
+    for sock in socks.values_mut().filter(|s| listening(s)) {
+        let conns = (local.get(&sock.port).into_iter().flatten())
+            .filter(|&(&addr, _)| match sock.addr {
+                listener if !listener.is_unspecified() => addr == listener,
+                _ if specific.contains(&(sock.port, addr)) => false,
+                IpAddr::V4(_) => addr.is_ipv4(),
+                IpAddr::V6(_) => sock.family == Family::Both || addr.is_ipv6(),
+            })
+            .map(|(_, n)| n)
+            .sum();
+        sock.conns = Some(conns);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "netlink")]
+fn diag_socket() -> Result<Socket> {
+    let mut socket =
+        Socket::new(NETLINK_SOCK_DIAG).context("Construct netlink socket information socket")?;
+    socket
+        .bind_auto()
+        .context("Bind netlink socket information socket")?;
+    socket
+        .connect(&SocketAddr::new(0, 0))
+        .context("Connect netlink socket information socket")?;
+    Ok(socket)
+}
+
+#[cfg(not(feature = "netlink"))]
+pub fn unix_sockets() -> Result<HashMap<Ino, SockInfo<'static>>> {
+    anyhow::bail!("Built without netlink support")
+}
+
+#[cfg(not(feature = "netlink"))]
+pub fn all_sockets<'i>(
+    _: &'i IfaceInfo,
+    _: Option<&[Extension]>,
+) -> Result<HashMap<Ino, SockInfo<'i>>> {
+    anyhow::bail!("Built without netlink support")
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Family {
     V4,
     V6,
     Both,
+    Unix,
+    Packet,
 }
+#[cfg(feature = "netlink")]
 impl Family {
     fn proto_const(&self) -> u8 {
         match self {
             Family::V4 => AF_INET,
             Family::V6 => AF_INET6,
-            Family::Both => panic!("Gee..."),
+            Family::Both | Family::Unix | Family::Packet => panic!("Gee..."),
         }
     }
     fn proto_socket_id(&self) -> SocketId {
         match self {
             Family::V4 => SocketId::new_v4(),
             Family::V6 => SocketId::new_v6(),
-            Family::Both => panic!("Gee..."),
+            Family::Both | Family::Unix | Family::Packet => panic!("Gee..."),
         }
     }
 }
@@ -96,6 +236,8 @@ impl Display for Family {
             Family::V4 => f.write_str("v4"),
             Family::V6 => f.write_str("v6"),
             Family::Both => f.write_str("*"),
+            Family::Unix => f.write_str("unix"),
+            Family::Packet => f.write_str("packet"),
         }
     }
 }
@@ -109,8 +251,13 @@ pub enum Protocol {
     RAW,
     SCTP,
     ICMP,
+    SMC,
+    RDS,
+    Unix,
+    Packet,
 }
 impl Protocol {
+    #[cfg(feature = "netlink")]
     fn proto_const(&self) -> u8 {
         match self {
             Protocol::TCP => IPPROTO_TCP,
@@ -119,11 +266,14 @@ impl Protocol {
             Protocol::RAW => IPPROTO_RAW,
             Protocol::SCTP => IPPROTO_SCTP,
             Protocol::ICMP => IPPROTO_ICMP,
+            Protocol::SMC | Protocol::RDS | Protocol::Unix | Protocol::Packet => {
+                panic!("{self} isn't queried through inet_diag")
+            }
         }
     }
-    const fn all() -> &'static [Protocol; 6] {
+    const fn all() -> &'static [Protocol; 10] {
         use Protocol::*;
-        &[TCP, UDP, UDPlite, RAW, SCTP, ICMP]
+        &[TCP, UDP, UDPlite, RAW, SCTP, ICMP, SMC, RDS, Unix, Packet]
     }
 }
 impl Display for Protocol {
@@ -135,6 +285,10 @@ impl Display for Protocol {
             Protocol::RAW => f.write_str("raw"),
             Protocol::SCTP => f.write_str("sctp"),
             Protocol::ICMP => f.write_str("icmp"),
+            Protocol::SMC => f.write_str("smc"),
+            Protocol::RDS => f.write_str("rds"),
+            Protocol::Unix => f.write_str("unix"),
+            Protocol::Packet => f.write_str("packet"),
         }
     }
 }
@@ -148,27 +302,208 @@ impl std::str::FromStr for Protocol {
                 return Ok(proto);
             }
         }
-        return Err(());
+        Err(())
     }
 }
 
+/// Optional inet_diag attributes, which the kernel only sends if asked to
+///
+/// Only those whose content lls shows, the others would be dropped unseen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    Cong,
+    Tos,
+    Tclass,
+    Skmeminfo,
+    /// Sent without asking if lls has CAP_NET_ADMIN
+    Mark,
+    /// Sent without asking since Linux 5.9
+    Cgroup,
+    /// Comes with tclass
+    ClassId,
+}
+impl Extension {
+    pub const ALL: [Extension; 7] = [
+        Extension::Cong,
+        Extension::Tos,
+        Extension::Tclass,
+        Extension::Skmeminfo,
+        Extension::Mark,
+        Extension::Cgroup,
+        Extension::ClassId,
+    ];
+    /// Those that the kernel decides to send on its own
+    #[cfg(feature = "netlink")]
+    const UNASKED: [Extension; 3] = [Extension::Mark, Extension::Cgroup, Extension::ClassId];
+    #[cfg(feature = "netlink")]
+    fn flag(self) -> ExtensionFlags {
+        match self {
+            Extension::Cong => ExtensionFlags::CONG,
+            Extension::Tos => ExtensionFlags::TOS,
+            Extension::Tclass => ExtensionFlags::TCLASS,
+            Extension::Skmeminfo => ExtensionFlags::SKMEMINFO,
+            Extension::Mark | Extension::Cgroup => ExtensionFlags::empty(),
+            Extension::ClassId => ExtensionFlags::TCLASS,
+        }
+    }
+    /// Whether a response has one of the [Self::UNASKED] extensions, which the kernel then
+    /// sends for all sockets
+    #[cfg(feature = "netlink")]
+    fn sent(self, nlas: &[Nla]) -> bool {
+        nlas.iter().any(|nla| match (self, nla) {
+            (Extension::Mark, Nla::Mark(_)) | (Extension::ClassId, Nla::ClassId(_)) => true,
+            (Extension::Cgroup, Nla::Other(attr)) => attr.kind() == INET_DIAG_CGROUP_ID,
+            _ => false,
+        })
+    }
+}
+impl std::str::FromStr for Extension {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "cong" | "cc" | "congestion" => Extension::Cong,
+            "tos" => Extension::Tos,
+            "tclass" => Extension::Tclass,
+            "skmeminfo" => Extension::Skmeminfo,
+            "mark" => Extension::Mark,
+            "cgroup" => Extension::Cgroup,
+            "class_id" | "classid" => Extension::ClassId,
+            "meminfo" | "info" | "vegasinfo" | "vegas" | "shutdown" => {
+                anyhow::bail!("lls doesn't show the inet_diag extension {s:?}")
+            }
+            _ => anyhow::bail!("Unknown inet_diag extension {s:?}"),
+        })
+    }
+}
+
+/// Owner of RDS sockets, and AF_UNIX sockets before Linux 5.3, which the kernel doesn't report
+pub const UNKNOWN_UID: u32 = u32::MAX;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct SockInfo<'a> {
     pub family: Family,
     pub protocol: Protocol,
+    /// 0 for AF_UNIX sockets, the ethertype for AF_PACKET sockets
     pub port: u16,
+    /// Loopback for AF_UNIX sockets, which can't be reached from other machines either,
+    /// unspecified for AF_PACKET sockets, which see frames to any address
     pub addr: IpAddr,
+    /// [UNKNOWN_UID] if the kernel doesn't report it, until a process claims the socket
     pub uid: u32,
     pub ino: Ino,
-    pub iface: Option<&'a str>,
+    pub cookie: Option<u64>,
+    /// Packets dropped because the receive buffer was full (UDP only)
+    pub drops: Option<u32>,
+    /// IP TOS byte, or the IPv6 traffic class, of outgoing packets
+    pub tos: Option<u8>,
+    /// SO_PRIORITY, or the net_cls class id if the process is in such a cgroup
+    pub priority: Option<u32>,
+    /// TCP congestion control algorithm, which accepted connections inherit
+    pub congestion: Option<Rc<str>>,
+    /// Maximum length of the accept queue, as passed to listen() (TCP and SCTP only)
+    pub backlog: Option<u32>,
+    /// Established connections that the listener accepted (TCP only), with -o conns
+    pub conns: Option<u32>,
+    /// Id of the cgroup v2 of the process that created the socket
+    pub cgroup: Option<u64>,
+    /// Programs registered with rpcbind for the port and protocol
+    pub rpc: Vec<String>,
+    /// How the port is announced on the network, e.g. mDNS _http._tcp, with --discover
+    pub announced: Vec<String>,
+    /// External addresses that the router forwards to the socket, with --gateway
+    pub exposed: Vec<String>,
+    /// Public addresses and security groups it can be reached through, with --cloud
+    pub cloud: Option<crate::cloud::Exposure>,
+    /// firewalld zones and services that open the port, with --firewalld
+    pub firewalld: Vec<String>,
+    /// Inode of the network namespace the socket was listed from
+    pub netns: Option<u64>,
+    /// SMC only: the internal TCP socket used for the handshake and as fallback
+    pub fallback: Option<Ino>,
+    /// File descriptor numbers in the owning process
+    pub fds: Vec<i32>,
+    pub iface: Option<Iface<'a>>,
+    /// AF_UNIX only: the path or abstract name it is bound to
+    pub unix: Option<UnixName>,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum UnixName {
+    Path(String),
+    /// Without the leading NUL and escaped, only visible within the network namespace
+    Abstract(String),
+}
+impl Display for UnixName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnixName::Path(path) => f.write_str(path),
+            // As ss and /proc/net/unix show them
+            UnixName::Abstract(name) => write!(f, "@{name}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Iface<'a> {
+    /// SO_BINDTODEVICE, or the scope of a link-local IPv6 address
+    Bound(&'a str),
+    /// Guessed from the local route covering the address
+    Route(&'a str),
+}
+impl<'a> Iface<'a> {
+    pub fn name(self) -> &'a str {
+        match self {
+            Iface::Bound(name) | Iface::Route(name) => name,
+        }
+    }
 }
 impl<'a> SockInfo<'a> {
-    fn new(
+    /// A socket that nothing is known about yet beyond where it listens
+    pub fn new(
+        family: Family,
+        protocol: Protocol,
+        port: u16,
+        addr: IpAddr,
+        uid: u32,
+        ino: Ino,
+    ) -> Self {
+        Self {
+            family,
+            protocol,
+            port,
+            addr,
+            uid,
+            ino,
+            cookie: None,
+            drops: None,
+            tos: None,
+            priority: None,
+            congestion: None,
+            backlog: None,
+            conns: None,
+            cgroup: None,
+            rpc: Vec::new(),
+            announced: Vec::new(),
+            exposed: Vec::new(),
+            cloud: None,
+            firewalld: Vec::new(),
+            netns: None,
+            fallback: None,
+            fds: Vec::new(),
+            iface: None,
+            unix: None,
+        }
+    }
+
+    #[cfg(feature = "netlink")]
+    fn from_inet(
         family: Family,
         protocol: Protocol,
         ir: InetResponse,
         interfaces: &'a HashMap<u32, String>,
         local_routes: &Rtbl,
+        interner: &mut Interner,
     ) -> Self {
         let family = if ir
             .nlas
@@ -179,26 +514,136 @@ impl<'a> SockInfo<'a> {
         } else {
             family
         };
-        let addr = ir.header.socket_id.source_address;
+        let (family, addr) = unmap(family, ir.header.socket_id.source_address);
         let iface = interfaces
             .get(&ir.header.socket_id.interface_id)
+            .map(|x| Iface::Bound(x))
             .or_else(|| {
                 local_routes
                     .route(addr)
                     .and_then(|iface| interfaces.get(&iface))
+                    .map(|x| Iface::Route(x))
+            });
+        let drops = ir.nlas.iter().find_map(|nla| match nla {
+            Nla::MemInfo(mem) => Some(mem.drops),
+            _ => None,
+        });
+        // The kernel only reports the traffic class for IPv6 sockets
+        let tos = (ir.nlas.iter())
+            .find_map(|nla| match nla {
+                Nla::Tc(tc) => Some(*tc),
+                _ => None,
             })
-            .map(|x| &**x);
+            .or_else(|| {
+                ir.nlas.iter().find_map(|nla| match nla {
+                    Nla::Tos(tos) => Some(*tos),
+                    _ => None,
+                })
+            });
+        let priority = ir.nlas.iter().find_map(|nla| match nla {
+            Nla::ClassId(id) => Some(*id),
+            _ => None,
+        });
+        let congestion = ir.nlas.iter().find_map(|nla| match nla {
+            Nla::Congestion(name) => Some(interner.str(name)),
+            _ => None,
+        });
+        // For listeners, the kernel reports the maximum accept queue length as send queue
+        let listening = ir.header.state == TCP_LISTEN;
+        let backlog = (listening && matches!(protocol, Protocol::TCP | Protocol::SCTP))
+            .then_some(ir.header.send_queue);
+        let cgroup = ir.nlas.iter().find_map(|nla| match nla {
+            // Always sent since Linux 5.9, but unknown to netlink-packet-sock-diag
+            Nla::Other(attr) if attr.kind() == INET_DIAG_CGROUP_ID => {
+                let mut id = [0; 8];
+                (attr.value_len() == id.len()).then(|| {
+                    attr.emit_value(&mut id);
+                    u64::from_ne_bytes(id)
+                })
+            }
+            _ => None,
+        });
+        let (port, uid, ino) = (
+            ir.header.socket_id.source_port,
+            ir.header.uid,
+            ir.header.inode.into(),
+        );
         Self {
-            family,
-            protocol,
-            port: ir.header.socket_id.source_port,
-            addr,
-            uid: ir.header.uid,
-            ino: ir.header.inode.into(),
+            cookie: Some(cookie(ir.header.socket_id.cookie)),
+            drops,
+            tos,
+            priority,
+            congestion,
+            backlog,
+            cgroup,
             iface,
+            ..Self::new(family, protocol, port, addr, uid, ino)
         }
     }
 }
+/// The kernel writes the cookie as two native endian u32, low half first
+#[cfg(feature = "netlink")]
+pub(super) fn cookie(raw: [u8; 8]) -> u64 {
+    let [a, b, c, d, e, f, g, h] = raw;
+    u64::from(u32::from_ne_bytes([a, b, c, d])) | u64::from(u32::from_ne_bytes([e, f, g, h])) << 32
+}
+
+/// Names of the ethertypes of common packet socket users, e.g. all for tcpdump, lldp for lldpd
+pub fn ethertype(ethertype: u16) -> String {
+    match ethertype {
+        0x0003 => "all".into(),
+        0x0800 => "ipv4".into(),
+        0x0806 => "arp".into(),
+        0x8035 => "rarp".into(),
+        0x86dd => "ipv6".into(),
+        0x888e => "eapol".into(),
+        0x88cc => "lldp".into(),
+        _ => format!("{ethertype:#06x}"),
+    }
+}
+
+/// An IPv6 socket bound to ::ffff:a.b.c.d only takes IPv4 connections to a.b.c.d, so it's kept
+/// as that address, on a dual-stack socket
+pub fn unmap(family: Family, addr: IpAddr) -> (Family, IpAddr) {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => (Family::Both, IpAddr::V4(v4)),
+            None => (family, addr),
+        },
+        IpAddr::V4(_) => (family, addr),
+    }
+}
+
+impl SockInfo<'_> {
+    /// Reachable over IPv4 or IPv6, unlike AF_UNIX and AF_PACKET sockets
+    pub fn is_inet(&self) -> bool {
+        matches!(self.family, Family::V4 | Family::V6 | Family::Both)
+    }
+    /// A dual-stack socket bound to an IPv4-mapped address, see [unmap]
+    pub fn v4_mapped(&self) -> bool {
+        self.family == Family::Both && self.addr.is_ipv4()
+    }
+    /// Bound to a port that needs CAP_NET_BIND_SERVICE
+    pub fn privileged_port(&self) -> bool {
+        self.bind_checked() && self.port < unprivileged_port_start()
+    }
+    /// Bound to a port that is only unprivileged because the sysctl was lowered
+    pub fn formerly_privileged_port(&self) -> bool {
+        self.bind_checked() && (unprivileged_port_start()..PRIVILEGED_PORTS).contains(&self.port)
+    }
+    /// The backlog is as large as net.core.somaxconn allows, so listen() may have been asked for more
+    pub fn backlog_clamped(&self) -> bool {
+        self.backlog
+            .is_some_and(|backlog| somaxconn().is_some_and(|max| backlog >= max))
+    }
+    fn bind_checked(&self) -> bool {
+        matches!(
+            self.protocol,
+            Protocol::TCP | Protocol::UDP | Protocol::UDPlite | Protocol::SCTP | Protocol::SMC
+        )
+    }
+}
+
 impl PartialOrd for SockInfo<'_> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -207,6 +652,6 @@ impl PartialOrd for SockInfo<'_> {
 impl Ord for SockInfo<'_> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         let key = |s: &SockInfo| (s.port, s.protocol, s.addr, s.family);
-        key(self).cmp(&key(other))
+        (key(self).cmp(&key(other))).then_with(|| self.unix.cmp(&other.unix))
     }
 }
@@ -0,0 +1,177 @@
+//! AF_UNIX listeners via unix_diag
+//!
+//! netlink-packet-sock-diag rejects names that aren't UTF-8, which abstract names need not be,
+//! can't ask for the owner's uid, and its state filter can't select datagram sockets, which
+//! never listen.
+
+use super::{
+    drive_req, nl_hdr_flags,
+    sock::{Family, Protocol, SockInfo, UnixName},
+};
+use crate::Ino;
+use anyhow::Result;
+use netlink_packet_core::{
+    NetlinkDeserializable, NetlinkHeader, NetlinkMessage, NetlinkPayload, NetlinkSerializable,
+    NLM_F_DUMP, NLM_F_REQUEST,
+};
+use netlink_packet_sock_diag::constants::{
+    AF_UNIX, SOCK_DGRAM, SOCK_DIAG_BY_FAMILY, SOCK_SEQPACKET, SOCK_STREAM, TCP_CLOSE, TCP_LISTEN,
+    UDIAG_SHOW_NAME, UDIAG_SHOW_RQLEN, UNIX_DIAG_NAME, UNIX_DIAG_RQLEN,
+};
+use netlink_packet_utils::nla::NlasIterator;
+use netlink_sys::Socket;
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr},
+};
+
+/// Since Linux 5.3
+const UNIX_DIAG_UID: u16 = 7;
+const UDIAG_SHOW_UID: u32 = 1 << UNIX_DIAG_UID;
+/// struct unix_diag_req: family, protocol, pad, states, inode, show, cookie
+const REQUEST_LEN: usize = 24;
+/// struct unix_diag_msg: family, type, state, pad, inode, cookie
+const RESPONSE_LEN: usize = 16;
+
+enum UnixDiag {
+    Request,
+    Response(Response),
+}
+
+struct Response {
+    kind: u8,
+    state: u8,
+    inode: u32,
+    cookie: [u8; 8],
+    /// sun_path, with the leading NUL of abstract names
+    name: Option<Vec<u8>>,
+    /// For listeners, the maximum accept queue length
+    backlog: Option<u32>,
+    uid: Option<u32>,
+}
+
+impl NetlinkSerializable for UnixDiag {
+    fn message_type(&self) -> u16 {
+        SOCK_DIAG_BY_FAMILY
+    }
+
+    fn buffer_len(&self) -> usize {
+        REQUEST_LEN
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) {
+        buffer.fill(0);
+        buffer[0] = AF_UNIX;
+        // Unconnected datagram sockets are TCP_CLOSE, like unbound or connecting stream sockets
+        let states = 1u32 << TCP_LISTEN | 1 << TCP_CLOSE;
+        buffer[4..8].copy_from_slice(&states.to_ne_bytes());
+        let show = UDIAG_SHOW_NAME | UDIAG_SHOW_RQLEN | UDIAG_SHOW_UID;
+        buffer[12..16].copy_from_slice(&show.to_ne_bytes());
+    }
+}
+
+impl NetlinkDeserializable for UnixDiag {
+    type Error = io::Error;
+
+    fn deserialize(_: &NetlinkHeader, payload: &[u8]) -> Result<Self, Self::Error> {
+        let Some(msg) = payload.get(..RESPONSE_LEN) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Short unix_diag_msg of {} bytes", payload.len()),
+            ));
+        };
+        let u32_at = |bytes: &[u8], at: usize| {
+            let bytes = bytes.get(at..at + 4)?;
+            Some(u32::from_ne_bytes(
+                bytes.try_into().expect("Checked length"),
+            ))
+        };
+        let mut response = Response {
+            kind: msg[1],
+            state: msg[2],
+            inode: u32_at(msg, 4).expect("Checked length"),
+            cookie: msg[8..16].try_into().expect("Checked length"),
+            name: None,
+            backlog: None,
+            uid: None,
+        };
+        for nla in NlasIterator::new(&payload[RESPONSE_LEN..]) {
+            let nla = nla.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            match nla.kind() {
+                UNIX_DIAG_NAME => response.name = Some(nla.value().to_vec()),
+                // Pending connections and backlog for listeners, queue sizes for the others
+                UNIX_DIAG_RQLEN => response.backlog = u32_at(nla.value(), 4),
+                UNIX_DIAG_UID => response.uid = u32_at(nla.value(), 0),
+                _ => (),
+            }
+        }
+        Ok(UnixDiag::Response(response))
+    }
+}
+
+/// Listening stream and seqpacket sockets, and bound datagram sockets that aren't connected
+pub fn listeners(socket: &Socket) -> Result<HashMap<Ino, SockInfo<'static>>> {
+    let mut ret = HashMap::new();
+    let packet = NetlinkMessage::new(
+        nl_hdr_flags(NLM_F_REQUEST | NLM_F_DUMP),
+        NetlinkPayload::InnerMessage(UnixDiag::Request),
+    );
+    drive_req(packet, socket, |inner| {
+        let UnixDiag::Response(r) = inner else {
+            return;
+        };
+        let listening = match r.kind {
+            SOCK_STREAM | SOCK_SEQPACKET => r.state == TCP_LISTEN,
+            SOCK_DGRAM => r.state == TCP_CLOSE && r.name.is_some(),
+            _ => false,
+        };
+        if !listening {
+            return;
+        }
+        let ino = Ino::from(r.inode);
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        // Looked up from the owning process on kernels that don't report it
+        let uid = r.uid.unwrap_or(super::sock::UNKNOWN_UID);
+        ret.insert(
+            ino,
+            SockInfo {
+                cookie: Some(super::sock::cookie(r.cookie)),
+                backlog: r.backlog.filter(|_| r.state == TCP_LISTEN),
+                unix: r.name.as_deref().map(name),
+                ..SockInfo::new(Family::Unix, Protocol::Unix, 0, localhost, uid, ino)
+            },
+        );
+    })?;
+    Ok(ret)
+}
+
+/// Abstract names start with a NUL and may contain any bytes, so they are escaped
+fn name(sun_path: &[u8]) -> UnixName {
+    match sun_path.split_first() {
+        Some((0, name)) => UnixName::Abstract(name.escape_ascii().to_string()),
+        _ => {
+            // The length passed to bind() may include the terminating NUL
+            let end = sun_path
+                .iter()
+                .position(|&b| b == 0)
+                .unwrap_or(sun_path.len());
+            UnixName::Path(String::from_utf8_lossy(&sun_path[..end]).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{name, UnixName};
+
+    #[test]
+    fn names() {
+        let path = UnixName::Path("/run/dbus/system_bus_socket".into());
+        assert_eq!(name(b"/run/dbus/system_bus_socket\0"), path);
+        let x11 = name(b"\0/tmp/.X11-unix/X0");
+        assert_eq!(x11, UnixName::Abstract("/tmp/.X11-unix/X0".into()));
+        assert_eq!(x11.to_string(), "@/tmp/.X11-unix/X0");
+        assert_eq!(name(b"\0a\0b\n\xff").to_string(), r"@a\x00b\n\xff");
+    }
+}
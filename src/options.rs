@@ -1,30 +1,370 @@
-use crate::netlink::route::Prefix;
+use crate::exit;
+use crate::log;
+use crate::netlink;
+use crate::netlink::route::{Prefix, RouteTables, RT_TABLE_LOCAL};
 use crate::netlink::sock::Protocol;
+use crate::netlink::sock::{Extension, Family, Iface, SockInfo};
 use crate::procs;
+use crate::template::Template;
+use crate::timings;
+use crate::users;
+use crate::users::Users;
+use crate::users::UsersCache;
 use crate::IfaceInfo;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
-use std;
+use itertools::Itertools;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::env::args;
-use std::net::IpAddr;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::ops::RangeInclusive;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process::exit;
-use uzers::Users;
-use uzers::UsersCache;
-
-struct Arg(Option<char>, char, &'static [&'static str]);
-static ARGS: [Arg; 6] = [
-    Arg(None, 'a', &["addr", "address", "prefix"]),
-    Arg(Some(':'), 'p', &["port"]),
-    Arg(Some('%'), 'P', &["pid", "process-id"]),
-    Arg(Some('/'), 'c', &["cmd", "command"]),
-    Arg(None, 'u', &["user"]),
-    Arg(None, 'i', &["iface", "interface"]),
+use std::process::Command;
+use std::time::Duration;
+
+/// Abbreviation (:80), id, whether the id is also a short flag (-p 80), and long names
+struct Arg(Option<char>, char, bool, &'static [&'static str]);
+static ARGS: [Arg; 21] = [
+    Arg(None, 'a', true, &["addr", "address", "prefix"]),
+    Arg(Some(':'), 'p', true, &["port"]),
+    Arg(Some('%'), 'P', true, &["pid", "process-id"]),
+    Arg(Some('/'), 'c', true, &["cmd", "command"]),
+    Arg(None, 'u', true, &["user"]),
+    Arg(None, 'i', true, &["iface", "interface"]),
+    Arg(None, 'L', false, &["log-format"]),
+    Arg(None, 'o', true, &["output", "columns"]),
+    Arg(None, 'T', true, &["format-template", "template"]),
+    Arg(None, 'S', true, &["sort"]),
+    Arg(None, 'R', false, &["proto", "protocol"]),
+    Arg(None, 'F', false, &["from-file"]),
+    Arg(None, 'E', false, &["enrich"]),
+    Arg(None, 'X', false, &["plugin"]),
+    Arg(None, 'D', false, &["proc-root"]),
+    Arg(None, 'W', false, &["errors"]),
+    // Long only, ss and netstat users type -t for TCP
+    Arg(None, 't', false, &["route-table"]),
+    Arg(None, 'd', false, &["diff"]),
+    Arg(None, 'e', false, &["ext"]),
+    Arg(None, 'M', false, &["max-procs"]),
+    Arg(None, 'O', false, &["timeout"]),
 ];
 
+#[derive(Debug, Default)]
+pub struct Options {
+    pub filters: Filters,
+    pub format: Format,
+    pub strict: bool,
+    pub sandbox: bool,
+    pub columns: Columns,
+    pub sort: Sort,
+    /// Don't fold many addresses of one port into a summary
+    pub expand: bool,
+    /// List the interface addresses that wildcard listeners are reachable on
+    pub expand_wildcard: bool,
+    /// Output that only changes if the listening sockets do, for diffing
+    pub stable: bool,
+    /// Print the tree in batches while processes are inspected
+    pub stream: bool,
+    /// Show exe and arguments instead of guessing the name of interpreted programs
+    pub full_cmdline: bool,
+    /// Merge related processes into one application node
+    pub group_apps: bool,
+    /// Mark lines of the tree with glyphs
+    pub icons: Option<IconStyle>,
+    /// What to do with sockets that couldn't be matched to a process
+    pub orphans: Orphans,
+    /// inet_diag extensions to request instead of the default per protocol
+    pub extensions: Option<Vec<Extension>>,
+    /// How long to wait for mDNS and SSDP answers
+    pub discover: Option<Duration>,
+    /// How long to wait for UPnP gateways to answer
+    pub gateway: Option<Duration>,
+    /// Ask the cloud's metadata service for public addresses
+    pub cloud: bool,
+    /// Ask firewalld which zones open the ports
+    pub firewalld: bool,
+    /// Stop inspecting processes after this many
+    pub max_procs: Option<usize>,
+    /// Stop inspecting processes after this long, and exit shortly after
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Orphans {
+    /// As ??? groups after the processes
+    #[default]
+    Show,
+    /// Only them, for --orphans
+    Only,
+    /// Not at all, for --no-orphans
+    Hide,
+}
+
+/// Order of processes in the output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Sort {
+    pub key: SortKey,
+    pub reverse: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// By lowest port
+    #[default]
+    Port,
+    Pid,
+    Cmd,
+    /// Most sockets first
+    Sockets,
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "port" => SortKey::Port,
+            "pid" => SortKey::Pid,
+            "cmd" | "command" | "name" => SortKey::Cmd,
+            "sockets" | "count" => SortKey::Sockets,
+            _ => bail!("Unknown sort key {s:?}"),
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Tree,
+    Template(Template),
+    Json(JsonShape),
+    JsonLines(JsonShape),
+    SummaryByUser,
+    Table,
+    Siem(Siem),
+    Csv(Delimiter),
+}
+
+/// Field separator of --csv and --tsv
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    Comma,
+    Tab,
+}
+
+/// Log formats understood by SIEMs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Siem {
+    /// ArcSight Common Event Format
+    Cef,
+    /// QRadar Log Event Extended Format
+    Leef,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconStyle {
+    /// Emoji if the terminal likely can show them
+    Auto,
+    Emoji,
+    Ascii,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonShape {
+    /// Groups containing their sockets, like the tree output
+    Tree,
+    /// One object per socket, with its group embedded
+    Flat,
+}
+
+impl std::str::FromStr for JsonShape {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "tree" | "nested" => JsonShape::Tree,
+            "flat" => JsonShape::Flat,
+            _ => bail!("Unknown JSON shape {s:?}, expected tree or flat"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Cmd,
+    Pid,
+    User,
+    Port,
+    Proto,
+    Addr,
+    Iface,
+    Inode,
+    Cookie,
+    Fd,
+    Group,
+    Drops,
+    Qos,
+    Cong,
+    Backlog,
+    Conns,
+    Cgroup,
+    Netns,
+    Rss,
+    Cpu,
+    Since,
+    Pkg,
+    Image,
+    Env,
+}
+
+impl Column {
+    const ALL: [Column; 9] = [
+        Column::Cmd,
+        Column::Pid,
+        Column::User,
+        Column::Image,
+        Column::Port,
+        Column::Proto,
+        Column::Addr,
+        Column::Iface,
+        Column::Drops,
+    ];
+    const EXTENDED: [Column; 9] = [
+        Column::Group,
+        Column::Since,
+        Column::Fd,
+        Column::Inode,
+        Column::Cookie,
+        Column::Qos,
+        Column::Cong,
+        Column::Backlog,
+        Column::Conns,
+    ];
+    const RESOURCES: [Column; 2] = [Column::Rss, Column::Cpu];
+
+    /// Name as given to --output, e.g. for CSV headers
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Cmd => "cmd",
+            Column::Pid => "pid",
+            Column::User => "user",
+            Column::Port => "port",
+            Column::Proto => "proto",
+            Column::Addr => "addr",
+            Column::Iface => "iface",
+            Column::Inode => "inode",
+            Column::Cookie => "cookie",
+            Column::Fd => "fd",
+            Column::Group => "group",
+            Column::Drops => "drops",
+            Column::Qos => "qos",
+            Column::Cong => "cc",
+            Column::Backlog => "backlog",
+            Column::Conns => "conns",
+            Column::Cgroup => "cgroup",
+            Column::Netns => "netns",
+            Column::Rss => "rss",
+            Column::Cpu => "cpu",
+            Column::Since => "since",
+            Column::Pkg => "pkg",
+            Column::Image => "image",
+            Column::Env => "env",
+        }
+    }
+}
+
+impl std::str::FromStr for Column {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "cmd" | "command" | "name" => Column::Cmd,
+            "pid" => Column::Pid,
+            "user" | "uid" => Column::User,
+            "port" => Column::Port,
+            "proto" | "protocol" => Column::Proto,
+            "addr" | "address" => Column::Addr,
+            "iface" | "interface" => Column::Iface,
+            "inode" | "ino" => Column::Inode,
+            "cookie" => Column::Cookie,
+            "fd" | "fds" => Column::Fd,
+            "group" | "groups" | "gid" => Column::Group,
+            "drops" => Column::Drops,
+            "qos" | "tos" | "prio" | "priority" => Column::Qos,
+            "cc" | "congestion" => Column::Cong,
+            "backlog" => Column::Backlog,
+            "conns" | "connections" => Column::Conns,
+            "cgroup" => Column::Cgroup,
+            "netns" => Column::Netns,
+            "rss" | "mem" | "memory" => Column::Rss,
+            "cpu" => Column::Cpu,
+            "since" | "started" => Column::Since,
+            "pkg" | "package" => Column::Pkg,
+            "env" => Column::Env,
+            "image" | "container" => Column::Image,
+            _ => bail!("Unknown output column {s:?}"),
+        })
+    }
+}
+
+/// Which fields to show, in which order
+#[derive(Debug)]
+pub struct Columns(pub Vec<Column>);
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self(Column::ALL.to_vec())
+    }
+}
+
+impl Columns {
+    pub fn shows(&self, column: Column) -> bool {
+        self.0.contains(&column)
+    }
+    pub fn process_level(&self) -> bool {
+        self.shows(Column::Cmd)
+            || self.shows(Column::Pid)
+            || self.shows(Column::User)
+            || self.shows(Column::Group)
+            || self.shows(Column::Since)
+            || self.shows(Column::Pkg)
+            || self.shows(Column::Env)
+            || self.shows(Column::Image)
+            || self.resources()
+    }
+    pub fn resources(&self) -> bool {
+        self.shows(Column::Rss) || self.shows(Column::Cpu)
+    }
+    pub fn port_level(&self) -> bool {
+        self.shows(Column::Port) || self.shows(Column::Proto)
+    }
+    pub fn addr_level(&self) -> bool {
+        self.shows(Column::Addr)
+            || self.shows(Column::Iface)
+            || self.shows(Column::Qos)
+            || self.shows(Column::Cong)
+            || self.shows(Column::Backlog)
+            || self.shows(Column::Cgroup)
+            || self.shows(Column::Netns)
+            || self.per_socket_details()
+    }
+    /// Fields that differ even for sockets with the same address
+    pub fn per_socket_details(&self) -> bool {
+        self.shows(Column::Inode) || self.shows(Column::Cookie) || self.shows(Column::Fd)
+    }
+    fn add(&mut self, columns: &[Column]) {
+        for &column in columns {
+            if !self.shows(column) {
+                self.0.push(column);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Filters {
     pub port: Vec<RangeInclusive<u16>>, // :
@@ -32,12 +372,14 @@ pub struct Filters {
     pub pid: Vec<i32>,                  // %
     pub proto: HashSet<Protocol>,       // tcp/udp/...
     pub pfxs: Vec<Prefix>,              // prefix or interface name
+    pub ifaces: Vec<String>,            // interface name
     pub user: Vec<u32>,
+    pub strict_addr: bool,
 }
 
 impl Filters {
     pub fn accept_process(&self, pd: &procs::ProcDesc) -> bool {
-        self.accept_pid(pd.pid) && self.accept_cmd(pd) && self.accept_user(pd.uid)
+        pd.pids().any(|pid| self.accept_pid(pid)) && self.accept_cmd(pd) && self.accept_user(pd.uid)
     }
 
     pub fn accept_pid(&self, pid: i32) -> bool {
@@ -74,14 +416,43 @@ impl Filters {
         self.port.is_empty() || self.port.iter().any(|r| r.contains(&port))
     }
 
+    /// AF_UNIX sockets only if asked for
     pub fn accept_proto(&self, proto: Protocol) -> bool {
-        self.proto.is_empty() || self.proto.contains(&proto)
+        (self.proto.is_empty() && proto != Protocol::Unix) || self.proto.contains(&proto)
+    }
+
+    pub fn accept_sock(&self, sock: &SockInfo) -> bool {
+        self.accept_port(sock.port) && self.accept_proto(sock.protocol) && self.accept_addr(sock)
     }
 
-    pub fn accept_addr(&self, addr: IpAddr) -> bool {
-        self.pfxs.is_empty()
-            || self.pfxs.iter().any(|pfx| pfx.matches(addr))
-            || addr.is_unspecified()
+    /// Wildcard listeners are reachable through any interface, unless bound to another one,
+    /// and on the addresses of their family, unless --strict-addr asks for bound addresses only.
+    /// AF_UNIX sockets have neither address nor interface.
+    pub fn accept_addr(&self, sock: &SockInfo) -> bool {
+        if sock.family == Family::Unix {
+            return self.pfxs.is_empty() && self.ifaces.is_empty();
+        }
+        if self.pfxs.is_empty() && self.ifaces.is_empty() {
+            return true;
+        }
+        if !sock.addr.is_unspecified() {
+            return self.pfxs.iter().any(|pfx| pfx.matches(sock.addr));
+        }
+        if self.strict_addr {
+            return false;
+        }
+        let reachable = |pfx: &Prefix| match sock.family {
+            Family::V4 | Family::V6 => pfx.dst.is_ipv4() == sock.addr.is_ipv4(),
+            // Unless bound to ::ffff:0.0.0.0
+            Family::Both => pfx.dst.is_ipv4() || sock.addr.is_ipv6(),
+            Family::Unix | Family::Packet => true,
+        };
+        match sock.iface {
+            Some(Iface::Bound(name)) if !self.ifaces.is_empty() => {
+                self.ifaces.iter().any(|iface| iface == name)
+            }
+            _ => !self.ifaces.is_empty() || self.pfxs.iter().any(reachable),
+        }
     }
 
     pub(crate) fn accept_wg(&self) -> bool {
@@ -89,16 +460,19 @@ impl Filters {
     }
 }
 
-pub fn match_arg(arg: &str, args: &mut std::env::Args) -> Result<Option<(char, String)>> {
+pub fn match_arg(
+    arg: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<Option<(char, String)>> {
     for m in &ARGS {
         if let Some(abbrev) = m.0 {
             if let Some(arg) = arg.strip_prefix(abbrev).filter(|s| !s.is_empty()) {
                 return Ok(Some((m.1, arg.into())));
             }
         }
-        for (pfx, name) in
-            std::iter::once(("-", m.1.to_string().as_str())).chain(m.2.iter().map(|&s| ("--", s)))
-        {
+        let short = m.1.to_string();
+        let short = m.2.then_some(("-", short.as_str()));
+        for (pfx, name) in short.into_iter().chain(m.3.iter().map(|&s| ("--", s))) {
             let f = format!("{pfx}{name}");
             if arg == f {
                 return Ok(Some((
@@ -110,7 +484,8 @@ pub fn match_arg(arg: &str, args: &mut std::env::Args) -> Result<Option<(char, S
             if let Some(arg) = arg.strip_prefix(&format!("{f}=")) {
                 return Ok(Some((m.1, arg.into())));
             }
-            if let Some(arg) = arg.strip_prefix(&f) {
+            // Glued values only for short flags, or --protocol would be --proto with "col"
+            if let Some(arg) = arg.strip_prefix(&f).filter(|_| pfx == "-") {
                 return Ok(Some((m.1, arg.into())));
             }
         }
@@ -118,6 +493,257 @@ pub fn match_arg(arg: &str, args: &mut std::env::Args) -> Result<Option<(char, S
     Ok(None)
 }
 
+/// Comma separated table ids or names, or all
+fn route_tables(arg: &str) -> Result<RouteTables> {
+    if arg == "all" {
+        return Ok(RouteTables::All);
+    }
+    let mut names = HashMap::from([
+        ("local".to_owned(), RT_TABLE_LOCAL),
+        ("main".to_owned(), 254),
+        ("default".to_owned(), 253),
+    ]);
+    // Newer iproute2 versions only ship the defaults in /usr/share
+    let mut files = vec![
+        PathBuf::from("/usr/share/iproute2/rt_tables"),
+        PathBuf::from("/etc/iproute2/rt_tables"),
+    ];
+    for dir in [
+        "/usr/share/iproute2/rt_tables.d",
+        "/etc/iproute2/rt_tables.d",
+    ] {
+        let entries = std::fs::read_dir(dir).into_iter().flatten().flatten();
+        let conf = (entries.map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf"));
+        files.extend(conf.sorted());
+    }
+    for path in files {
+        let Ok(rt_tables) = std::fs::read_to_string(path) else {
+            continue;
+        };
+        for line in rt_tables.lines() {
+            let mut fields = line.split_whitespace();
+            if let (Some(Ok(id)), Some(name)) = (fields.next().map(str::parse), fields.next()) {
+                if !name.starts_with('#') {
+                    names.insert(name.to_owned(), id);
+                }
+            }
+        }
+    }
+    let tables = (arg.split(','))
+        .map(|table| {
+            (table.parse().ok())
+                .or_else(|| names.get(table).copied())
+                .with_context(|| format!("Unknown routing table {table:?}, expected an id, a name from rt_tables, or all"))
+        })
+        .collect::<Result<_>>()?;
+    Ok(RouteTables::Local(tables))
+}
+
+fn seconds(secs: &str) -> Result<Duration> {
+    (secs.parse::<f64>().ok())
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        .with_context(|| format!("Can't parse {secs:?} as seconds"))
+}
+
+/// A single port or a range, e.g. 8000-8999, which is part of the full argument arg
+fn port_range(part: &str, arg: &str) -> Result<RangeInclusive<u16>> {
+    let mut split = part.splitn(2, '-');
+    let start_port = split
+        .next()
+        .expect("Split iterator should always return at least one element");
+    let end_port = split.next();
+    let start_port: u16 = start_port.parse().with_context(|| {
+        format!(
+            "Parse port {}{:?} of {:?}",
+            match end_port.is_some() {
+                true => "range start ",
+                false => "",
+            },
+            start_port,
+            arg,
+        )
+    })?;
+    let end_port = match end_port {
+        Some(end_port) => end_port
+            .parse()
+            .with_context(|| format!("Parse port range end {:?} of {:?}", end_port, arg))?,
+        None => start_port,
+    };
+    Ok(match start_port <= end_port {
+        true => start_port..=end_port,
+        false => end_port..=start_port,
+    })
+}
+
+/// Interface name pattern: * and ? as in shell globs, a trailing + as in iptables
+fn iface_matches(pattern: &str, name: &str) -> bool {
+    fn glob(pattern: &[u8], name: &[u8]) -> bool {
+        match (pattern, name) {
+            ([], []) => true,
+            ([b'*', rest @ ..], _) => (0..=name.len()).any(|skip| glob(rest, &name[skip..])),
+            ([b'?', prest @ ..], [_, nrest @ ..]) => glob(prest, nrest),
+            ([p, prest @ ..], [n, nrest @ ..]) => p == n && glob(prest, nrest),
+            _ => false,
+        }
+    }
+    match pattern.strip_suffix('+') {
+        Some(prefix) => name.starts_with(prefix),
+        None => glob(pattern.as_bytes(), name.as_bytes()),
+    }
+}
+
+/// Comma separated protocol names, e.g. tcp,udp
+fn protocols(arg: &str) -> Option<Vec<Protocol>> {
+    arg.split(',').map(|p| p.parse().ok()).collect()
+}
+
+/// Options that have to be known before interfaces and sockets are queried
+#[derive(Debug, Default)]
+pub struct EarlyOptions {
+    /// Skip lookups that simple questions don't need: routes, wireguard, user names
+    pub fast: bool,
+    /// Read sockets and processes from a --json capture instead of the running system
+    pub from_file: Option<PathBuf>,
+    /// Where to save a support bundle archive, and what to put in it
+    pub capture: Option<Capture>,
+    /// Command to add fields to JSON output, which has to be started before the sandbox
+    pub enrich: Option<String>,
+    /// Lua script with hooks, which has to be read before the sandbox
+    pub plugin: Option<PathBuf>,
+    /// Where the local routes that attribute addresses to interfaces come from
+    pub route_tables: RouteTables,
+    /// --json snapshot to compare with, which has to be read before the sandbox
+    pub diff: Option<PathBuf>,
+}
+
+/// Handle arguments that have to take effect before interfaces and sockets are queried
+pub fn parse_early_args() -> Result<EarlyOptions> {
+    let (capture, args) = split_capture(std::env::args().skip(1).collect())?;
+    let mut early = EarlyOptions {
+        capture,
+        ..Default::default()
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if matches!(
+            arg.as_str(),
+            "-h" | "--help" | "help" | "-help" | "--h" | "-?"
+        ) {
+            // Like the output, a closed stdout isn't a failure
+            std::io::stdout().write_all(include_bytes!("help.txt")).ok();
+            exit(0);
+        }
+        match match_arg(&arg, &mut args)? {
+            Some(('L', arg)) => match arg.as_str() {
+                "text" => log::set_json(false),
+                "json" => log::set_json(true),
+                _ => bail!("Unknown log format {arg:?}, expected text or json"),
+            },
+            Some(('W', arg)) => match arg.as_str() {
+                "text" => exit::set_json(false),
+                "json" => exit::set_json(true),
+                _ => bail!("Unknown error format {arg:?}, expected text or json"),
+            },
+            Some(('F', path)) => early.from_file = Some(path.into()),
+            Some(('E', cmd)) => early.enrich = Some(cmd),
+            Some(('X', path)) => early.plugin = Some(path.into()),
+            Some(('t', tables)) => early.route_tables = route_tables(&tables)?,
+            Some(('d', path)) => early.diff = Some(path.into()),
+            Some(('D', path)) => procs::set_root(path.into())?,
+            Some(_) => (),
+            None => {
+                if let Some(verbosity) = verbosity(&arg) {
+                    (0..verbosity).for_each(|_| log::verbose());
+                } else if matches!(arg.as_str(), "-q" | "--quiet") {
+                    log::quiet();
+                } else if let Some(elevate) =
+                    arg.strip_prefix("--").filter(|a| ELEVATORS.contains(a))
+                {
+                    if users::get_effective_uid() != 0 {
+                        reexec_with(elevate)?;
+                    }
+                } else if arg == "--timings" {
+                    timings::enable();
+                } else if arg == "--fast" {
+                    early.fast = true;
+                } else if arg == "--debug-netlink" {
+                    netlink::debug_to(Box::new(std::io::stderr()));
+                } else if let Some(path) = arg.strip_prefix("--debug-netlink=") {
+                    let file = File::create(path)
+                        .with_context(|| format!("Create netlink debug dump file {path:?}"))?;
+                    netlink::debug_to(Box::new(BufWriter::new(file)));
+                }
+            }
+        }
+    }
+    Ok(early)
+}
+
+/// Subcommand that lists like without it, and saves a support bundle
+const CAPTURE: &str = "capture";
+
+/// Flag of lls capture to include what may hold secrets
+const KEEP_CMDLINES: &str = "--keep-cmdlines";
+
+#[derive(Debug, PartialEq)]
+pub struct Capture {
+    pub path: PathBuf,
+    /// Include the processes' command lines and status, and the arguments of lls
+    pub cmdlines: bool,
+}
+
+/// Split `capture <file> [--keep-cmdlines]` off the arguments after arg 0
+fn split_capture(mut args: Vec<String>) -> Result<(Option<Capture>, Vec<String>)> {
+    if args.first().map(String::as_str) != Some(CAPTURE) {
+        return Ok((None, args));
+    }
+    args.remove(0);
+    let path = match args.first() {
+        Some(path) if !path.starts_with('-') => args.remove(0).into(),
+        _ => bail!("lls capture needs the file to save the bundle to, as in lls capture <file>"),
+    };
+    let before = args.len();
+    args.retain(|arg| arg != KEEP_CMDLINES);
+    let cmdlines = args.len() < before;
+    Ok((Some(Capture { path, cmdlines }), args))
+}
+
+const ELEVATORS: &[&str] = &["sudo", "pkexec"];
+
+/// Replace ourselves by `elevate lls args…`, minus the elevation flag
+fn reexec_with(elevate: &str) -> Result<()> {
+    let exe = std::env::current_exe().context("Find own executable")?;
+    let args = std::env::args().skip(1).filter(|arg| {
+        !arg.strip_prefix("--")
+            .is_some_and(|a| ELEVATORS.contains(&a))
+    });
+    let err = Command::new(elevate).arg(exe).args(args).exec();
+    Err(err).with_context(|| format!("Execute {elevate}"))
+}
+
+fn early_flag(arg: &str) -> bool {
+    verbosity(arg).is_some()
+        || arg
+            .strip_prefix("--")
+            .is_some_and(|a| ELEVATORS.contains(&a))
+        || matches!(
+            arg,
+            "-q" | "--quiet" | "--debug-netlink" | "--timings" | "--fast"
+        )
+        || arg.starts_with("--debug-netlink=")
+}
+
+fn verbosity(arg: &str) -> Option<usize> {
+    match arg {
+        "--verbose" => Some(1),
+        _ => arg
+            .strip_prefix('-')
+            .filter(|vs| !vs.is_empty() && vs.chars().all(|v| v == 'v'))
+            .map(str::len),
+    }
+}
+
 pub fn parse_args(
     IfaceInfo {
         id2name: ifaces,
@@ -125,23 +751,39 @@ pub fn parse_args(
         ..
     }: &IfaceInfo,
     users: &UsersCache,
-) -> Result<Filters> {
+) -> Result<Options> {
+    let ifaces_by_id = ifaces;
     let ifaces = ifaces
         .iter()
         .map(|(&id, name)| (name, id))
         .collect::<HashMap<_, _>>();
-    for arg in args() {
-        if matches!(
-            arg.as_str(),
-            "-h" | "--help" | "help" | "-help" | "--h" | "-?"
-        ) {
-            print!("{}", include_str!("help.txt"));
-            exit(0);
-        }
-    }
     let mut filters: Filters = Filters::default();
-    let mut args = args();
-    args.next().expect("Arg 0 missing");
+    let mut format = Format::default();
+    let mut strict = false;
+    let mut sandbox = true;
+    let mut columns = Columns::default();
+    let mut extended = false;
+    let mut resources = false;
+    let mut sort = Sort::default();
+    let mut expand = false;
+    let mut expand_wildcard = false;
+    let mut stable = false;
+    let mut stream = false;
+    let mut full_cmdline = false;
+    let mut group_apps = false;
+    let mut icons = None;
+    let mut orphans = Orphans::default();
+    let mut extensions = None;
+    let mut flat = false;
+    let mut discover = None;
+    let mut gateway = None;
+    let mut cloud = false;
+    let mut firewalld = false;
+    let mut max_procs = None;
+    let mut timeout = None;
+    let mut args = (split_capture(std::env::args().skip(1).collect())?.1)
+        .into_iter()
+        .peekable();
     while let Some(arg) = args.next() {
         let normal_match = match_arg(&arg, &mut args)?;
         match normal_match {
@@ -155,7 +797,7 @@ pub fn parse_args(
                     filters.user.push(user.uid())
                 } else if let Ok(uid) = arg.parse() {
                     if users.get_user_by_uid(uid).is_none() {
-                        eprintln!("WARNING: Unknown user id: {uid}");
+                        log::warn!("Unknown user id: {uid}");
                     }
                     filters.user.push(uid);
                 } else {
@@ -163,65 +805,167 @@ pub fn parse_args(
                 }
             }
             Some(('p', arg)) => {
-                let mut split = arg.splitn(2, '-');
-                let start_port = split
-                    .next()
-                    .expect("Split iterator should always return at least one element");
-                let end_port = split.next();
-                let start_port: u16 = start_port.parse().with_context(|| {
-                    format!(
-                        "Parse port {}{:?} of range {:?}",
-                        match end_port.is_some() {
-                            true => "range start ",
-                            false => "",
-                        },
-                        start_port,
-                        &arg,
-                    )
-                })?;
-                let end_port = match end_port {
-                    Some(end_port) => end_port.parse().with_context(|| {
-                        format!("Parse port range end {:?} of range {:?}", end_port, &arg)
-                    })?,
-                    None => start_port,
-                };
-
-                filters.port.push(match start_port <= end_port {
-                    true => start_port..=end_port,
-                    false => end_port..=start_port,
-                });
+                for part in arg.split(',') {
+                    filters.port.push(port_range(part, &arg)?);
+                }
             }
             Some(('i', arg)) => {
-                if let Some(&ifaceid) = ifaces.get(&arg) {
-                    for pfx in local_routes.for_iface(ifaceid) {
-                        filters.pfxs.push(pfx);
-                    }
-                    continue;
-                } else {
+                let selected = match arg.parse::<u32>() {
+                    Ok(index) => (ifaces_by_id.get_key_value(&index).into_iter())
+                        .map(|(&id, name)| (name, id))
+                        .collect::<Vec<_>>(),
+                    Err(_) => (ifaces.iter())
+                        .filter(|(name, _)| iface_matches(&arg, name))
+                        .map(|(&name, &id)| (name, id))
+                        .sorted()
+                        .collect(),
+                };
+                if selected.is_empty() {
                     bail!("Unknown interface {arg}");
                 }
+                for (name, ifaceid) in selected {
+                    filters.pfxs.extend(local_routes.for_iface(ifaceid));
+                    filters.ifaces.push(name.clone());
+                }
             }
             Some(('a', arg)) => filters.pfxs.push(
                 arg.parse()
                     .with_context(|| format!("Can't parse {arg:?} as prefix"))?,
             ),
+            Some(('e', arg)) => {
+                extensions = Some(match arg.as_str() {
+                    "none" => Vec::new(),
+                    "all" => Extension::ALL.to_vec(),
+                    _ => arg.split(',').map(str::parse).collect::<Result<_>>()?,
+                })
+            }
+            Some(('M', arg)) => {
+                max_procs = Some(
+                    arg.parse()
+                        .with_context(|| format!("Invalid --max-procs {arg:?}"))?,
+                )
+            }
+            Some(('O', arg)) => timeout = Some(seconds(&arg)?),
+            Some(('L' | 'W' | 'F' | 'E' | 'X' | 't' | 'd' | 'D', _)) => (), // parse_early_args
+            Some(('T', arg)) => format = Format::Template(arg.parse()?),
+            Some(('S', arg)) => sort.key = arg.parse()?,
+            Some(('R', arg)) => filters
+                .proto
+                .extend(protocols(&arg).with_context(|| format!("Unknown protocol in {arg:?}"))?),
+            Some(('o', arg)) => {
+                columns = Columns(
+                    arg.split(',')
+                        .filter(|c| !c.is_empty())
+                        .map(str::parse)
+                        .collect::<Result<_>>()?,
+                )
+            }
             Some((c, _)) => {
                 unreachable!("Argument parser bug - {c}");
             }
             None => {
                 if matches!(arg.as_str(), "-s" | "--self") {
-                    let uids = [uzers::get_current_uid(), uzers::get_effective_uid()];
+                    let uids = [users::get_current_uid(), users::get_effective_uid()];
                     filters.user.extend_from_slice(&uids);
-                } else if let Some(Ok(proto)) = arg.strip_prefix("--").map(str::parse) {
-                    filters.proto.insert(proto);
-                } else if let Ok(proto) = arg.parse() {
-                    filters.proto.insert(proto);
+                } else if early_flag(&arg) {
+                    // parse_early_args
+                } else if matches!(arg.as_str(), "-x" | "--extended") {
+                    extended = true;
+                } else if matches!(arg.as_str(), "-r" | "--reverse") {
+                    sort.reverse = true;
+                } else if matches!(arg.as_str(), "--full-cmdline" | "--no-heuristics") {
+                    full_cmdline = true;
+                } else if arg == "--icons" {
+                    icons = Some(IconStyle::Auto);
+                } else if let Some(style) = arg.strip_prefix("--icons=") {
+                    icons = Some(match style {
+                        "auto" => IconStyle::Auto,
+                        "emoji" => IconStyle::Emoji,
+                        "ascii" => IconStyle::Ascii,
+                        _ => bail!("Unknown icon style {style:?}, expected auto, emoji, or ascii"),
+                    });
+                } else if arg == "--orphans" {
+                    orphans = Orphans::Only;
+                } else if arg == "--no-orphans" {
+                    orphans = Orphans::Hide;
+                } else if arg == "--group-apps" {
+                    group_apps = true;
+                } else if arg == "--stable" {
+                    stable = true;
+                } else if arg == "--stream" {
+                    stream = true;
+                } else if arg == "--expand" {
+                    expand = true;
+                } else if arg == "--expand-wildcard" {
+                    expand = true;
+                    expand_wildcard = true;
+                } else if arg == "--resources" {
+                    resources = true;
+                } else if arg == "--pkg" {
+                    columns.add(&[Column::Pkg]);
+                } else if arg == "--env-hints" {
+                    columns.add(&[Column::Env]);
+                } else if arg == "--strict" {
+                    strict = true;
+                } else if arg == "--strict-addr" {
+                    filters.strict_addr = true;
+                } else if arg == "--no-sandbox" {
+                    sandbox = false;
+                } else if arg == "--json" {
+                    let shape = args.next_if(|shape| shape.parse::<JsonShape>().is_ok());
+                    format = Format::Json(shape.map_or(Ok(JsonShape::Tree), |s| s.parse())?);
+                } else if let Some(shape) = arg.strip_prefix("--json=") {
+                    format = Format::Json(shape.parse()?);
+                } else if matches!(arg.as_str(), "--ndjson" | "--jsonl") {
+                    format = Format::JsonLines(JsonShape::Tree);
+                } else if arg == "--discover" {
+                    discover = Some(Duration::from_secs(1));
+                } else if let Some(secs) = arg.strip_prefix("--discover=") {
+                    discover = Some(seconds(secs)?);
+                } else if arg == "--firewalld" {
+                    firewalld = true;
+                } else if arg == "--cloud" {
+                    cloud = true;
+                } else if arg == "--gateway" {
+                    gateway = Some(Duration::from_secs(1));
+                } else if let Some(secs) = arg.strip_prefix("--gateway=") {
+                    gateway = Some(seconds(secs)?);
+                } else if arg == "--flat" {
+                    flat = true;
+                } else if arg == "--summary-by-user" {
+                    format = Format::SummaryByUser;
+                } else if arg == "--table" {
+                    format = Format::Table;
+                } else if arg == "--csv" {
+                    format = Format::Csv(Delimiter::Comma);
+                } else if arg == "--tsv" {
+                    format = Format::Csv(Delimiter::Tab);
+                } else if arg == "--cef" {
+                    format = Format::Siem(Siem::Cef);
+                } else if arg == "--leef" {
+                    format = Format::Siem(Siem::Leef);
+                } else if let Some(protos) = protocols(arg.strip_prefix("--").unwrap_or(&arg)) {
+                    filters.proto.extend(protos);
+                } else if let Ok(ports) = (arg.split(','))
+                    .map(|p| port_range(p, &arg))
+                    .collect::<Result<Vec<_>>>()
+                {
+                    if let Some(user) =
+                        (arg.parse().ok()).and_then(|uid| users.get_user_by_uid(uid))
+                    {
+                        log::warn!(
+                            "{arg} is taken as a port, for the user {} use -u {arg}",
+                            user.name().to_string_lossy()
+                        );
+                    }
+                    filters.port.extend(ports);
                 } else if let Ok(prefix) = arg.parse() {
                     filters.pfxs.push(prefix);
                 } else if let Some(&ifaceid) = ifaces.get(&arg) {
                     for pfx in local_routes.for_iface(ifaceid) {
                         filters.pfxs.push(pfx);
                     }
+                    filters.ifaces.push(arg);
                 } else if let Some(user) = users.get_user_by_name(&arg) {
                     filters.user.push(user.uid())
                 } else {
@@ -230,5 +974,124 @@ pub fn parse_args(
             }
         }
     }
-    Ok(filters)
+    if flat {
+        format = match format {
+            Format::Json(_) => Format::Json(JsonShape::Flat),
+            Format::JsonLines(_) => Format::JsonLines(JsonShape::Flat),
+            _ => bail!("--flat only works with --json and --ndjson"),
+        };
+    }
+    if stream && !matches!(format, Format::Tree) {
+        bail!("--stream only works with the tree output, --ndjson streams by itself");
+    }
+    #[cfg(not(feature = "json"))]
+    if matches!(format, Format::Json(_) | Format::JsonLines(_)) {
+        bail!("lls was built without JSON support for --json and --ndjson");
+    }
+    if extended {
+        columns.add(&Column::EXTENDED);
+    }
+    if resources {
+        columns.add(&Column::RESOURCES);
+    }
+    if orphans == Orphans::Only {
+        columns.add(&[Column::User, Column::Cgroup]);
+    }
+    Ok(Options {
+        filters,
+        format,
+        strict,
+        sandbox,
+        columns,
+        sort,
+        expand,
+        expand_wildcard,
+        stable,
+        stream,
+        full_cmdline,
+        group_apps,
+        icons,
+        orphans,
+        extensions,
+        discover,
+        gateway,
+        cloud,
+        firewalld,
+        max_procs,
+        timeout,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{iface_matches, match_arg, port_range, split_capture, Capture};
+
+    #[test]
+    fn port_lists() {
+        let arg = "22,80,443,8999-8000";
+        let ranges = arg
+            .split(',')
+            .map(|p| port_range(p, arg).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [22..=22, 80..=80, 443..=443, 8000..=8999]);
+        assert!(port_range("", "22,,80").is_err());
+    }
+
+    #[test]
+    fn capture_path() {
+        let args = |args: &[&str]| args.iter().map(|&a| a.to_owned()).collect::<Vec<_>>();
+        let (capture, rest) = split_capture(args(&["capture", "b.tar.zst", "-o", "pid"])).unwrap();
+        let path = "b.tar.zst".into();
+        assert_eq!(
+            capture,
+            Some(Capture {
+                path,
+                cmdlines: false
+            })
+        );
+        assert_eq!(rest, ["-o", "pid"]);
+        let (capture, rest) =
+            split_capture(args(&["capture", "b", "-oport", "--keep-cmdlines"])).unwrap();
+        assert!(capture.unwrap().cmdlines);
+        assert_eq!(rest, ["-oport"]);
+        assert!(split_capture(args(&["capture", "-o", "b"])).is_err());
+        assert!(split_capture(args(&["capture"])).is_err());
+        let (capture, rest) = split_capture(args(&[":80", "capture"])).unwrap();
+        assert_eq!((capture, rest.len()), (None, 2));
+    }
+
+    #[test]
+    fn iface_patterns() {
+        assert!(iface_matches("eth0", "eth0"));
+        assert!(!iface_matches("eth0", "eth01"));
+        assert!(iface_matches("veth*", "veth4a2f"));
+        assert!(iface_matches("veth*", "veth"));
+        assert!(iface_matches("*.100", "eno1.100"));
+        assert!(iface_matches("wg?", "wg0"));
+        assert!(!iface_matches("wg?", "wg10"));
+        assert!(iface_matches("wg+", "wg10"));
+        assert!(!iface_matches("wg+", "awg0"));
+    }
+
+    #[test]
+    fn long_names() {
+        let parse = |arg: &str, rest: &[&str]| {
+            let mut rest = rest.iter().map(|s| s.to_string());
+            match_arg(arg, &mut rest).unwrap()
+        };
+        assert_eq!(parse("--protocol", &["tcp"]), Some(('R', "tcp".into())));
+        assert_eq!(parse("--protocol=tcp", &[]), Some(('R', "tcp".into())));
+        assert_eq!(parse("--proto=udp", &[]), Some(('R', "udp".into())));
+        assert_eq!(parse("-p80", &[]), Some(('p', "80".into())));
+        assert_eq!(parse("--port80", &[]), None);
+        assert_eq!(parse("-Ecat", &[]), None);
+        assert_eq!(parse("-L", &["json"]), None);
+        assert_eq!(parse("-t", &["all"]), None);
+        assert_eq!(parse("-o", &["port"]), Some(('o', "port".into())));
+        assert_eq!(parse("--enrich", &["cat"]), Some(('E', "cat".into())));
+        assert_eq!(parse("--ext=tos", &[]), Some(('e', "tos".into())));
+        assert_eq!(parse("--extended", &[]), None);
+        assert_eq!(parse("--timeout", &["2.5"]), Some(('O', "2.5".into())));
+        assert_eq!(parse("--timeouts", &[]), None);
+    }
 }
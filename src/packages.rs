@@ -0,0 +1,266 @@
+//! Find the distribution package owning an executable, for --pkg
+//!
+//! The package databases are read directly, the sandbox doesn't allow running dpkg, rpm or apk.
+//! They are read once per run, however many batches of processes ask.
+
+use crate::{log, procs};
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+/// Relative to the root of the system, see [procs::sysroot]
+const DPKG: &str = "var/lib/dpkg";
+const APK: &str = "lib/apk/db/installed";
+/// Newer distributions keep it in /usr, with a symlink from /var/lib/rpm
+#[cfg(feature = "rpm")]
+const RPM: &[&str] = &[
+    "usr/lib/sysimage/rpm/rpmdb.sqlite",
+    "var/lib/rpm/rpmdb.sqlite",
+];
+
+/// "Package version" for each file of each installed package
+type Index = HashMap<PathBuf, Arc<str>>;
+
+static INDEX: OnceLock<Index> = OnceLock::new();
+
+/// Package name and version for each of the given executables that belongs to a package
+pub fn owners<'a>(exes: impl IntoIterator<Item = &'a Path>) -> HashMap<PathBuf, String> {
+    let mut exes = exes.into_iter().peekable();
+    if exes.peek().is_none() {
+        return HashMap::new();
+    }
+    let index = INDEX.get_or_init(|| {
+        index().unwrap_or_else(|e| {
+            log::warn!("{e:#}");
+            Index::new()
+        })
+    });
+    // On merged-/usr systems, the package may list /bin/x, but the exe link points to /usr/bin/x
+    exes.filter_map(|exe| {
+        let pkg = aliases(exe).iter().find_map(|alias| index.get(alias))?;
+        Some((exe.to_owned(), pkg.to_string()))
+    })
+    .collect()
+}
+
+fn index() -> Result<Index> {
+    let sysroot = procs::sysroot();
+    let (dpkg_dir, apk_db) = (sysroot.join(DPKG), sysroot.join(APK));
+    if dpkg_dir.exists() {
+        return dpkg(&dpkg_dir);
+    }
+    if apk_db.exists() {
+        return apk(&apk_db);
+    }
+    #[cfg(feature = "rpm")]
+    if let Some(rpm_db) = RPM.iter().map(|db| sysroot.join(db)).find(|db| db.exists()) {
+        return rpm(&rpm_db);
+    }
+    log::info!("--pkg: No dpkg, rpm or apk database found");
+    Ok(Index::new())
+}
+
+fn aliases(exe: &Path) -> Vec<PathBuf> {
+    let mut ret = vec![exe.to_owned()];
+    if let Ok(rest) = exe.strip_prefix("/usr") {
+        if ["bin", "sbin", "lib", "lib64"]
+            .iter()
+            .any(|d| rest.starts_with(d))
+        {
+            ret.push(Path::new("/").join(rest));
+        }
+    }
+    ret
+}
+
+fn dpkg(dir: &Path) -> Result<Index> {
+    let status = dir.join("status");
+    let status = read_to_string(&status).with_context(|| format!("Read {status:?}"))?;
+    let mut versions = HashMap::new();
+    for stanza in status.split("\n\n") {
+        let field = |name: &str| {
+            stanza
+                .lines()
+                .find_map(|l| l.strip_prefix(name)?.strip_prefix(": "))
+        };
+        if let (Some(pkg), Some(version)) = (field("Package"), field("Version")) {
+            versions.insert(pkg, version);
+        }
+    }
+    let mut index = Index::new();
+    let info = dir.join("info");
+    for entry in read_dir(&info).with_context(|| format!("Read {info:?}"))? {
+        let path = entry?.path();
+        if path.extension() != Some("list".as_ref()) {
+            continue;
+        }
+        let Ok(list) = read_to_string(&path) else {
+            continue;
+        };
+        let pkg = path.file_stem().unwrap_or_default().to_string_lossy();
+        // Multiarch packages are listed as name:arch
+        let pkg = pkg.split(':').next().unwrap_or_default();
+        let pkg: Arc<str> = match versions.get(pkg) {
+            Some(version) => format!("{pkg} {version}").into(),
+            None => pkg.into(),
+        };
+        for line in list.lines() {
+            index.insert(line.into(), pkg.clone());
+        }
+    }
+    Ok(index)
+}
+
+fn apk(db: &Path) -> Result<Index> {
+    let installed = read_to_string(db).with_context(|| format!("Read {db:?}"))?;
+    let mut index = Index::new();
+    for stanza in installed.split("\n\n") {
+        let (mut pkg, mut version, mut dir) = ("", "", Path::new("/").to_owned());
+        let mut files = Vec::new();
+        for line in stanza.lines() {
+            match line.split_once(':') {
+                Some(("P", p)) => pkg = p,
+                Some(("V", v)) => version = v,
+                Some(("F", d)) => dir = Path::new("/").join(d),
+                Some(("R", file)) => files.push(dir.join(file)),
+                _ => (),
+            }
+        }
+        let pkg: Arc<str> = format!("{pkg} {version}").into();
+        index.extend(files.into_iter().map(|file| (file, pkg.clone())));
+    }
+    Ok(index)
+}
+
+/// Read the headers out of the SQLite backend of rpm 4.16 and later
+///
+/// The older Berkeley DB format isn't supported.
+#[cfg(feature = "rpm")]
+fn rpm(db: &Path) -> Result<Index> {
+    use rusqlite::{Connection, OpenFlags};
+    // Immutable: the database may be in WAL mode, and nothing may be written next to it
+    let uri = format!(
+        "file:{}?immutable=1",
+        db.to_string_lossy()
+            .replace('%', "%25")
+            .replace('?', "%3f")
+            .replace('#', "%23")
+    );
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI;
+    let conn = Connection::open_with_flags(uri, flags).with_context(|| format!("Open {db:?}"))?;
+    let mut query =
+        (conn.prepare("SELECT blob FROM Packages")).with_context(|| format!("Query {db:?}"))?;
+    let mut rows = query.query([])?;
+    let mut index = Index::new();
+    while let Some(row) = rows.next()? {
+        let blob = row.get_ref(0)?.as_blob()?;
+        if let Some((pkg, files)) = rpm_header(blob) {
+            let pkg: Arc<str> = pkg.into();
+            index.extend(files.into_iter().map(|file| (file, pkg.clone())));
+        }
+    }
+    Ok(index)
+}
+
+/// Name, version-release and file list out of an rpm header blob
+///
+/// The blob is the index entry count and data size, the index entries (tag, type, offset,
+/// count), and the data they point into, all big endian.
+#[cfg(feature = "rpm")]
+fn rpm_header(blob: &[u8]) -> Option<(String, Vec<PathBuf>)> {
+    const NAME: u32 = 1000;
+    const VERSION: u32 = 1001;
+    const RELEASE: u32 = 1002;
+    const DIRINDEXES: u32 = 1116;
+    const BASENAMES: u32 = 1117;
+    const DIRNAMES: u32 = 1118;
+    let u32_at =
+        |b: &[u8], at: usize| Some(u32::from_be_bytes(b.get(at..at + 4)?.try_into().ok()?));
+    let entries = u32_at(blob, 0)? as usize;
+    let data = blob.get(8 + entries.checked_mul(16)?..)?;
+    let find = |tag: u32| {
+        (0..entries).find_map(|i| {
+            let entry = 8 + i * 16;
+            (u32_at(blob, entry)? == tag).then(|| {
+                Some((
+                    u32_at(blob, entry + 8)? as usize,
+                    u32_at(blob, entry + 12)? as usize,
+                ))
+            })?
+        })
+    };
+    let strings = |tag: u32| -> Option<Vec<&str>> {
+        let (offset, count) = find(tag)?;
+        let mut strings = data.get(offset..)?.split(|&b| b == 0);
+        (0..count)
+            .map(|_| std::str::from_utf8(strings.next()?).ok())
+            .collect()
+    };
+    let string = |tag: u32| strings(tag).and_then(|s| s.first().copied());
+    let pkg = format!(
+        "{} {}-{}",
+        string(NAME)?,
+        string(VERSION)?,
+        string(RELEASE)?
+    );
+    // Packages without files have none of these
+    let files = match (strings(DIRNAMES), strings(BASENAMES), find(DIRINDEXES)) {
+        (Some(dirs), Some(bases), Some((offset, _))) => (bases.iter().enumerate())
+            .map(|(i, base)| {
+                let dir = dirs.get(u32_at(data, offset + i * 4)? as usize)?;
+                Some(Path::new(dir).join(base))
+            })
+            .collect::<Option<_>>()?,
+        _ => Vec::new(),
+    };
+    Some((pkg, files))
+}
+
+#[cfg(all(test, feature = "rpm"))]
+mod test {
+    use super::rpm_header;
+    use std::path::PathBuf;
+
+    #[test]
+    fn rpm_blob() {
+        // Tag, type, and data of each index entry
+        let entries: &[(u32, u32, Vec<u8>, u32)] = &[
+            (1000, 6, b"openssh-server\0".to_vec(), 1),
+            (1001, 6, b"9.6p1\0".to_vec(), 1),
+            (1002, 6, b"3.fc40\0".to_vec(), 1),
+            (1116, 4, [0u32, 1, 0].map(u32::to_be_bytes).concat(), 3),
+            (1117, 8, b"sshd\0sshd_config\0sftp-server\0".to_vec(), 3),
+            (1118, 8, b"/usr/sbin/\0/etc/ssh/\0".to_vec(), 2),
+        ];
+        let (mut index, mut data) = (Vec::new(), Vec::new());
+        for (tag, kind, bytes, count) in entries {
+            for field in [*tag, *kind, data.len() as u32, *count] {
+                index.extend(field.to_be_bytes());
+            }
+            data.extend(bytes);
+        }
+        let blob = [
+            &(entries.len() as u32).to_be_bytes()[..],
+            &(data.len() as u32).to_be_bytes(),
+            &index,
+            &data,
+        ]
+        .concat();
+        let (pkg, files) = rpm_header(&blob).unwrap();
+        assert_eq!(pkg, "openssh-server 9.6p1-3.fc40");
+        assert_eq!(
+            files,
+            [
+                "/usr/sbin/sshd",
+                "/etc/ssh/sshd_config",
+                "/usr/sbin/sftp-server"
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(rpm_header(&blob[..20]), None);
+    }
+}
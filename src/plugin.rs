@@ -0,0 +1,153 @@
+//! Lua hooks for custom filtering, naming and output, for --plugin
+//!
+//! The script may define these global functions:
+//!  * `socket(s)`: Called for each collected socket, with the fields of --json. Return false to hide it.
+//!  * `process(p)`: Called for each process, with the fields of --json. Return a string to rename it.
+//!  * `render(label, depth)`: Called for each line of the tree. Return a string to replace the label.
+//!
+//! Only the table, string, math and utf8 libraries are available, and the base library without
+//! dofile, loadfile and load, so scripts can't touch files or run programs. print writes to
+//! stderr, so it doesn't mix into the output.
+
+use crate::{
+    json,
+    netlink::sock::SockInfo,
+    procs::ProcDesc,
+    termtree::{Tree, CELL},
+};
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use mlua::{Function, Lua, LuaOptions, StdLib, Value as LuaValue, Variadic};
+use serde_json::Value;
+use std::path::Path;
+
+pub struct Plugin {
+    lua: Lua,
+    name: String,
+}
+
+/// A Lua state without access to files, which mlua's base library always has
+fn sandboxed() -> Result<Lua> {
+    let libs = StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8;
+    let lua = Lua::new_with(libs, LuaOptions::default()).context("Start Lua")?;
+    for name in ["dofile", "loadfile", "load"] {
+        lua.globals().set(name, mlua::Nil).context("Start Lua")?;
+    }
+    // Like the original, but to stderr, stdout is lls' output
+    let print = lua
+        .create_function(|lua, args: Variadic<LuaValue>| {
+            let tostring: Function = lua.globals().get("tostring")?;
+            let strings = (args.into_iter())
+                .map(|arg| tostring.call::<_, String>(arg))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            eprintln!("{}", strings.join("\t"));
+            Ok(())
+        })
+        .context("Start Lua")?;
+    lua.globals().set("print", print).context("Start Lua")?;
+    Ok(lua)
+}
+
+impl Plugin {
+    /// Run the script, before the sandbox makes it unreadable
+    pub fn load(path: &Path) -> Result<Plugin> {
+        let script =
+            std::fs::read_to_string(path).with_context(|| format!("Read plugin {path:?}"))?;
+        let name = path.display().to_string();
+        let lua = sandboxed()?;
+        lua.load(&script)
+            .set_name(format!("@{name}"))
+            .exec()
+            .with_context(|| format!("Run plugin {name}"))?;
+        Ok(Plugin { lua, name })
+    }
+
+    fn hook(&self, name: &str) -> Result<Option<Function<'_>>> {
+        (self.lua.globals().get(name))
+            .with_context(|| format!("Plugin {}: {name} isn't a function", self.name))
+    }
+
+    /// Whether the socket hook keeps the socket
+    pub fn socket(&self, socket: &SockInfo) -> Result<bool> {
+        let Some(hook) = self.hook("socket")? else {
+            return Ok(true);
+        };
+        let ret = hook
+            .call::<_, LuaValue>(to_lua(&self.lua, &json::socket(socket))?)
+            .with_context(|| format!("Plugin {}: socket hook", self.name))?;
+        Ok(!matches!(ret, LuaValue::Boolean(false)))
+    }
+
+    /// Rename the process, if the process hook wants to
+    pub fn process(&self, pd: &mut ProcDesc) -> Result<()> {
+        let Some(hook) = self.hook("process")? else {
+            return Ok(());
+        };
+        let name = hook
+            .call::<_, Option<String>>(to_lua(&self.lua, &json::process(pd))?)
+            .with_context(|| format!("Plugin {}: process hook", self.name))?;
+        if name.is_some() {
+            pd.name = name;
+        }
+        Ok(())
+    }
+
+    /// Let the render hook rewrite all lines of the tree
+    pub fn render(&self, tree: &mut Tree) -> Result<()> {
+        let Some(hook) = self.hook("render")? else {
+            return Ok(());
+        };
+        tree.try_for_each_label(0, &mut |label, depth| {
+            // Replacements lose the alignment with sibling lines
+            let plain = label.split(CELL).filter(|c| !c.is_empty()).join(" ");
+            let replacement = hook
+                .call::<_, Option<String>>((plain.as_str(), depth))
+                .with_context(|| format!("Plugin {}: render hook", self.name))?;
+            if let Some(replacement) = replacement.filter(|r| *r != plain) {
+                *label = replacement;
+            }
+            Ok(())
+        })
+    }
+}
+
+fn to_lua<'lua>(lua: &'lua Lua, value: &Value) -> Result<LuaValue<'lua>> {
+    Ok(match value {
+        Value::Null => LuaValue::Nil,
+        Value::Bool(b) => LuaValue::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => LuaValue::Integer(i),
+            None => LuaValue::Number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => LuaValue::String(lua.create_string(s)?),
+        Value::Array(items) => LuaValue::Table(
+            lua.create_sequence_from(
+                (items.iter())
+                    .map(|v| to_lua(lua, v))
+                    .collect::<Result<Vec<_>>>()?,
+            )?,
+        ),
+        Value::Object(fields) => {
+            let table = lua.create_table()?;
+            for (k, v) in fields {
+                table.set(k.as_str(), to_lua(lua, v)?)?;
+            }
+            LuaValue::Table(table)
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use mlua::Value;
+
+    #[test]
+    fn no_file_access() {
+        let lua = super::sandboxed().unwrap();
+        for name in ["dofile", "loadfile", "load", "io", "os", "require"] {
+            assert!(matches!(lua.globals().get(name), Ok(Value::Nil)), "{name}");
+        }
+        assert!(lua.load("dofile('/etc/passwd')").exec().is_err());
+        assert!(lua.load("print('to stderr', 1, nil)").exec().is_ok());
+    }
+}
@@ -1,30 +1,132 @@
 use super::netlink::sock::SockInfo;
+use crate::users::{Groups, Users, UsersCache};
 use crate::Ino;
+use crate::{inetd, intern::Interner};
 use anyhow::{Context, Result};
-use procfs::process::Process;
+use chrono::{DateTime, Local};
+use procfs::{process::Process, FromRead, WithCurrentSystemInfo};
 use std::{
-    collections::HashMap, ffi::OsString, ops::ControlFlow, os::unix::prelude::OsStringExt,
-    path::PathBuf,
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+    fs::OpenOptions,
+    io::Read,
+    net::SocketAddr,
+    ops::ControlFlow,
+    os::unix::{
+        fs::{MetadataExt, OpenOptionsExt},
+        prelude::OsStringExt,
+    },
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::OnceLock,
 };
-use uzers::{Users, UsersCache};
 
 pub type Pid = i32;
 
+/// Ports below this need CAP_NET_BIND_SERVICE to bind, unless changed by sysctl
+pub const PRIVILEGED_PORTS: u16 = 1024;
+static UNPRIVILEGED_PORT_START: OnceLock<u16> = OnceLock::new();
+static SOMAXCONN: OnceLock<Option<u32>> = OnceLock::new();
+static CGROUPS: OnceLock<HashMap<u64, String>> = OnceLock::new();
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+static OWN_NETNS: OnceLock<Option<u64>> = OnceLock::new();
+const CAP_NET_BIND_SERVICE: u32 = 10;
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct ProcDesc<'a> {
     pub pid: Pid,
-    pub user: String,
+    pub user: Rc<str>,
     pub uid: u32,
+    /// Uid and user name inside a foreign user namespace, e.g. of a rootless container
+    pub ns_user: Option<(u32, Option<String>)>,
+    /// Real uid and user name, if they differ from the effective ones, e.g. of a setuid
+    /// program that hasn't dropped its privileges yet
+    pub real_user: Option<(u32, Rc<str>)>,
+    pub gid: Option<u32>,
+    pub group: Option<Rc<str>>,
+    /// Supplementary groups
+    pub groups: Vec<Rc<str>>,
     pub name: Option<String>,
     pub info: ProcNamePre,
     pub sockets: Vec<SockInfo<'a>>,
+    /// Other processes with the same sockets open, e.g. forked workers
+    pub sharing: Vec<Pid>,
+    /// Runs as root or has CAP_NET_BIND_SERVICE, i.e. could have bound privileged ports itself
+    pub may_bind_privileged: bool,
+    /// Process start. Sockets the process inherited or got from systemd or inetd can be older.
+    pub started: Option<DateTime<Local>>,
+    /// Parent process, for grouping applications
+    pub ppid: Option<Pid>,
+    /// Path of the unified or systemd cgroup
+    pub cgroup: Option<String>,
+    /// Image of the container the process runs in
+    pub image: Option<String>,
+    /// Owning distribution package and version, only looked up with --pkg
+    pub package: Option<String>,
+    /// Only collected with --resources
+    pub resources: Option<Resources>,
+    /// Variables out of ENV_HINTS, only read with --env-hints
+    pub env: BTreeMap<String, String>,
+    /// For inetd and xinetd, the programs they start for connections to their sockets
+    pub spawns: inetd::Services,
+}
+
+/// Environment variables that tell how a service got its sockets: systemd socket activation,
+/// or the port PaaS platforms like Heroku or Cloud Run ask it to listen on
+const ENV_HINTS: &[&str] = &["LISTEN_FDS", "LISTEN_FDNAMES", "LISTEN_PID", "PORT"];
+
+/// Read the ENV_HINTS variables of processes
+pub fn read_env(processes: &mut [ProcDesc]) {
+    for pd in processes {
+        let Ok(environ) = process(pd.pid).and_then(|p| p.environ()) else {
+            continue;
+        };
+        pd.env = (ENV_HINTS.iter())
+            .filter_map(|&key| {
+                let value = environ.get(OsStr::new(key))?;
+                Some((key.into(), value.to_string_lossy().into()))
+            })
+            .collect();
+    }
+}
+
+/// Summed over the process and all processes sharing its sockets
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Resources {
+    /// Resident set size in bytes
+    pub rss: u64,
+    /// Average CPU usage over the process lifetime, like ps reports it, in ‰ of one core
+    pub cpu_permille: u64,
+}
+
+impl Resources {
+    pub fn of(pids: impl IntoIterator<Item = Pid>) -> Result<Resources> {
+        let uptime = (procfs::Uptime::from_file(root().join("uptime")))
+            .context("Read uptime")?
+            .uptime;
+        let tps = procfs::ticks_per_second() as f64;
+        let page_size = procfs::page_size();
+        let mut sum = Resources::default();
+        for pid in pids {
+            let Ok(stat) = process(pid).and_then(|p| p.stat()) else {
+                continue;
+            };
+            let cpu = (stat.utime + stat.stime) as f64 / tps;
+            let age = uptime - stat.starttime as f64 / tps;
+            if age > 0. {
+                sum.cpu_permille += (cpu / age * 1000.).round() as u64;
+            }
+            sum.rss += stat.rss * page_size;
+        }
+        Ok(sum)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ProcNamePre {
     pub name: Option<String>,
     pub comm: Option<String>,
-    pub exe: Option<PathBuf>,
+    pub exe: Option<Rc<Path>>,
     pub cmdline: Option<Vec<String>>,
 }
 
@@ -32,39 +134,285 @@ impl<'a> ProcDesc<'a> {
     pub fn inspect_ps(
         p: Result<Process, procfs::ProcError>,
         socks: &mut HashMap<Ino, SockInfo<'a>>,
-        user_names: &UsersCache,
+        claimed: &mut HashMap<Ino, Vec<Pid>>,
+        user_names: Option<&UsersCache>,
         self_user_ns: Option<u64>,
+        interner: &mut Interner,
     ) -> Result<ProcDesc<'a>> {
         let p = p?;
-        let (name, info) = ps_name(&p);
-        let user = user_names
-            .get_user_by_uid(p.uid()?)
-            .filter(|_| get_user_ns(&p).ok() == self_user_ns)
-            .map_or_else(
-                || format!("{}", p.uid().unwrap()),
-                |u| u.name().to_string_lossy().into_owned(),
-            );
-        let sockets = p
-            .fd()?
-            .filter_map(|f| match f.ok()?.target {
-                procfs::process::FDTarget::Socket(s) => socks.remove(&s),
-                _ => None,
-            })
-            .collect();
+        let (name, info) = ps_name(&p, interner);
+        let same_user_ns = get_user_ns(&p).ok() == self_user_ns;
+        let mut sockets = Vec::<SockInfo>::new();
+        for f in p.fd()?.filter_map(|f| f.ok()) {
+            if let procfs::process::FDTarget::Socket(ino) = f.target {
+                if let Some(mut sock) = socks.remove(&ino) {
+                    sock.fds.push(f.fd);
+                    sockets.push(sock);
+                    claimed.insert(ino, Vec::new());
+                } else if let Some(sock) = sockets.iter_mut().find(|s| s.ino == ino) {
+                    sock.fds.push(f.fd);
+                } else if let Some(sharing) = claimed.get_mut(&ino) {
+                    if sharing.last() != Some(&p.pid) {
+                        sharing.push(p.pid);
+                    }
+                }
+            }
+        }
+        let status = match sockets.is_empty() {
+            true => None,
+            false => p.status().ok(),
+        };
+        let stat = match sockets.is_empty() {
+            true => None,
+            false => p.stat().ok(),
+        };
+        let started = stat.as_ref().and_then(|s| s.starttime().get().ok());
+        let cgroup = match sockets.is_empty() {
+            true => None,
+            false => (p.cgroups().ok().into_iter().flatten())
+                .find(|cg| cg.hierarchy == 0 || cg.controllers.iter().any(|c| c == "name=systemd"))
+                .map(|cg| cg.pathname),
+        };
+        let (uid, ruid) = uids(p.uid()?, status.as_ref().map(|s| (s.ruid, s.euid)));
+        let mut user_name = |uid: u32| match user_names.and_then(|u| u.get_user_by_uid(uid)) {
+            Some(u) => interner.str(&u.name().to_string_lossy()),
+            None => interner.str(&uid.to_string()),
+        };
+        let user = user_name(uid);
+        let real_user = ruid.map(|ruid| (ruid, user_name(ruid)));
+        let ns_user = match sockets.is_empty() || same_user_ns || user_names.is_none() {
+            true => None,
+            false => ns_user(&p, uid),
+        };
+        let mut group_name = |gid: u32| match user_names.and_then(|u| u.get_group_by_gid(gid)) {
+            Some(g) => interner.str(&g.name().to_string_lossy()),
+            None => interner.str(&gid.to_string()),
+        };
+        let may_bind_privileged = uid == 0
+            || status.as_ref().map_or(true, |s| {
+                (s.capeff | s.capprm) & 1 << CAP_NET_BIND_SERVICE != 0
+            });
         Ok(ProcDesc {
             pid: p.pid,
+            may_bind_privileged,
             name,
             sockets,
+            sharing: Vec::new(),
+            resources: None,
+            env: BTreeMap::new(),
+            spawns: inetd::Services::new(),
+            package: None,
+            image: None,
             user,
+            ns_user,
+            real_user,
+            started,
+            ppid: stat.map(|s| s.ppid),
+            cgroup,
             info,
-            uid: p.uid()?,
+            uid,
+            gid: status.as_ref().map(|s| s.egid),
+            group: status.as_ref().map(|s| group_name(s.egid)),
+            groups: status.map_or_else(Vec::new, |s| {
+                s.groups
+                    .into_iter()
+                    .filter(|&g| g as u32 != s.egid)
+                    .map(|g| group_name(g as u32))
+                    .collect()
+            }),
         })
     }
 }
 
-fn ps_name(p: &Process) -> (Option<String>, ProcNamePre) {
+/// The effective uid, and the real one if it differs, e.g. for a setuid program that hasn't
+/// dropped its privileges yet. The owner of /proc/<pid> is only the fallback without status,
+/// as it is root for processes that aren't dumpable.
+fn uids(owner: u32, status: Option<(u32, u32)>) -> (u32, Option<u32>) {
+    match status {
+        Some((ruid, euid)) => (euid, (ruid != euid).then_some(ruid)),
+        None => (owner, None),
+    }
+}
+
+/// Analyze another procfs mount instead of /proc, for --proc-root
+pub fn set_root(root: PathBuf) -> Result<()> {
+    ROOT.set(root)
+        .map_err(|_| anyhow::anyhow!("Only one --proc-root can be given"))
+}
+
+/// Where procfs is mounted, /proc unless overridden by --proc-root
+pub fn root() -> &'static Path {
+    ROOT.get().map_or(Path::new("/proc"), PathBuf::as_path)
+}
+
+/// The file system a --proc-root belongs to, for its users and packages: the directory the procfs
+/// is mounted in, e.g. /mnt/image for /mnt/image/proc
+pub fn sysroot() -> &'static Path {
+    root().parent().unwrap_or(Path::new("/"))
+}
+
+/// Container runtimes put the 64 hex digit container id into the cgroup path,
+/// e.g. /system.slice/docker-<id>.scope or /kubepods/…/cri-containerd-<id>.scope
+pub fn container_id(cgroup: &str) -> Option<String> {
+    cgroup.split(['/', '-', '.']).find_map(|part| {
+        (part.len() == 64 && part.bytes().all(|b| b.is_ascii_hexdigit())).then(|| part.into())
+    })
+}
+
+/// Whether sockets and processes are those of a different procfs than the running system's
+pub fn foreign_root() -> bool {
+    root() != Path::new("/proc")
+}
+
+pub fn process(pid: Pid) -> procfs::ProcResult<Process> {
+    Process::new_with_root(root().join(pid.to_string()))
+}
+
+/// First port that doesn't need CAP_NET_BIND_SERVICE in our network namespace,
+/// net.ipv4.ip_unprivileged_port_start applies to IPv6 as well
+pub fn unprivileged_port_start() -> u16 {
+    *UNPRIVILEGED_PORT_START.get_or_init(|| {
+        std::fs::read_to_string(root().join("sys/net/ipv4/ip_unprivileged_port_start"))
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(PRIVILEGED_PORTS)
+    })
+}
+
+/// net.core.somaxconn, the limit that listen() silently clamps backlogs to
+pub fn somaxconn() -> Option<u32> {
+    *SOMAXCONN.get_or_init(|| {
+        std::fs::read_to_string(root().join("sys/net/core/somaxconn"))
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+    })
+}
+
+/// Network namespace whose sockets root()/net lists
+pub fn netns() -> Option<u64> {
+    std::fs::metadata(root().join("self/ns/net"))
+        .ok()
+        .map(|m| m.ino())
+}
+
+/// Network namespace lls runs in
+pub fn own_netns() -> Option<u64> {
+    *OWN_NETNS.get_or_init(|| std::fs::metadata("/proc/self/ns/net").ok().map(|m| m.ino()))
+}
+
+/// Path of the cgroup v2 with the given id, which is the inode number of its directory
+pub fn cgroup_path(id: u64) -> Option<&'static str> {
+    let cgroups = CGROUPS.get_or_init(|| {
+        let mut root = Path::new("/sys/fs/cgroup");
+        // Hybrid hierarchy: v1 controllers in /sys/fs/cgroup, v2 mounted below
+        if root.join("unified").is_dir() {
+            root = Path::new("/sys/fs/cgroup/unified");
+        }
+        let mut ret = HashMap::new();
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            if let Ok(meta) = dir.metadata() {
+                let path = dir.strip_prefix(root).expect("Walked from root");
+                ret.insert(meta.ino(), format!("/{}", path.display()));
+            }
+            let entries = std::fs::read_dir(&dir).into_iter().flatten().flatten();
+            for entry in entries {
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    dirs.push(entry.path());
+                }
+            }
+        }
+        ret
+    });
+    cgroups.get(&id).map(String::as_str)
+}
+
+impl ProcDesc<'_> {
+    /// Privileged ports that the process could not have bound itself,
+    /// so the socket was passed in or the privileged port range was raised after binding
+    pub fn unexpectedly_privileged(&self, sock: &SockInfo) -> bool {
+        !self.may_bind_privileged && sock.privileged_port()
+    }
+    /// Container address that docker's userland proxy forwards a published port to
+    ///
+    /// Only from docker-proxy's command line. Ports published by DNAT rules alone would need
+    /// the nftables/iptables rules, which lls doesn't read.
+    pub fn container_target(&self) -> Option<SocketAddr> {
+        let cmdline = self.info.cmdline.as_ref()?;
+        let exe = Path::new(cmdline.first()?).file_name()?;
+        if exe != "docker-proxy" {
+            return None;
+        }
+        let flag = |name: &str| {
+            cmdline.iter().enumerate().find_map(|(i, arg)| {
+                let arg = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-'))?;
+                match arg.strip_prefix(name)? {
+                    "" => cmdline.get(i + 1).map(String::as_str),
+                    value => value.strip_prefix('='),
+                }
+            })
+        };
+        let ip = flag("container-ip")?.parse().ok()?;
+        let port = flag("container-port")?.parse().ok()?;
+        Some(SocketAddr::new(ip, port))
+    }
+    /// The executable was replaced or removed while running
+    pub fn exe_deleted(&self) -> bool {
+        (self.info.exe.as_ref()).is_some_and(|exe| exe.to_string_lossy().ends_with(" (deleted)"))
+    }
+    pub fn pids(&self) -> impl Iterator<Item = Pid> + '_ {
+        std::iter::once(self.pid).chain(self.sharing.iter().copied())
+    }
+}
+
+/// Render e.g. 4100-4107,4200
+pub fn pid_ranges(pids: impl IntoIterator<Item = Pid>) -> String {
+    let mut pids = pids.into_iter().collect::<Vec<_>>();
+    pids.sort();
+    pids.dedup();
+    let mut ranges = Vec::<(Pid, Pid)>::new();
+    for pid in pids {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == pid => *end = pid,
+            _ => ranges.push((pid, pid)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| match start == end {
+            true => format!("{start}"),
+            false => format!("{start}-{end}"),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Executable path and arguments, without guessing at interpreters
+pub fn full_cmdline(info: &ProcNamePre) -> Option<String> {
+    let cmdline = info.cmdline.as_deref().unwrap_or_default();
+    let exe = match &info.exe {
+        Some(exe) => exe.to_string_lossy().into_owned(),
+        None => cmdline.first()?.clone(),
+    };
+    let quote = |arg: &String| match arg.is_empty()
+        || arg
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "\"'\\".contains(c))
+    {
+        true => format!("{arg:?}"),
+        false => arg.clone(),
+    };
+    Some(
+        std::iter::once(quote(&exe))
+            .chain(cmdline.iter().skip(1).map(quote))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+fn ps_name(p: &Process, interner: &mut Interner) -> (Option<String>, ProcNamePre) {
     let comm = p.stat().ok().map(|s| remove_paren(s.comm));
-    let exe = p.exe().ok();
+    let exe = p.exe().ok().map(|exe| interner.path(exe));
     let cmdline = p.cmdline().ok();
     let name = comm
         .clone()
@@ -74,7 +422,7 @@ fn ps_name(p: &Process) -> (Option<String>, ProcNamePre) {
             exe.as_ref()
                 .and_then(|p| p.file_name().map(|p| p.to_string_lossy().into_owned()))
         })
-        .or_else(|| cmdline.as_ref().and_then(|v| v.get(0).cloned()));
+        .or_else(|| cmdline.as_ref().and_then(|v| v.first().cloned()));
     let proc_name_pre = ProcNamePre {
         comm,
         exe,
@@ -302,8 +650,9 @@ fn interpreter_ps_name(
             return cmdline.next().cloned();
         } else if has_arg.contains(&arg.as_str()) {
             cmdline.next();
-        } else if no_arg.contains(&arg.as_str()) {
-        } else if prefix_arg.iter().any(|&pfx| arg.starts_with(pfx)) {
+        } else if no_arg.contains(&arg.as_str())
+            || prefix_arg.iter().any(|&pfx| arg.starts_with(pfx))
+        {
         } else if arg.starts_with('-') {
             return None; // Unknown arg, better give up
         } else {
@@ -348,6 +697,48 @@ impl Ord for ProcDesc<'_> {
     }
 }
 
+/// Translate a uid through the process's uid_map, and look up its name in the process's root
+fn ns_user(p: &Process, uid: u32) -> Option<(u32, Option<String>)> {
+    let mut uid_map = String::new();
+    p.open_relative("uid_map")
+        .ok()?
+        .read_to_string(&mut uid_map)
+        .ok()?;
+    let ns_uid = uid_map.lines().find_map(|line| {
+        let mut fields = line.split_whitespace().map(|f| f.parse::<u32>().ok());
+        let (inside, outside, count) = (fields.next()??, fields.next()??, fields.next()??);
+        (outside..outside.saturating_add(count))
+            .contains(&uid)
+            .then(|| inside + (uid - outside))
+    })?;
+    let name = passwd(p).and_then(|passwd| {
+        passwd.lines().find_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            (fields.nth(1)?.parse() == Ok(ns_uid)).then(|| name.to_owned())
+        })
+    });
+    Some((ns_uid, name))
+}
+
+/// Larger than any real passwd file
+const MAX_PASSWD: u64 = 1 << 20;
+
+/// The /etc/passwd in the process's root, which its owner controls: not if it is a symlink, a
+/// FIFO or device that would block, or something else than a file of reasonable size
+fn passwd(p: &Process) -> Option<String> {
+    let file = (OpenOptions::new().read(true))
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(root().join(p.pid.to_string()).join("root/etc/passwd"))
+        .ok()?;
+    if !file.metadata().ok()?.is_file() {
+        return None;
+    }
+    let mut passwd = String::new();
+    file.take(MAX_PASSWD).read_to_string(&mut passwd).ok()?;
+    Some(passwd)
+}
+
 pub fn get_user_ns(p: &Process) -> Result<u64> {
     Ok(p.namespaces()
         .context("Namespaces inaccessible")?
@@ -357,13 +748,93 @@ pub fn get_user_ns(p: &Process) -> Result<u64> {
         .identifier)
 }
 
+/// Why processes could not be inspected, to tell which privilege is missing
+#[derive(Debug, Default)]
+pub struct Uninspectable {
+    /// Unreadable fd tables, by process owner
+    pub denied: BTreeMap<u32, usize>,
+    /// Exited between listing and inspecting
+    pub vanished: usize,
+    pub other: usize,
+}
+
+impl Uninspectable {
+    pub fn add(&mut self, err: &anyhow::Error, uid: Option<u32>) {
+        match err.downcast_ref::<procfs::ProcError>() {
+            Some(procfs::ProcError::PermissionDenied(_)) => {
+                *self.denied.entry(uid.unwrap_or(u32::MAX)).or_default() += 1
+            }
+            Some(procfs::ProcError::NotFound(_)) => self.vanished += 1,
+            _ => self.other += 1,
+        }
+    }
+}
+
+/// Whether /proc hides other users' processes completely
+pub fn hidepid() -> bool {
+    let Ok(mounts) = std::fs::read_to_string("/proc/self/mountinfo") else {
+        return false;
+    };
+    // id parent major:minor root mountpoint options [optional fields] - fstype source super-options
+    mounts.lines().any(|line| {
+        let mut halves = line.splitn(2, " - ");
+        let mountpoint = halves.next().and_then(|m| m.split(' ').nth(4));
+        let super_options = halves.next().and_then(|s| s.split(' ').nth(2));
+        mountpoint == Some("/proc")
+            && super_options.is_some_and(|o| {
+                o.split(',').any(|o| {
+                    o.strip_prefix("hidepid=")
+                        .is_some_and(|v| !matches!(v, "0" | "off"))
+                })
+            })
+    })
+}
+
 pub fn ourself() -> Result<Process> {
     Ok(procfs::process::Process::myself()?)
 }
 
 #[cfg(test)]
 mod test {
-    use super::ProcNamePre;
+    use super::{container_id, ProcNamePre};
+    use std::path::Path;
+
+    #[test]
+    fn cgroup_container_ids() {
+        let id = "4f1e9c0e3b8d2a7f6c5b4a39281706f5e4d3c2b1a09f8e7d6c5b4a3928170605";
+        for path in [
+            format!("/system.slice/docker-{id}.scope"),
+            format!("/docker/{id}"),
+            format!("/machine.slice/libpod-{id}.scope/container"),
+            format!("/kubepods.slice/kubepods-besteffort.slice/cri-containerd-{id}.scope"),
+            // cgroup v1, cgroupfs driver
+            format!("/kubepods/besteffort/pod0b5c1e6e-3f1d-4c8e-9a7b-2d4e6f8a0c1e/{id}"),
+            format!("/user.slice/user-1000.slice/user@1000.service/user.slice/libpod-{id}.scope"),
+        ] {
+            assert_eq!(container_id(&path).as_deref(), Some(id), "{path}");
+        }
+        assert_eq!(
+            container_id("/user.slice/user-1000.slice/session-2.scope"),
+            None
+        );
+    }
+
+    #[test]
+    fn pid_ranges() {
+        assert_eq!(super::pid_ranges([42]), "42");
+        assert_eq!(
+            super::pid_ranges([4200, 4101, 4100, 4102, 4107, 4103]),
+            "4100-4103,4107,4200"
+        );
+    }
+
+    #[test]
+    fn setuid_uids() {
+        // /proc/<pid> of a non-dumpable process is owned by root, whatever it runs as
+        assert_eq!(super::uids(0, Some((1000, 65534))), (65534, Some(1000)));
+        assert_eq!(super::uids(0, Some((1000, 1000))), (1000, None));
+        assert_eq!(super::uids(1000, None), (1000, None));
+    }
 
     #[test]
     fn py_ps_name_synapse() {
@@ -379,7 +850,7 @@ mod test {
         let name = super::py_ps_name(&ProcNamePre {
             name: Some("python".into()),
             comm: Some("python".into()),
-            exe: Some("/usr/bin/python3".into()),
+            exe: Some(Path::new("/usr/bin/python3").into()),
             cmdline: Some(cmdline),
         });
         assert_eq!(name.as_deref(), Some("python -m synapse.app.homeserver"));
@@ -395,7 +866,7 @@ mod test {
         let name = super::py_ps_name(&ProcNamePre {
             name: Some("python".into()),
             comm: Some("python".into()),
-            exe: Some("/usr/bin/python3.10".into()),
+            exe: Some(Path::new("/usr/bin/python3.10").into()),
             cmdline: Some(cmdline),
         });
         assert_eq!(name.as_deref(), Some("/home/self/MMS/app.py"));
@@ -436,7 +907,7 @@ mod test {
         let name = super::java_ps_name(&ProcNamePre {
             name: Some("java".to_owned()),
             comm: Some("java".to_owned()),
-            exe: Some("/opt/java/openjdk/bin/java".into()),
+            exe: Some(Path::new("/opt/java/openjdk/bin/java").into()),
             cmdline: Some(cmdline),
         });
         assert_eq!(
@@ -455,7 +926,7 @@ mod test {
         let name = super::node_ps_name(&ProcNamePre {
             name: Some("node".into()),
             comm: Some("node".into()),
-            exe: Some("/usr/bin/node".into()),
+            exe: Some(Path::new("/usr/bin/node").into()),
             cmdline: Some(cmdline),
         });
         assert_eq!(name.as_deref(), Some("node serve.js"));
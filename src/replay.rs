@@ -0,0 +1,311 @@
+//! Read back --json or --ndjson output for --from-file, to explore a capture from another machine
+
+use crate::{
+    bundle, cloud,
+    json::SCHEMA_VERSION,
+    netlink::sock::{unmap, Family, Iface, SockInfo, UnixName, UNKNOWN_UID},
+    procs::{ProcDesc, ProcNamePre, Resources},
+    IfaceInfo, Ino, Listing,
+};
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, Local};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs::read,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
+
+/// The groups of the capture, in the shape of --ndjson lines
+pub fn load(path: &Path) -> Result<Vec<Value>> {
+    let content = read(path).with_context(|| format!("Read capture {path:?}"))?;
+    let text = match content.starts_with(&ZSTD_MAGIC) {
+        true => bundle::capture(&content).with_context(|| format!("Read bundle {path:?}"))?,
+        false => String::from_utf8(content).with_context(|| format!("Read capture {path:?}"))?,
+    };
+    let docs = match serde_json::from_str::<Value>(&text) {
+        Ok(doc) => vec![doc],
+        Err(_) => (text.lines())
+            .filter(|l| !l.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Parse capture {path:?} as JSON or JSON lines"))?,
+    };
+    let mut groups = Vec::new();
+    for mut doc in docs {
+        let version = doc["schema_version"].as_u64();
+        ensure!(
+            version == Some(SCHEMA_VERSION.into()),
+            "Capture has schema version {version:?}, expected {SCHEMA_VERSION}"
+        );
+        if doc["kind"].is_string() {
+            // An --ndjson line
+            groups.push(doc);
+        } else if let Value::Array(g) = doc["groups"].take() {
+            // --json
+            groups.extend(g);
+        } else if let Value::Array(sockets) = doc["sockets"].take() {
+            // --json flat
+            groups.extend(regroup(sockets));
+        } else {
+            bail!("Capture has neither groups nor sockets");
+        }
+    }
+    Ok(groups)
+}
+
+/// Start of a zstd frame, as in lls capture bundles
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Undo --json flat: collect sockets with the same owner
+fn regroup(sockets: Vec<Value>) -> Vec<Value> {
+    let mut groups = Vec::<(Value, Vec<Value>)>::new();
+    for mut socket in sockets {
+        let owner = socket["owner"].take();
+        if let Value::Object(socket) = &mut socket {
+            socket.remove("owner");
+        }
+        match groups.iter_mut().find(|(o, _)| *o == owner) {
+            Some((_, sockets)) => sockets.push(socket),
+            None => groups.push((owner, vec![socket])),
+        }
+    }
+    (groups.into_iter())
+        .map(|(mut group, sockets)| {
+            group["sockets"] = Value::Array(sockets);
+            group
+        })
+        .collect()
+}
+
+/// Interface names from the capture, and the ports of wireguard/vxlan interfaces
+///
+/// Local routes aren't part of the capture, so interface filters don't match anything.
+pub fn interfaces(groups: &[Value]) -> IfaceInfo {
+    let mut info = IfaceInfo::default();
+    for group in groups {
+        if group["kind"] == "interface" {
+            let Some(id) = group["ifindex"]
+                .as_u64()
+                .and_then(|i| u32::try_from(i).ok())
+            else {
+                continue;
+            };
+            if let Some(name) = group["interface"].as_str() {
+                info.id2name.insert(id, name.into());
+            }
+            for socket in group["sockets"].as_array().into_iter().flatten() {
+                if let Some(port) = socket["port"].as_u64().and_then(|p| u16::try_from(p).ok()) {
+                    info.interface_ports.push((id, port));
+                }
+            }
+        }
+    }
+    // Socket interfaces are stored by name, make up indexes for them
+    let mut next_id = u32::MAX;
+    for group in groups {
+        for socket in group["sockets"].as_array().into_iter().flatten() {
+            let Some(name) = socket["iface"].as_str() else {
+                continue;
+            };
+            if !info.id2name.values().any(|n| n == name) {
+                info.id2name.insert(next_id, name.into());
+                next_id -= 1;
+            }
+        }
+    }
+    info
+}
+
+/// Sockets not belonging to any process by inode, and processes with their sockets
+pub fn sockets<'i>(
+    groups: &[Value],
+    iface_info: &'i IfaceInfo,
+) -> Result<(HashMap<Ino, SockInfo<'i>>, Vec<ProcDesc<'i>>)> {
+    let listing = listing(groups, iface_info)?;
+    let others = (listing.interfaces.into_iter().chain(listing.unknown))
+        .flat_map(|(_, sockets)| sockets)
+        .map(|s| (s.ino, s))
+        .collect();
+    Ok((others, listing.processes))
+}
+
+/// The groups as they were output, e.g. to compare with for --diff
+pub fn listing<'i>(groups: &[Value], iface_info: &'i IfaceInfo) -> Result<Listing<'i>> {
+    let mut listing = Listing {
+        processes: Vec::new(),
+        interfaces: Vec::new(),
+        unknown: Vec::new(),
+    };
+    for group in groups {
+        let sockets = (group["sockets"].as_array().into_iter().flatten())
+            .map(|s| socket(s, iface_info))
+            .collect::<Result<Vec<_>>>()?;
+        match group["kind"].as_str() {
+            Some("process") => listing.processes.push(process(group, sockets)?),
+            Some("interface") => {
+                let ifindex = u32_of(&group["ifindex"]).unwrap_or_default();
+                listing.interfaces.push((ifindex, sockets));
+            }
+            Some("unknown") => {
+                let uid = u32_of(&group["uid"]).unwrap_or(UNKNOWN_UID);
+                listing.unknown.push((uid, sockets));
+            }
+            kind => bail!("Unknown group kind {kind:?} in capture"),
+        }
+    }
+    Ok(listing)
+}
+
+fn string(v: &Value) -> Option<String> {
+    v.as_str().map(Into::into)
+}
+
+fn u32_of(v: &Value) -> Option<u32> {
+    v.as_u64().and_then(|v| u32::try_from(v).ok())
+}
+
+fn process<'i>(group: &Value, sockets: Vec<SockInfo<'i>>) -> Result<ProcDesc<'i>> {
+    let pid = (group["pid"].as_i64())
+        .and_then(|p| i32::try_from(p).ok())
+        .context("Process without pid in capture")?;
+    let uid = u32_of(&group["uid"]).context("Process without uid in capture")?;
+    let name = string(&group["name"]);
+    let strings = |v: &Value| {
+        (v.as_array().into_iter().flatten())
+            .filter_map(string)
+            .collect::<Vec<_>>()
+    };
+    Ok(ProcDesc {
+        pid,
+        user: string(&group["user"])
+            .unwrap_or_else(|| uid.to_string())
+            .into(),
+        uid,
+        ns_user: u32_of(&group["ns_uid"]).map(|ns_uid| (ns_uid, string(&group["ns_user"]))),
+        real_user: u32_of(&group["real_uid"]).map(|uid| {
+            let name = string(&group["real_user"]).unwrap_or_else(|| uid.to_string());
+            (uid, name.into())
+        }),
+        gid: u32_of(&group["gid"]),
+        group: string(&group["group"]).map(Into::into),
+        groups: strings(&group["groups"])
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+        info: ProcNamePre {
+            name: name.clone(),
+            comm: string(&group["comm"]),
+            exe: string(&group["exe"]).map(|exe| PathBuf::from(exe).into()),
+            cmdline: group["cmdline"]
+                .is_array()
+                .then(|| strings(&group["cmdline"])),
+        },
+        name,
+        sockets,
+        sharing: (group["pids"].as_array().into_iter().flatten())
+            .filter_map(|p| p.as_i64().and_then(|p| i32::try_from(p).ok()))
+            .filter(|&p| p != pid)
+            .collect(),
+        may_bind_privileged: group["may_bind_privileged"].as_bool().unwrap_or(true),
+        started: (group["started"].as_str())
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Local)),
+        ppid: (group["ppid"].as_i64()).and_then(|p| i32::try_from(p).ok()),
+        cgroup: string(&group["cgroup"]),
+        image: string(&group["image"]),
+        package: string(&group["package"]),
+        env: (group["env"].as_object().into_iter().flatten())
+            .filter_map(|(k, v)| Some((k.clone(), string(v)?)))
+            .collect(),
+        spawns: (group["spawns"].as_object().into_iter().flatten())
+            .filter_map(|(k, v)| {
+                let (port, proto) = k.split_once('/')?;
+                Some(((port.parse().ok()?, proto.parse().ok()?), string(v)?))
+            })
+            .collect(),
+        resources: group["rss"].as_u64().map(|rss| Resources {
+            rss,
+            cpu_permille: (group["cpu_percent"].as_f64().unwrap_or(0.) * 10.).round() as u64,
+        }),
+    })
+}
+
+fn socket<'i>(s: &Value, iface_info: &'i IfaceInfo) -> Result<SockInfo<'i>> {
+    let iface = s["iface"].as_str().and_then(|name| {
+        let name = iface_info.id2name.values().find(|n| *n == name)?;
+        Some(match s["iface_bound"].as_bool() {
+            Some(true) => Iface::Bound(name),
+            _ => Iface::Route(name),
+        })
+    });
+    let family = match s["family"].as_str() {
+        Some("v4") => Family::V4,
+        Some("v6") => Family::V6,
+        Some("dual") => Family::Both,
+        Some("unix") => Family::Unix,
+        Some("packet") => Family::Packet,
+        family => bail!("Unknown socket family {family:?} in capture"),
+    };
+    let addr = match family {
+        Family::Unix => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        Family::Packet => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        _ => (s["addr"].as_str())
+            .and_then(|a| a.parse().ok())
+            .context("Socket without address in capture")?,
+    };
+    let (family, addr) = unmap(family, addr);
+    Ok(SockInfo {
+        family,
+        protocol: (s["protocol"].as_str())
+            .and_then(|p| p.parse().ok())
+            .with_context(|| format!("Unknown protocol {} in capture", s["protocol"]))?,
+        port: (s["port"].as_u64())
+            .and_then(|p| u16::try_from(p).ok())
+            .context("Socket without port in capture")?,
+        addr,
+        uid: u32_of(&s["uid"]).unwrap_or(UNKNOWN_UID),
+        ino: s["inode"]
+            .as_u64()
+            .context("Socket without inode in capture")?,
+        cookie: s["cookie"].as_u64(),
+        drops: u32_of(&s["drops"]),
+        tos: (s["tos"].as_u64()).and_then(|t| u8::try_from(t).ok()),
+        priority: u32_of(&s["priority"]),
+        congestion: s["congestion"].as_str().map(Into::into),
+        backlog: u32_of(&s["backlog"]),
+        conns: u32_of(&s["conns"]),
+        // Cgroup ids only resolve to paths on the captured machine
+        cgroup: None,
+        rpc: (s["rpc"].as_array().into_iter().flatten())
+            .filter_map(string)
+            .collect(),
+        announced: (s["announced"].as_array().into_iter().flatten())
+            .filter_map(string)
+            .collect(),
+        exposed: (s["exposed"].as_array().into_iter().flatten())
+            .filter_map(string)
+            .collect(),
+        firewalld: (s["firewalld"].as_array().into_iter().flatten())
+            .filter_map(string)
+            .collect(),
+        cloud: s["cloud"].as_object().map(|cloud| cloud::Exposure {
+            public: (cloud["public"].as_array().into_iter().flatten())
+                .filter_map(|a| a.as_str()?.parse().ok())
+                .collect(),
+            groups: (cloud["groups"].as_array().into_iter().flatten())
+                .filter_map(string)
+                .collect(),
+        }),
+        // Namespace inodes only mean something on the captured machine
+        netns: None,
+        fallback: s["fallback_inode"].as_u64(),
+        fds: (s["fds"].as_array().into_iter().flatten())
+            .filter_map(|fd| fd.as_i64().and_then(|fd| i32::try_from(fd).ok()))
+            .collect(),
+        iface,
+        unix: (string(&s["path"]).map(UnixName::Path))
+            .or_else(|| string(&s["abstract"]).map(UnixName::Abstract)),
+    })
+}
@@ -0,0 +1,147 @@
+use crate::{
+    netlink::sock::{ethertype, Family, Iface, SockInfo, UNKNOWN_UID},
+    options::{Column, Filters},
+    procs::{self, pid_ranges, Pid, Resources},
+    IfaceInfo, Listing,
+};
+use chrono::{DateTime, Local, SecondsFormat};
+use itertools::Itertools;
+use std::rc::Rc;
+
+/// One line per socket, for line-based output formats
+pub struct Row<'a> {
+    pub cmd: Option<String>,
+    pub pids: Vec<Pid>,
+    pub user: Rc<str>,
+    /// Primary and supplementary groups
+    pub groups: Vec<Rc<str>>,
+    pub resources: Option<Resources>,
+    pub started: Option<DateTime<Local>>,
+    pub package: Option<String>,
+    pub image: Option<String>,
+    /// KEY=value, for --env-hints
+    pub env: Vec<String>,
+    pub sock: &'a SockInfo<'a>,
+}
+
+impl Row<'_> {
+    pub fn get(&self, column: Column) -> String {
+        match column {
+            Column::Cmd => self.cmd.clone().unwrap_or_default(),
+            Column::Pid => pid_ranges(self.pids.iter().copied()),
+            Column::User => self.user.to_string(),
+            Column::Group => self.groups.join(","),
+            Column::Port => match self.sock.family {
+                Family::Unix => String::new(),
+                Family::Packet => ethertype(self.sock.port),
+                _ => self.sock.port.to_string(),
+            },
+            Column::Proto => self.sock.protocol.to_string(),
+            Column::Addr => match self.sock.family {
+                Family::Both if self.sock.v4_mapped() => self.sock.addr.to_string(),
+                Family::Both | Family::Packet => "*".into(),
+                Family::Unix => (self.sock.unix.as_ref())
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+                _ => self.sock.addr.to_string(),
+            },
+            Column::Iface => self.sock.iface.map(Iface::name).unwrap_or_default().into(),
+            Column::Fd => self.sock.fds.iter().join(","),
+            Column::Inode => self.sock.ino.to_string(),
+            Column::Cookie => self.sock.cookie.map(|c| c.to_string()).unwrap_or_default(),
+            Column::Rss => self
+                .resources
+                .map(|r| r.rss.to_string())
+                .unwrap_or_default(),
+            Column::Cpu => (self.resources)
+                .map(|r| format!("{}.{}", r.cpu_permille / 10, r.cpu_permille % 10))
+                .unwrap_or_default(),
+            Column::Since => (self.started)
+                .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, false))
+                .unwrap_or_default(),
+            Column::Image => self.image.clone().unwrap_or_default(),
+            Column::Pkg => self.package.clone().unwrap_or_default(),
+            Column::Env => self.env.join(" "),
+            Column::Drops => self.sock.drops.map(|d| d.to_string()).unwrap_or_default(),
+            Column::Qos => [
+                self.sock.tos.map(|t| format!("tos {t:#04x}")),
+                self.sock.priority.map(|p| format!("prio {p}")),
+            ]
+            .into_iter()
+            .flatten()
+            .join(" "),
+            Column::Cong => self.sock.congestion.as_deref().unwrap_or_default().into(),
+            Column::Backlog => self.sock.backlog.map(|b| b.to_string()).unwrap_or_default(),
+            Column::Conns => self.sock.conns.map(|c| c.to_string()).unwrap_or_default(),
+            Column::Cgroup => (self.sock.cgroup)
+                .map(|id| procs::cgroup_path(id).map_or_else(|| format!("#{id}"), Into::into))
+                .unwrap_or_default(),
+            Column::Netns => self.sock.netns.map(|ns| ns.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+pub fn rows<'a>(
+    listing: &'a Listing,
+    iface_info: &'a IfaceInfo,
+    filters: &'a Filters,
+) -> impl Iterator<Item = Row<'a>> + 'a {
+    let processes = listing.processes.iter().flat_map(|pd| {
+        pd.sockets.iter().map(|sock| Row {
+            cmd: pd.name.clone(),
+            pids: pd.pids().collect(),
+            user: pd.user.clone(),
+            groups: pd.group.iter().chain(&pd.groups).cloned().collect(),
+            resources: pd.resources,
+            started: pd.started,
+            package: pd.package.clone(),
+            image: pd.image.clone(),
+            env: pd.env.iter().map(|(k, v)| format!("{k}={v}")).collect(),
+            sock,
+        })
+    });
+    let interfaces = listing.interfaces.iter().flat_map(|(if_id, socks)| {
+        let name = match iface_info.id2name.get(if_id) {
+            Some(ifname) => format!("[network interface {ifname}]"),
+            None => format!("[network interface #{if_id}]"),
+        };
+        socks.iter().map(move |sock| Row {
+            cmd: Some(name.clone()),
+            pids: Vec::new(),
+            user: uid_label(sock.uid),
+            groups: Vec::new(),
+            resources: None,
+            started: None,
+            package: None,
+            image: None,
+            env: Vec::new(),
+            sock,
+        })
+    });
+    let unknown = listing.unknown.iter().flat_map(|(uid, socks)| {
+        socks.iter().map(move |sock| Row {
+            cmd: Some("???".into()),
+            pids: Vec::new(),
+            user: uid_label(*uid),
+            groups: Vec::new(),
+            resources: None,
+            started: None,
+            package: None,
+            image: None,
+            env: Vec::new(),
+            sock,
+        })
+    });
+    processes
+        .chain(interfaces)
+        .chain(unknown)
+        .filter(|row| filters.accept_sock(row.sock))
+}
+
+/// The uid, or nothing if the kernel didn't tell
+fn uid_label(uid: u32) -> Rc<str> {
+    match uid {
+        UNKNOWN_UID => "".into(),
+        uid => uid.to_string().into(),
+    }
+}
@@ -0,0 +1,204 @@
+//! Names of the RPC programs behind the ports that rpcbind (portmapper) hands out
+//!
+//! NFS helpers like mountd, statd, and lockd listen on random high ports and register them
+//! with rpcbind, whose PMAPPROC_DUMP call lists all registrations. The exchange is bounded in
+//! time and size, the local rpcbind might be hung or not rpcbind at all.
+
+use crate::{
+    netlink::sock::{Protocol, SockInfo},
+    Ino,
+};
+use anyhow::{bail, ensure, Context, Result};
+use itertools::Itertools;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::read_to_string,
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+const RPCBIND_PORT: u16 = 111;
+const PMAP_PROG: u32 = 100000;
+const PMAP_VERS: u32 = 2;
+const PMAPPROC_DUMP: u32 = 4;
+/// For the whole exchange
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// A registration takes 20 bytes, this is for thousands
+const MAX_REPLY: usize = 64 << 10;
+
+/// For systems without /etc/rpc, e.g. containers
+const PROGRAMS: &[(u32, &str)] = &[
+    (100000, "portmapper"),
+    (100003, "nfs"),
+    (100005, "mountd"),
+    (100011, "rquotad"),
+    (100021, "nlockmgr"),
+    (100024, "status"),
+    (100227, "nfs_acl"),
+];
+
+/// Set the RPC programs of sockets on ports registered with rpcbind, if it is listening
+pub fn resolve(socks: &mut HashMap<Ino, SockInfo>) -> Result<()> {
+    let rpcbind = socks
+        .values()
+        .filter(|s| s.port == RPCBIND_PORT && s.protocol == Protocol::TCP)
+        .map(|s| s.addr)
+        .collect::<Vec<_>>();
+    if rpcbind.is_empty() {
+        return Ok(());
+    }
+    let localhost = (rpcbind.iter())
+        .map(|addr| match addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        })
+        .unique()
+        .collect::<Vec<_>>();
+    let registrations = dump(&localhost).context("Query rpcbind")?;
+    let etc_rpc = read_to_string("/etc/rpc").unwrap_or_default();
+    let mut programs = BTreeMap::<_, Vec<_>>::new();
+    for (program, proto, port) in registrations {
+        let names = programs.entry((port, proto)).or_default();
+        let name = name(&etc_rpc, program);
+        // One entry per version
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    for sock in socks.values_mut() {
+        if let Some(names) = programs.get(&(sock.port, sock.protocol)) {
+            sock.rpc = names.clone();
+        }
+    }
+    Ok(())
+}
+
+/// Program, protocol, and port of all registrations
+fn dump(addrs: &[IpAddr]) -> Result<Vec<(u32, Protocol, u16)>> {
+    let deadline = Instant::now() + TIMEOUT;
+    let left = || {
+        Some(deadline.saturating_duration_since(Instant::now()))
+            .filter(|left| !left.is_zero())
+            .with_context(|| format!("Timed out after {TIMEOUT:?}"))
+    };
+    let addrs = (addrs.iter()).map(|&addr| SocketAddr::new(addr, RPCBIND_PORT));
+    let mut stream = (addrs.clone())
+        .find_map(|addr| TcpStream::connect_timeout(&addr, left().ok()?).ok())
+        .with_context(|| format!("Connect to {}", addrs.format(" or ")))?;
+    stream.set_write_timeout(Some(left()?))?;
+    let xid = std::process::id();
+    // No credentials, no verifier, no arguments
+    let call = [xid, 0, 2, PMAP_PROG, PMAP_VERS, PMAPPROC_DUMP, 0, 0, 0, 0];
+    let mut request = (0x8000_0000 | (call.len() * 4) as u32)
+        .to_be_bytes()
+        .to_vec();
+    request.extend(call.iter().flat_map(|w| w.to_be_bytes()));
+    stream.write_all(&request).context("Send call")?;
+    // A read timeout bounds each read, not all of them
+    let mut read = |mut buf: &mut [u8]| -> Result<()> {
+        while !buf.is_empty() {
+            stream.set_read_timeout(Some(left()?))?;
+            match stream.read(buf).context("Read reply")? {
+                0 => bail!("Reply truncated"),
+                n => buf = &mut buf[n..],
+            }
+        }
+        Ok(())
+    };
+    // Record marking: fragments with their length, the highest bit marks the last one
+    let mut reply = Vec::new();
+    loop {
+        let mut mark = [0; 4];
+        read(&mut mark)?;
+        let mark = u32::from_be_bytes(mark);
+        let start = reply.len();
+        let end = start + (mark & 0x7fff_ffff) as usize;
+        ensure!(end <= MAX_REPLY, "Reply longer than {MAX_REPLY} bytes");
+        reply.resize(end, 0);
+        read(&mut reply[start..])?;
+        if mark & 0x8000_0000 != 0 {
+            break;
+        }
+    }
+    parse(&reply, xid)
+}
+
+fn parse(reply: &[u8], xid: u32) -> Result<Vec<(u32, Protocol, u16)>> {
+    let mut words = reply
+        .chunks_exact(4)
+        .map(|w| u32::from_be_bytes([w[0], w[1], w[2], w[3]]));
+    let mut next = || words.next().context("Reply truncated");
+    ensure!(next()? == xid, "Reply to another call");
+    ensure!(next()? == 1, "Not a reply");
+    ensure!(next()? == 0, "Call denied");
+    let _verifier_flavor = next()?;
+    for _ in 0..next()?.div_ceil(4) {
+        next()?;
+    }
+    ensure!(next()? == 0, "Call failed");
+    let mut ret = Vec::new();
+    while next()? == 1 {
+        let (program, _version, proto, port) = (next()?, next()?, next()?, next()?);
+        let proto = match proto {
+            6 => Protocol::TCP,
+            17 => Protocol::UDP,
+            _ => continue,
+        };
+        ret.push((program, proto, port as u16));
+    }
+    Ok(ret)
+}
+
+/// Name of the program according to /etc/rpc, or the number
+fn name(etc_rpc: &str, program: u32) -> String {
+    let number = program.to_string();
+    let listed = etc_rpc.lines().find_map(|line| {
+        let mut words = line.split('#').next()?.split_whitespace();
+        let (name, n) = (words.next()?, words.next()?);
+        (n == number).then_some(name)
+    });
+    let known = || {
+        PROGRAMS
+            .iter()
+            .find(|(p, _)| *p == program)
+            .map(|(_, n)| *n)
+    };
+    listed.or_else(known).map_or(number, Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{name, parse};
+    use crate::netlink::sock::Protocol;
+
+    #[test]
+    fn dump_reply() {
+        let words: &[u32] = &[
+            42, 1, 0, 0, 0, 0, // xid, reply, accepted, empty verifier, success
+            1, 100000, 2, 6, 111, //
+            1, 100005, 3, 17, 20048, //
+            1, 100021, 4, 6, 40001, //
+            0,
+        ];
+        let reply = words
+            .iter()
+            .flat_map(|w| w.to_be_bytes())
+            .collect::<Vec<_>>();
+        let registrations = parse(&reply, 42).unwrap();
+        assert_eq!(
+            registrations,
+            [
+                (100000, Protocol::TCP, 111),
+                (100005, Protocol::UDP, 20048),
+                (100021, Protocol::TCP, 40001),
+            ]
+        );
+        assert!(parse(&reply, 43).is_err());
+        assert!(parse(&reply[..reply.len() - 4], 42).is_err());
+        let etc_rpc = "# comment\nmountd\t\t100005\tmount showmount\n";
+        assert_eq!(name(etc_rpc, 100005), "mountd");
+        assert_eq!(name(etc_rpc, 100021), "nlockmgr");
+        assert_eq!(name(etc_rpc, 300019), "300019");
+    }
+}
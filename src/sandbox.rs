@@ -0,0 +1,263 @@
+//! Self-sandboxing with seccomp and Landlock, unless --no-sandbox
+//!
+//! seccomp, installed before the scan, allows only the syscalls lls needs: netlink, /proc,
+//! NSS, D-Bus, and for --discover, --gateway and --cloud, the network. Files can only be
+//! opened for reading, and x32 syscalls kill lls. Landlock, once processes have been
+//! inspected, additionally confines reading to a few directories.
+
+use crate::{log, procs};
+use anyhow::{Context, Result};
+use landlock::{
+    path_beneath_rules, Access, AccessFs, AccessNet, Ruleset, RulesetAttr, RulesetCreatedAttr,
+    RulesetStatus, ABI,
+};
+use seccompiler::{
+    sock_filter, BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+    SeccompFilter, SeccompRule,
+};
+use std::{collections::BTreeMap, path::Path};
+
+// /proc for sockets and processes, the rest for user name lookups through NSS
+const READABLE: &[&str] = &[
+    "/proc", "/sys", "/etc", "/usr", "/lib", "/lib64", "/var/lib",
+];
+
+/// What the scan, the lookups and the output need, without conditions on the arguments.
+/// Everything else, e.g. executing programs, ptrace, process_vm_readv, perf_event_open,
+/// userfaultfd, io_uring, keyctl, kill, mounts and namespaces, fails with EPERM.
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    // Memory
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    // Files, opened read-only, see [read_only]
+    libc::SYS_read,
+    libc::SYS_pread64,
+    libc::SYS_readv,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_close,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_fstatfs,
+    libc::SYS_statfs,
+    libc::SYS_getdents64,
+    libc::SYS_readlinkat,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    libc::SYS_fcntl,
+    libc::SYS_getcwd,
+    // Threads, time and signals
+    libc::SYS_futex,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_gettid,
+    libc::SYS_getpid,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_gettimeofday,
+    libc::SYS_getrandom,
+    libc::SYS_uname,
+    libc::SYS_sysinfo,
+    // Users
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_getgroups,
+    libc::SYS_getresuid,
+    libc::SYS_getresgid,
+    // Netlink, D-Bus, rpcbind, --discover, --gateway and --cloud, see [SOCKET_DOMAINS]
+    libc::SYS_connect,
+    libc::SYS_bind,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_sendmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_shutdown,
+    libc::SYS_ppoll,
+    // The --enrich command
+    libc::SYS_wait4,
+    libc::SYS_waitid,
+    // Landlock, applied later
+    libc::SYS_landlock_create_ruleset,
+    libc::SYS_landlock_add_rule,
+    libc::SYS_landlock_restrict_self,
+];
+
+/// The legacy syscalls that libc still uses on x86_64
+#[cfg(target_arch = "x86_64")]
+const ALLOWED_LEGACY_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_stat,
+    libc::SYS_lstat,
+    libc::SYS_readlink,
+    libc::SYS_access,
+    libc::SYS_poll,
+];
+#[cfg(not(target_arch = "x86_64"))]
+const ALLOWED_LEGACY_SYSCALLS: &[libc::c_long] = &[];
+
+const SOCKET_DOMAINS: &[libc::c_int] = &[
+    libc::AF_NETLINK,
+    libc::AF_UNIX,
+    libc::AF_INET,
+    libc::AF_INET6,
+    // The RDS socket table
+    21,
+];
+
+/// The only ioctls: non-blocking sockets for connect timeouts, and the terminal's size
+const IOCTLS: &[libc::c_ulong] = &[libc::FIONBIO, libc::TIOCGWINSZ, libc::TCGETS];
+
+/// Forbid all syscalls but those lls needs, and opening files for writing
+pub fn syscalls() {
+    if let Err(e) = seccomp() {
+        log::warn!("{e:#}, continuing without seccomp");
+    }
+}
+
+/// Restrict ourselves to reading files, as far as the kernel allows
+///
+/// Landlock also denies access to /proc/<pid>/fd of processes outside the sandbox,
+/// so this can only be applied once all processes have been inspected.
+pub fn filesystem() {
+    if let Err(e) = landlock() {
+        log::warn!("{e:#}, continuing without Landlock");
+    }
+}
+
+fn landlock() -> Result<()> {
+    let abi = ABI::V4;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))?
+        .handle_access(AccessNet::from_all(abi))?
+        .create()?
+        .add_rules(path_beneath_rules(
+            (READABLE.iter().map(Path::new))
+                .chain([procs::root()])
+                .chain(procs::foreign_root().then(procs::sysroot))
+                .filter(|p| p.exists()),
+            AccessFs::from_read(abi),
+        ))?
+        .restrict_self()
+        .context("Landlock restrict self")?;
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => log::debug!("Landlock fully enforced"),
+        RulesetStatus::PartiallyEnforced => log::debug!("Landlock partially enforced"),
+        RulesetStatus::NotEnforced => log::debug!("Landlock not supported by kernel"),
+    }
+    Ok(())
+}
+
+fn seccomp() -> Result<()> {
+    let mut rules = (ALLOWED_SYSCALLS.iter().chain(ALLOWED_LEGACY_SYSCALLS))
+        .map(|&syscall| (syscall, vec![]))
+        .collect::<BTreeMap<_, _>>();
+    rules.insert(
+        libc::SYS_openat,
+        when(2, SeccompCmpOp::MaskedEq(WRITING), 0)?,
+    );
+    #[cfg(target_arch = "x86_64")]
+    rules.insert(libc::SYS_open, when(1, SeccompCmpOp::MaskedEq(WRITING), 0)?);
+    let domains = SOCKET_DOMAINS.iter().map(|&d| d as u64);
+    rules.insert(libc::SYS_socket, one_of(0, domains)?);
+    rules.insert(libc::SYS_ioctl, one_of(1, IOCTLS.iter().copied())?);
+    // Threads, but no new namespaces
+    rules.insert(
+        libc::SYS_clone,
+        when(0, SeccompCmpOp::MaskedEq(NAMESPACES), 0)?,
+    );
+    // Landlock sets no_new_privs, threads may name themselves
+    let prctls = [
+        libc::PR_SET_NO_NEW_PRIVS,
+        libc::PR_SET_NAME,
+        libc::PR_GET_NAME,
+    ];
+    rules.insert(libc::SYS_prctl, one_of(0, prctls.map(|p| p as u64))?);
+    // Aborting on a panic signals ourselves
+    let pid = std::process::id().into();
+    rules.insert(libc::SYS_tgkill, when(0, SeccompCmpOp::Eq, pid)?);
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Errno(libc::EPERM as u32),
+        SeccompAction::Allow,
+        std::env::consts::ARCH
+            .try_into()
+            .context("Seccomp not supported on this architecture")?,
+    )
+    .context("Construct seccomp filter")?;
+    let program: BpfProgram = filter.try_into().context("Compile seccomp filter")?;
+    let program = [prelude(), program].concat();
+    seccompiler::apply_filter(&program).context("Apply seccomp filter")?;
+    log::debug!("Seccomp filter applied");
+    Ok(())
+}
+
+/// Allow a syscall if its argument at index compares to value
+fn when(index: u8, op: SeccompCmpOp, value: u64) -> Result<Vec<SeccompRule>> {
+    // All flags and numbers checked fit into the lower half
+    let condition = SeccompCondition::new(index, SeccompCmpArgLen::Dword, op, value)?;
+    Ok(vec![SeccompRule::new(vec![condition])?])
+}
+
+/// Allow a syscall if its argument at index is one of values
+fn one_of(index: u8, values: impl IntoIterator<Item = u64>) -> Result<Vec<SeccompRule>> {
+    values.into_iter().try_fold(vec![], |mut rules, value| {
+        rules.extend(when(index, SeccompCmpOp::Eq, value)?);
+        Ok(rules)
+    })
+}
+
+/// Flags of open that write, create or truncate
+const WRITING: u64 =
+    (libc::O_WRONLY | libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC | libc::O_APPEND) as u64;
+
+/// Flags of clone that create namespaces
+const NAMESPACES: u64 = (libc::CLONE_NEWNS
+    | libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWCGROUP) as u64;
+
+/// Checks before the filter compiled by seccompiler, which can only allow or deny:
+/// x32 syscalls on x86_64, which the rules above don't cover, kill lls, and clone3, whose
+/// flags a filter can't inspect, fails with ENOSYS, so that libc falls back to clone.
+fn prelude() -> BpfProgram {
+    let insn = |code: u32, k: u32, jt, jf| sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    };
+    let load_nr = insn(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 0, 0, 0);
+    let ret = |action: SeccompAction| insn(libc::BPF_RET | libc::BPF_K, action.into(), 0, 0);
+    let jump_if = |op: u32, k: u32| insn(libc::BPF_JMP | op | libc::BPF_K, k, 0, 1);
+    let mut prelude = vec![load_nr];
+    if cfg!(target_arch = "x86_64") {
+        prelude.push(jump_if(libc::BPF_JGE, 0x4000_0000));
+        prelude.push(ret(SeccompAction::KillProcess));
+    }
+    prelude.push(jump_if(libc::BPF_JEQ, libc::SYS_clone3 as u32));
+    prelude.push(ret(SeccompAction::Errno(libc::ENOSYS as u32)));
+    prelude
+}
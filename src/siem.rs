@@ -0,0 +1,120 @@
+//! One CEF or LEEF record per listening socket, for --cef and --leef
+
+use crate::{
+    netlink::sock::Family,
+    options::{Column, Filters, Siem},
+    procs,
+    rows::{rows, Row},
+    IfaceInfo, Listing,
+};
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::io::Write;
+
+const VENDOR: &str = "lls";
+const PRODUCT: &str = "lls";
+const EVENT_ID: &str = "listener";
+/// CEF severity, 0-10: informational
+const SEVERITY: u8 = 3;
+
+pub fn write(
+    listing: &Listing,
+    iface_info: &IfaceInfo,
+    filters: &Filters,
+    format: Siem,
+    out: &mut impl Write,
+) -> Result<()> {
+    let host =
+        std::fs::read_to_string(procs::root().join("sys/kernel/hostname")).unwrap_or_default();
+    let now = Local::now();
+    for row in rows(listing, iface_info, filters) {
+        let record = match format {
+            Siem::Cef => {
+                let time = now.timestamp_millis().to_string();
+                let fields = fields(&row, host.trim(), time, CEF_KEYS);
+                format!(
+                    "CEF:0|{VENDOR}|{PRODUCT}|{}|{EVENT_ID}|Listening socket|{SEVERITY}|{}",
+                    env!("CARGO_PKG_VERSION"),
+                    (fields.iter())
+                        .map(|(k, v)| format!("{k}={}", cef_escape(v)))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                )
+            }
+            Siem::Leef => {
+                // LEEF's default devTimeFormat
+                let time = now.format("%b %d %Y %H:%M:%S").to_string();
+                let fields = fields(&row, host.trim(), time, LEEF_KEYS);
+                format!(
+                    "LEEF:1.0|{VENDOR}|{PRODUCT}|{}|{EVENT_ID}|{}",
+                    env!("CARGO_PKG_VERSION"),
+                    (fields.iter())
+                        .map(|(k, v)| format!("{k}={}", leef_escape(v)))
+                        .collect::<Vec<_>>()
+                        .join("\t"),
+                )
+            }
+        };
+        writeln!(out, "{record}").context("Write output")?;
+    }
+    Ok(())
+}
+
+/// Field names for time, host, protocol, address, port, user, pid, process
+type Keys = [&'static str; 8];
+const CEF_KEYS: Keys = [
+    "rt", "dvchost", "proto", "src", "spt", "suser", "spid", "sproc",
+];
+const LEEF_KEYS: Keys = [
+    "devTime",
+    "identHostName",
+    "proto",
+    "src",
+    "srcPort",
+    "usrName",
+    "pid",
+    "proc",
+];
+
+fn fields(row: &Row, host: &str, time: String, keys: Keys) -> Vec<(&'static str, String)> {
+    let [time_key, host_key, proto, src, port, user, pid, process] = keys;
+    let mut ret = vec![
+        (time_key, time),
+        (host_key, host.into()),
+        (proto, row.get(Column::Proto).to_ascii_uppercase()),
+        (
+            src,
+            match row.sock.family {
+                Family::Unix | Family::Packet => row.get(Column::Addr),
+                _ => row.sock.addr.to_string(),
+            },
+        ),
+        (port, row.get(Column::Port)),
+        (user, row.get(Column::User)),
+    ];
+    // The first pid, the others share the socket
+    if let Some(first) = row.pids.first() {
+        ret.push((pid, first.to_string()));
+    }
+    ret.push((process, row.get(Column::Cmd)));
+    if row.sock.family == Family::Both {
+        // Dual-stack sockets also accept IPv4
+        ret.push(("cs1Label", "dualStack".into()));
+        ret.push(("cs1", "true".into()));
+    }
+    ret.retain(|(_, v)| !v.is_empty());
+    ret
+}
+
+fn cef_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// LEEF has no escaping, fields are separated by tabs
+fn leef_escape(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
@@ -1,9 +1,14 @@
 use super::Ino;
 use crate::{
-    netlink::sock::{Family, Protocol, SockInfo},
-    IfaceInfo,
+    log,
+    netlink::sock::{unmap, Family, Iface, Protocol, SockInfo},
+    procs, IfaceInfo,
 };
 use anyhow::{Context, Result};
+use procfs::{
+    net::{TcpNetEntries, UdpNetEntries},
+    FromReadSI,
+};
 use std::collections::HashMap;
 
 pub fn all_sockets<'i>(
@@ -13,33 +18,46 @@ pub fn all_sockets<'i>(
         ..
     }: &'i IfaceInfo,
 ) -> Result<HashMap<Ino, SockInfo<'i>>> {
-    eprintln!("WARNING: Falling back to parsing info from procfs, limited to TCP and UDP");
+    if cfg!(feature = "netlink") && !procs::foreign_root() {
+        log::warn!("Falling back to parsing info from procfs, limited to TCP and UDP");
+    }
     let mut ret = HashMap::new();
     let mut errs = Vec::new();
     let mut one_success = false;
 
+    let drops = [udp_drops("net/udp"), udp_drops("net/udp6")]
+        .into_iter()
+        .flatten()
+        .collect::<HashMap<_, _>>();
+
     macro_rules! save {
-        ($fami:ident, $proto:ident, $file:ident) => {
-            let file = procfs::net::$file()
-                .context(concat!("Error parsing /proc/net/", stringify!($file)));
+        ($fami:ident, $proto:ident, $file:ident, $entries:ty) => {
+            let path = procs::root().join(concat!("net/", stringify!($file)));
+            let file = FromReadSI::from_file(&path, procfs::current_system_info())
+                .map(|entries: $entries| entries.0)
+                .with_context(|| format!("Error parsing {path:?}"));
             match file {
                 Ok(s) => {
                     one_success |= true;
                     s.into_iter().for_each(|s| {
                         if s.remote_address.port() == 0 {
+                            let (family, addr) = unmap(Family::$fami, s.local_address.ip());
                             ret.insert(
                                 s.inode,
                                 SockInfo {
-                                    family: Family::$fami,
-                                    protocol: Protocol::$proto,
-                                    port: s.local_address.port(),
-                                    addr: s.local_address.ip(),
-                                    uid: s.uid,
-                                    ino: s.inode,
+                                    drops: drops.get(&s.inode).copied(),
                                     iface: local_routes
-                                        .route(s.local_address.ip())
+                                        .route(addr)
                                         .and_then(|iface| interfaces.get(&iface))
-                                        .map(|s| &**s),
+                                        .map(|s| Iface::Route(s)),
+                                    ..SockInfo::new(
+                                        family,
+                                        Protocol::$proto,
+                                        s.local_address.port(),
+                                        addr,
+                                        s.uid,
+                                        s.inode,
+                                    )
                                 },
                             );
                         }
@@ -49,16 +67,16 @@ pub fn all_sockets<'i>(
             };
         };
     }
-    save!(V6, UDP, udp6);
-    save!(V6, TCP, tcp6);
-    save!(V4, UDP, udp);
-    save!(V4, TCP, tcp);
+    save!(V6, UDP, udp6, UdpNetEntries);
+    save!(V6, TCP, tcp6, TcpNetEntries);
+    save!(V4, UDP, udp, UdpNetEntries);
+    save!(V4, TCP, tcp, TcpNetEntries);
 
     match errs.is_empty() {
         true => Ok(ret),
         false => {
             for e in errs {
-                eprintln!("{}", e);
+                log::warn!("{e:#}");
             }
             match one_success {
                 true => Ok(ret),
@@ -67,3 +85,18 @@ pub fn all_sockets<'i>(
         }
     }
 }
+
+/// The procfs crate doesn't parse the drops column, which is the last one in /proc/net/udp
+fn udp_drops(file: &str) -> HashMap<Ino, u32> {
+    let Ok(content) = std::fs::read_to_string(procs::root().join(file)) else {
+        return HashMap::new();
+    };
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            Some((fields.get(9)?.parse().ok()?, fields.get(12)?.parse().ok()?))
+        })
+        .collect()
+}
@@ -0,0 +1,109 @@
+//! Bound RDS (Reliable Datagram Sockets), e.g. Oracle RAC interconnects
+//!
+//! RDS has no sock_diag support, the socket list is read with getsockopt(RDS_INFO_SOCKETS) on an
+//! RDS socket, like rds-info does.
+
+use crate::{
+    log,
+    netlink::sock::{Family, Iface, Protocol, SockInfo, UNKNOWN_UID},
+    IfaceInfo, Ino,
+};
+use anyhow::{bail, Result};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+const AF_RDS: i32 = 21;
+const SOL_RDS: i32 = 276;
+const RDS_INFO_SOCKETS: i32 = 10006;
+const RDS6_INFO_SOCKETS: i32 = 10015;
+/// struct rds_info_socket: sndbuf, bound and connected address and port, rcvbuf, inode
+const INFO_LEN: usize = 4 + 4 + 4 + 2 + 2 + 4 + 8;
+/// struct rds6_info_socket: Same, with IPv6 addresses
+const INFO6_LEN: usize = 4 + 16 + 16 + 2 + 2 + 4 + 8;
+
+/// All bound RDS sockets, if the rds module is loaded
+///
+/// The uid is unknown, it is set to that of the owning process once that is found.
+pub fn all_sockets(iface_info: &IfaceInfo) -> HashMap<Ino, SockInfo<'_>> {
+    // Creating an RDS socket would load the module, which is a well-known attack surface
+    if !Path::new("/sys/module/rds").exists() {
+        return HashMap::new();
+    }
+    match sockets(iface_info) {
+        Ok(socks) => socks,
+        Err(e) => {
+            log::info!("RDS sockets: {e:#}");
+            HashMap::new()
+        }
+    }
+}
+
+fn sockets(
+    IfaceInfo {
+        id2name: interfaces,
+        local_routes,
+        ..
+    }: &IfaceInfo,
+) -> Result<HashMap<Ino, SockInfo<'_>>> {
+    let fd = unsafe { libc::socket(AF_RDS, libc::SOCK_SEQPACKET, 0) };
+    if fd < 0 {
+        bail!("Create RDS socket: {}", io::Error::last_os_error());
+    }
+    let v4 = info(fd, RDS_INFO_SOCKETS);
+    // Only since Linux 4.20
+    let v6 = info(fd, RDS6_INFO_SOCKETS);
+    unsafe { libc::close(fd) };
+    let mut ret = HashMap::new();
+    let entries = (v4?.chunks_exact(INFO_LEN))
+        .map(|e| {
+            let addr = Ipv4Addr::new(e[4], e[5], e[6], e[7]);
+            let port = u16::from_be_bytes([e[12], e[13]]);
+            let ino = u64::from_ne_bytes(e[20..28].try_into().expect("Chunk length"));
+            (Family::V4, IpAddr::V4(addr), port, ino)
+        })
+        .collect::<Vec<_>>();
+    let entries6 = v6.unwrap_or_default();
+    let entries6 = entries6.chunks_exact(INFO6_LEN).filter_map(|e| {
+        let bytes: [u8; 16] = e[4..20].try_into().expect("Chunk length");
+        let addr = Ipv6Addr::from(bytes);
+        let port = u16::from_be_bytes([e[36], e[37]]);
+        let ino = u64::from_ne_bytes(e[44..52].try_into().expect("Chunk length"));
+        // IPv4 sockets are listed again as v4-mapped addresses
+        (addr.to_ipv4_mapped().is_none()).then_some((Family::V6, IpAddr::V6(addr), port, ino))
+    });
+    for (family, addr, port, ino) in entries.into_iter().chain(entries6) {
+        if port == 0 {
+            continue;
+        }
+        let iface = local_routes
+            .route(addr)
+            .and_then(|iface| interfaces.get(&iface))
+            .map(|s| Iface::Route(s));
+        // The owner is only known once a process claims it
+        let info = SockInfo::new(family, Protocol::RDS, port, addr, UNKNOWN_UID, ino);
+        ret.insert(ino, SockInfo { iface, ..info });
+    }
+    Ok(ret)
+}
+
+/// The kernel answers ENOSPC and the needed length if the buffer is too small
+fn info(fd: i32, opt: i32) -> Result<Vec<u8>> {
+    let mut buf = Vec::<u8>::new();
+    loop {
+        let mut len = buf.len() as libc::socklen_t;
+        let ret = unsafe { libc::getsockopt(fd, SOL_RDS, opt, buf.as_mut_ptr().cast(), &mut len) };
+        if ret >= 0 {
+            buf.truncate(len as usize);
+            return Ok(buf);
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ENOSPC) || len as usize <= buf.len() {
+            bail!("Read RDS socket list: {err}");
+        }
+        buf.resize(len as usize, 0);
+    }
+}
@@ -0,0 +1,71 @@
+//! Compact per-user overview for --summary-by-user
+
+use crate::{
+    netlink::sock::UNKNOWN_UID,
+    options::Filters,
+    rows::rows,
+    users::{Users, UsersCache},
+    IfaceInfo, Listing,
+};
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, io::Write};
+
+#[derive(Default)]
+struct Counts {
+    listeners: usize,
+    wildcard: usize,
+    privileged: usize,
+}
+
+/// Count listening sockets by the user owning them
+pub fn write(
+    listing: &Listing,
+    iface_info: &IfaceInfo,
+    filters: &Filters,
+    users: &UsersCache,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut by_uid = BTreeMap::<u32, Counts>::new();
+    for row in rows(listing, iface_info, filters) {
+        let counts = by_uid.entry(row.sock.uid).or_default();
+        counts.listeners += 1;
+        counts.wildcard += usize::from(row.sock.addr.is_unspecified());
+        counts.privileged += usize::from(row.sock.privileged_port());
+    }
+    let header = ["USER", "LISTENERS", "WILDCARD", "PRIVILEGED"];
+    let lines = by_uid
+        .into_iter()
+        .map(|(uid, c)| {
+            let user = match users.get_user_by_uid(uid) {
+                Some(u) => u.name().to_string_lossy().into(),
+                None if uid == UNKNOWN_UID => "?".into(),
+                None => uid.to_string(),
+            };
+            [
+                user,
+                c.listeners.to_string(),
+                c.wildcard.to_string(),
+                c.privileged.to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let width = |i: usize| {
+        lines
+            .iter()
+            .map(|l| l[i].len())
+            .chain([header[i].len()])
+            .max()
+            .unwrap_or(0)
+    };
+    let [w0, w1, w2, w3] = [width(0), width(1), width(2), width(3)];
+    let [h0, h1, h2, h3] = header;
+    writeln!(out, "{h0:<w0$}  {h1:>w1$}  {h2:>w2$}  {h3:>w3$}").context("Write summary")?;
+    for [user, listeners, wildcard, privileged] in lines {
+        writeln!(
+            out,
+            "{user:<w0$}  {listeners:>w1$}  {wildcard:>w2$}  {privileged:>w3$}"
+        )
+        .context("Write summary")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! One aligned line per socket, like ss -ltnp, for --table
+
+use crate::{
+    netlink::sock::{Family, Iface},
+    options::{Column, Filters},
+    rows::rows,
+    IfaceInfo, Listing,
+};
+use anyhow::{Context, Result};
+use std::{io::Write, net::IpAddr};
+
+const HEADER: [&str; 5] = ["PROTO", "ADDR:PORT", "PID", "USER", "PROCESS"];
+
+pub fn write(
+    listing: &Listing,
+    iface_info: &IfaceInfo,
+    filters: &Filters,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut lines = vec![HEADER.map(String::from)];
+    for row in rows(listing, iface_info, filters) {
+        let sock = row.sock;
+        let addr = match (sock.family, sock.addr) {
+            (Family::Both, IpAddr::V6(_)) => "*".into(),
+            (_, IpAddr::V6(addr)) => format!("[{addr}]"),
+            (_, addr) => addr.to_string(),
+        };
+        // Bound interfaces as ss shows them
+        let iface = match sock.iface {
+            Some(Iface::Bound(name)) => format!("%{name}"),
+            _ => String::new(),
+        };
+        let local = match sock.family {
+            Family::Unix => row.get(Column::Addr),
+            Family::Packet => format!("*{iface}:{}", row.get(Column::Port)),
+            _ => format!("{addr}{iface}:{}", sock.port),
+        };
+        let line = [
+            row.get(Column::Proto),
+            local,
+            row.get(Column::Pid),
+            row.get(Column::User),
+            row.get(Column::Cmd),
+        ];
+        // Placeholders keep the number of fields the same for awk and cut
+        lines.push(line.map(|cell| match cell.is_empty() {
+            true => "-".into(),
+            false => cell,
+        }));
+    }
+    let widths = (0..HEADER.len())
+        .map(|i| {
+            lines
+                .iter()
+                .map(|l| l[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    for line in lines {
+        let cells = (line.iter().zip(&widths)).map(|(cell, &width)| format!("{cell:width$}"));
+        let line = cells.collect::<Vec<_>>().join("  ");
+        writeln!(out, "{}", line.trim_end()).context("Write output")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use crate::{options::Column, rows::Row};
+use anyhow::{bail, Context, Result};
+
+/// Per-socket line format, e.g. `{cmd}\t{port}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Piece>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Field(Column),
+}
+
+impl Template {
+    pub fn render(&self, row: &Row) -> String {
+        let mut out = String::new();
+        for piece in &self.0 {
+            match piece {
+                Piece::Literal(lit) => out.push_str(lit),
+                Piece::Field(column) => out.push_str(&row.get(*column)),
+            }
+        }
+        out
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut pieces = Vec::new();
+        let mut lit = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => lit.push('\t'),
+                    Some('n') => lit.push('\n'),
+                    Some('\\') => lit.push('\\'),
+                    Some(c) => bail!("Unknown escape sequence \\{c} in template"),
+                    None => bail!("Template ends with \\"),
+                },
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    lit.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    lit.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').context("Unclosed { in template")?;
+                    pieces.push(Piece::Literal(std::mem::take(&mut lit)));
+                    pieces.push(Piece::Field(rest[..end].parse()?));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => bail!("Unmatched }} in template"),
+                c => lit.push(c),
+            }
+        }
+        pieces.push(Piece::Literal(lit));
+        pieces.retain(|p| *p != Piece::Literal(String::new()));
+        Ok(Template(pieces))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Piece, Template};
+    use crate::options::Column;
+
+    #[test]
+    fn parse_template() {
+        let template: Template = r"{cmd}\t{port} {{x}}".parse().unwrap();
+        assert_eq!(
+            template.0,
+            [
+                Piece::Field(Column::Cmd),
+                Piece::Literal("\t".into()),
+                Piece::Field(Column::Port),
+                Piece::Literal(" {x}".into()),
+            ]
+        );
+        assert!("{nope}".parse::<Template>().is_err());
+        assert!("{cmd".parse::<Template>().is_err());
+    }
+}
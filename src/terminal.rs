@@ -0,0 +1,259 @@
+//! Pick tree colors and characters the terminal can display
+
+use crate::{log, netlink::sock::Protocol, options::IconStyle, termtree::Theme};
+use anstyle::{Ansi256Color, AnsiColor, Color, RgbColor, Style};
+use std::{collections::HashMap, env::var};
+
+#[derive(Debug, Clone, Copy)]
+enum Depth {
+    Ansi8,
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+/// Theme for output to a terminal, or plain if color is false
+pub fn theme(color: bool) -> Theme {
+    let term = var("TERM").unwrap_or_default();
+    if term == "dumb" {
+        return Theme {
+            ascii: true,
+            ..Theme::default()
+        };
+    }
+    if !color {
+        return Theme::default();
+    }
+    let light = light_background();
+    let depth = depth(&term);
+    let (prefix, highlight) = match depth {
+        Depth::TrueColor => match light {
+            false => (rgb(138, 138, 138), rgb(215, 175, 0)),
+            true => (rgb(108, 108, 108), rgb(175, 95, 0)),
+        },
+        Depth::Ansi256 => match light {
+            false => (ansi256(245), ansi256(178)),
+            true => (ansi256(242), ansi256(130)),
+        },
+        Depth::Ansi16 => match light {
+            false => (ansi(AnsiColor::BrightBlack), ansi(AnsiColor::Yellow)),
+            true => (Style::new().dimmed(), ansi(AnsiColor::Red)),
+        },
+        Depth::Ansi8 => match light {
+            false => (Style::new().dimmed(), ansi(AnsiColor::Yellow)),
+            true => (Style::new().dimmed(), ansi(AnsiColor::Red)),
+        },
+    };
+    let mut theme = Theme {
+        prefix: Some(prefix),
+        highlight: Some(highlight),
+        classes: classes(depth, light),
+        ascii: false,
+    };
+    if let Ok(colors) = var("LLS_COLORS") {
+        configure(&mut theme, &colors);
+    }
+    theme
+}
+
+/// Port labels by protocol and by who can reach them, to spot e.g. UDP or network listeners
+fn classes(depth: Depth, light: bool) -> HashMap<String, Style> {
+    let tcp = match (depth, light) {
+        (Depth::Ansi8, _) | (_, true) => ansi(AnsiColor::Blue),
+        (_, false) => ansi(AnsiColor::BrightBlue),
+    };
+    let udp = ansi(AnsiColor::Magenta);
+    let other = ansi(AnsiColor::Green);
+    [
+        ("tcp", tcp),
+        ("udp", udp),
+        ("udplite", udp),
+        ("sctp", ansi(AnsiColor::Cyan)),
+        ("smc", ansi(AnsiColor::Cyan)),
+        ("raw", other),
+        ("icmp", other),
+        ("rds", other),
+        ("packet", other),
+        ("loopback", Style::new().dimmed()),
+        ("network", Style::new().bold()),
+    ]
+    .into_iter()
+    .map(|(class, style)| (class.into(), style))
+    .collect()
+}
+
+/// LLS_COLORS overrides the theme as LS_COLORS does for ls, e.g. udp=1;33:loopback=:prefix=90.
+/// An empty style turns the class's style off.
+fn configure(theme: &mut Theme, colors: &str) {
+    for entry in colors.split(':').filter(|e| !e.is_empty()) {
+        let Some((class, codes)) = entry.split_once('=') else {
+            log::warn!("Ignoring {entry:?} in LLS_COLORS, expected class=style");
+            continue;
+        };
+        let style = match codes {
+            "" => None,
+            codes => match sgr(codes) {
+                Some(style) => Some(style),
+                None => {
+                    log::warn!("Ignoring {entry:?} in LLS_COLORS, unknown style {codes:?}");
+                    continue;
+                }
+            },
+        };
+        match class {
+            "prefix" => theme.prefix = style,
+            "highlight" => theme.highlight = style,
+            "loopback" | "private" | "network" => set(theme, class, style),
+            class => match class.parse::<Protocol>() {
+                Ok(proto) => set(theme, &proto.to_string(), style),
+                Err(()) => log::warn!("Ignoring {entry:?} in LLS_COLORS, unknown class {class:?}"),
+            },
+        }
+    }
+}
+
+fn set(theme: &mut Theme, class: &str, style: Option<Style>) {
+    match style {
+        Some(style) => {
+            theme.classes.insert(class.into(), style);
+        }
+        None => {
+            theme.classes.remove(class);
+        }
+    }
+}
+
+/// SGR parameters as in LS_COLORS, e.g. 1;34 for bold blue, or 38;5;208 for orange
+fn sgr(codes: &str) -> Option<Style> {
+    let mut codes = codes.split(';').map(|c| c.parse::<u8>().ok());
+    let basic = |index: u8| Some(Color::Ansi(Ansi256Color(index).into_ansi()?));
+    let mut style = Style::new();
+    while let Some(code) = codes.next() {
+        style = match code? {
+            0 => Style::new(),
+            1 => style.bold(),
+            2 => style.dimmed(),
+            3 => style.italic(),
+            4 => style.underline(),
+            7 => style.invert(),
+            c @ 30..=37 => style.fg_color(basic(c - 30)),
+            c @ 90..=97 => style.fg_color(basic(c - 90 + 8)),
+            c @ 40..=47 => style.bg_color(basic(c - 40)),
+            c @ 100..=107 => style.bg_color(basic(c - 100 + 8)),
+            c @ (38 | 48) => {
+                let color = match codes.next()?? {
+                    5 => Color::Ansi256(Ansi256Color(codes.next()??)),
+                    2 => Color::Rgb(RgbColor(codes.next()??, codes.next()??, codes.next()??)),
+                    _ => return None,
+                };
+                match c {
+                    38 => style.fg_color(Some(color)),
+                    _ => style.bg_color(Some(color)),
+                }
+            }
+            _ => return None,
+        };
+    }
+    Some(style)
+}
+
+fn depth(term: &str) -> Depth {
+    let colorterm = var("COLORTERM").unwrap_or_default();
+    if matches!(colorterm.as_str(), "truecolor" | "24bit") {
+        Depth::TrueColor
+    } else if term.contains("256color") {
+        Depth::Ansi256
+    } else if term.ends_with("8color") || matches!(term, "vt100" | "vt220" | "ansi") {
+        Depth::Ansi8
+    } else {
+        Depth::Ansi16
+    }
+}
+
+/// Some terminals (rxvt, konsole, …) set COLORFGBG to e.g. "15;0", the last number being the background
+fn light_background() -> bool {
+    var("COLORFGBG")
+        .ok()
+        .and_then(|fgbg| fgbg.rsplit(';').next()?.parse::<u8>().ok())
+        .is_some_and(|bg| bg == 7 || (9..=15).contains(&bg))
+}
+
+fn ansi(color: AnsiColor) -> Style {
+    Color::Ansi(color).on_default()
+}
+
+fn ansi256(color: u8) -> Style {
+    Color::Ansi256(Ansi256Color(color)).on_default()
+}
+
+fn rgb(r: u8, g: u8, b: u8) -> Style {
+    Color::Rgb(RgbColor(r, g, b)).on_default()
+}
+
+/// Glyphs in front of lines, for --icons
+pub struct Icons {
+    /// Reachable from the network
+    pub network: &'static str,
+    pub loopback: &'static str,
+    /// Reachable from the network, but not from the internet according to --cloud
+    pub private: &'static str,
+    /// Process running in or forwarding to a container
+    pub container: &'static str,
+}
+
+/// Emoji, unless the terminal is dumb or the locale isn't UTF-8, which hints at a font without them
+pub fn icons(style: IconStyle) -> Icons {
+    let emoji = match style {
+        IconStyle::Emoji => true,
+        IconStyle::Ascii => false,
+        IconStyle::Auto => var("TERM").map_or(true, |t| t != "dumb") && utf8_locale(),
+    };
+    match emoji {
+        true => Icons {
+            network: "🌐",
+            loopback: "🏠",
+            private: "🔒",
+            container: "🐳",
+        },
+        false => Icons {
+            network: "[net]",
+            loopback: "[lo]",
+            private: "[priv]",
+            container: "[ctr]",
+        },
+    }
+}
+
+fn utf8_locale() -> bool {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .into_iter()
+        .find_map(|v| var(v).ok().filter(|l| !l.is_empty()))
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    locale.contains("utf-8") || locale.contains("utf8")
+}
+
+#[cfg(test)]
+mod test {
+    use super::sgr;
+    use anstyle::{Ansi256Color, AnsiColor, Color, Style};
+
+    #[test]
+    fn ls_colors_styles() {
+        let bold_blue = Style::new()
+            .bold()
+            .fg_color(Some(Color::Ansi(AnsiColor::Blue)));
+        assert_eq!(sgr("1;34"), Some(bold_blue));
+        assert_eq!(
+            sgr("38;5;208;2"),
+            Some(
+                Style::new()
+                    .fg_color(Some(Color::Ansi256(Ansi256Color(208))))
+                    .dimmed()
+            )
+        );
+        assert_eq!(sgr("0;97"), Some(AnsiColor::BrightWhite.on_default()));
+        assert_eq!(sgr("38;5"), None);
+        assert_eq!(sgr("blue"), None);
+    }
+}
@@ -1,36 +1,135 @@
 use itertools::Itertools;
+use std::collections::HashMap;
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-const GREY: anstyle::Style = anstyle::Color::Ansi(anstyle::AnsiColor::BrightBlack).on_default();
+/// How to draw the tree, default is plain unicode without colors
+#[derive(Default)]
+pub struct Theme {
+    /// Tree structure and separators
+    pub prefix: Option<anstyle::Style>,
+    /// Entries marked by highlight_since
+    pub highlight: Option<anstyle::Style>,
+    /// Entries marked by classify_since, e.g. port labels by protocol and reach. The colors
+    /// and effects of all classes of an entry are combined.
+    pub classes: HashMap<String, anstyle::Style>,
+    /// No box drawing characters
+    pub ascii: bool,
+}
+
+impl Theme {
+    fn paint(style: Option<anstyle::Style>, s: &str) -> String {
+        match style {
+            Some(style) => format!("{}{s}{}", style.render(), style.render_reset()),
+            None => s.into(),
+        }
+    }
+
+    fn style(&self, entry: &Entry) -> Option<anstyle::Style> {
+        if entry.highlight {
+            return self.highlight;
+        }
+        (entry.classes.iter())
+            .filter_map(|class| self.classes.get(class))
+            .copied()
+            .reduce(|a, b| {
+                a.fg_color(a.get_fg_color().or(b.get_fg_color()))
+                    .bg_color(a.get_bg_color().or(b.get_bg_color()))
+                    .effects(a.get_effects() | b.get_effects())
+            })
+    }
+}
+
+/// Separates cells of entry data that are aligned with the same cells of sibling entries
+pub const CELL: char = '\u{1f}';
 
 pub struct Tree(Vec<Entry>);
 pub struct Entry {
     pub data: String,
     pub children: Tree,
+    pub highlight: bool,
+    pub classes: Vec<String>,
 }
 impl Tree {
     pub fn leaf(&mut self, data: String) -> &mut Self {
         self.0.push(Entry {
             data,
             children: Tree::new(),
+            highlight: false,
+            classes: Vec::new(),
         });
         self
     }
     pub fn node(&mut self, data: String, children: Tree) -> &mut Self {
         if !children.0.is_empty() {
-            self.0.push(Entry { data, children });
+            self.0.push(Entry {
+                data,
+                children,
+                highlight: false,
+                classes: Vec::new(),
+            });
         }
         self
     }
     pub fn new() -> Self {
         Self(vec![])
     }
+    pub fn extend(&mut self, other: Tree) -> &mut Self {
+        self.0.extend(other.0);
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    /// Mark entries added after the tree had length start, rendered in color if enabled
+    pub fn highlight_since(&mut self, start: usize) {
+        for entry in &mut self.0[start..] {
+            entry.highlight = true;
+        }
+    }
+    /// Tag entries added after the tree had length start, for the theme to color them
+    pub fn classify_since(&mut self, start: usize, classes: &[&str]) {
+        for entry in &mut self.0[start..] {
+            entry.classes.extend(classes.iter().map(|&c| c.into()));
+        }
+    }
 
-    pub fn render(&self, mw: Option<usize>, color: bool, ret: &mut impl FnMut(&[u8])) {
+    /// Call f on the label of each entry, with its depth below the root
+    #[cfg(feature = "lua")]
+    pub fn try_for_each_label<E>(
+        &mut self,
+        depth: usize,
+        f: &mut impl FnMut(&mut String, usize) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for entry in &mut self.0 {
+            f(&mut entry.data, depth)?;
+            entry.children.try_for_each_label(depth + 1, f)?;
+        }
+        Ok(())
+    }
+
+    pub fn render(&self, mw: Option<usize>, theme: &Theme, ret: &mut impl FnMut(&[u8])) {
+        let widths = self.cell_widths();
         for entry in &self.0 {
-            render_entry(entry, mw, color, ret, None);
+            render_entry(entry, mw, theme, ret, None, &widths);
         }
     }
+
+    /// Widest content of each CELL separated column among the entries
+    fn cell_widths(&self) -> Vec<usize> {
+        let mut widths = Vec::new();
+        for entry in &self.0 {
+            for (i, cell) in entry.data.split(CELL).enumerate() {
+                match widths.get_mut(i) {
+                    Some(w) => *w = cell.width().max(*w),
+                    None => widths.push(cell.width()),
+                }
+            }
+        }
+        widths
+    }
 }
 
 struct Prefix<'a> {
@@ -38,14 +137,17 @@ struct Prefix<'a> {
     prefix: Option<&'a Prefix<'a>>,
 }
 
-fn render_pfx(prefix: Option<&Prefix>, rightmost: bool, ret: &mut impl FnMut(&str)) {
+fn render_pfx(prefix: Option<&Prefix>, rightmost: bool, ascii: bool, ret: &mut impl FnMut(&str)) {
     if let Some(prefix) = prefix {
-        render_pfx(prefix.prefix, false, ret);
-        match (rightmost, prefix.last) {
-            (false, true) => ret("  "),
-            (false, false) => ret("│ "),
-            (true, true) => ret("└ "),
-            (true, false) => ret("├ "),
+        render_pfx(prefix.prefix, false, ascii, ret);
+        match (rightmost, prefix.last, ascii) {
+            (false, true, _) => ret("  "),
+            (false, false, false) => ret("│ "),
+            (true, true, false) => ret("└ "),
+            (true, false, false) => ret("├ "),
+            (false, false, true) => ret("| "),
+            (true, true, true) => ret("` "),
+            (true, false, true) => ret("+ "),
         }
     }
 }
@@ -53,23 +155,30 @@ fn render_pfx(prefix: Option<&Prefix>, rightmost: bool, ret: &mut impl FnMut(&st
 fn render_entry(
     tree: &Entry,
     mw: Option<usize>,
-    color: bool,
+    theme: &Theme,
     ret: &mut impl FnMut(&[u8]),
     prefix: Option<&Prefix<'_>>,
+    widths: &[usize],
 ) {
-    if color {
-        let mut out = String::new();
-        render_pfx(prefix, true, &mut |s| out.push_str(s));
-        ret(format!("{}{}{}", GREY.render(), out, GREY.render_reset()).as_bytes());
-    } else {
-        render_pfx(prefix, true, &mut |s| ret(s.as_bytes()));
-    }
+    let mut pfx = String::new();
+    render_pfx(prefix, true, theme.ascii, &mut |s| pfx.push_str(s));
+    ret(Theme::paint(theme.prefix, &pfx).as_bytes());
+    let aligned = align(&tree.data, widths);
+    let collapsed = match mw {
+        Some(mw) if aligned.width() > mw => None,
+        _ => collapse(&tree.children.0, mw.map(|mw| mw - aligned.width()), theme),
+    };
+    // Padding is only needed if something follows
+    let data = match collapsed {
+        Some(_) => &aligned,
+        None => aligned.trim_end(),
+    };
     let mut out = String::new();
     if let Some(mw) = mw {
-        if out.width() + tree.data.width() <= mw {
-            out.push_str(&tree.data);
+        if out.width() + data.width() <= mw {
+            out.push_str(data);
         } else {
-            for c in tree.data.chars() {
+            for c in data.chars() {
                 if out.width() + c.width().unwrap_or(0) < mw {
                     out.push(c);
                 } else {
@@ -77,43 +186,66 @@ fn render_entry(
                 }
             }
             while out.width() < mw {
-                out.push('…');
+                out.push(match theme.ascii {
+                    true => '.',
+                    false => '…',
+                });
             }
         }
     } else {
-        out.push_str(&tree.data);
+        out.push_str(data);
+    }
+    if let Some(style) = theme.style(tree) {
+        out = Theme::paint(Some(style), &out);
     }
-    let collapsed = collapse(&tree.children.0, mw.map(|mw| mw - out.width()), color);
     if let Some(collapsed) = &collapsed {
         out.push_str(collapsed);
     }
     ret(out.as_bytes());
     ret(b"\n");
     if collapsed.is_none() {
+        let widths = tree.children.cell_widths();
         for (pos, child) in tree.children.0.iter().with_position() {
             let last = matches!(pos, itertools::Position::Last | itertools::Position::Only);
             let prefix = Prefix { last, prefix };
-            render_entry(child, mw, color, ret, Some(&prefix));
+            render_entry(child, mw, theme, ret, Some(&prefix), &widths);
         }
     }
 }
 
-fn collapse(children: &[Entry], mw: Option<usize>, color: bool) -> Option<String> {
-    let sep = if color {
-        format!("{} / {}", GREY.render(), GREY.render_reset())
-    } else {
-        " / ".into()
-    };
+/// Pad all but the last cell to the column widths, and separate cells by a space
+fn align(data: &str, widths: &[usize]) -> String {
+    let mut cells = data.split(CELL).collect::<Vec<_>>();
+    let last = cells.pop().unwrap_or_default();
+    let mut ret = (cells.iter().enumerate())
+        .map(|(i, cell)| {
+            let pad = widths.get(i).map_or(0, |w| w.saturating_sub(cell.width()));
+            format!("{cell}{}", " ".repeat(pad))
+        })
+        .join(" ");
+    if !last.is_empty() {
+        if !ret.is_empty() {
+            ret.push(' ');
+        }
+        ret.push_str(last);
+    }
+    ret
+}
+
+fn collapse(children: &[Entry], mw: Option<usize>, theme: &Theme) -> Option<String> {
+    let sep = Theme::paint(theme.prefix, " / ");
     match &children {
-        &[Entry { data, children }] => {
+        &[entry @ Entry { data, children, .. }] => {
+            let data = &align(data, &[]);
             let nw = data.width() + sep.width();
+            let data = Theme::paint(theme.style(entry), data);
             if mw.map_or_else(|| true, |mw| nw <= mw) {
                 if children.0.is_empty() {
                     Some(format!("{sep}{data}"))
                 } else {
                     Some(format!(
                         "{sep}{data}{}",
-                        collapse(&children.0, mw.map(|mw| mw.saturating_sub(nw)), color)?
+                        collapse(&children.0, mw.map(|mw| mw.saturating_sub(nw)), theme)?
                     ))
                 }
             } else {
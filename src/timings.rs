@@ -0,0 +1,52 @@
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PHASES: Mutex<Vec<(String, Duration)>> = Mutex::new(Vec::new());
+
+pub fn enable() {
+    ENABLED.store(true, Relaxed);
+}
+
+/// Run f, and remember how long it took if --timings was given
+pub fn time<T>(phase: impl Display, f: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Relaxed) {
+        return f();
+    }
+    let start = Instant::now();
+    let ret = f();
+    let elapsed = start.elapsed();
+    let phase = phase.to_string();
+    let mut phases = PHASES.lock().expect("Timings poisoned");
+    // Phases that run once per process when streaming
+    match phases.iter_mut().find(|(p, _)| *p == phase) {
+        Some((_, total)) => *total += elapsed,
+        None => phases.push((phase, elapsed)),
+    }
+    ret
+}
+
+/// The phases since the last call, e.g. for each round of lls bench
+#[cfg(feature = "netlink")]
+pub fn take() -> Vec<(String, Duration)> {
+    std::mem::take(&mut *PHASES.lock().expect("Timings poisoned"))
+}
+
+pub fn report() {
+    if !ENABLED.load(Relaxed) {
+        return;
+    }
+    let phases = PHASES.lock().expect("Timings poisoned");
+    let width = phases.iter().map(|(p, _)| p.len()).max().unwrap_or(0);
+    let total: Duration = phases.iter().map(|(_, d)| *d).sum();
+    for (phase, duration) in phases.iter() {
+        eprintln!("{phase:width$}  {:>9.3}ms", duration.as_secs_f64() * 1e3);
+    }
+    eprintln!("{:width$}  {:>9.3}ms", "sum", total.as_secs_f64() * 1e3);
+}
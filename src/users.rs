@@ -0,0 +1,172 @@
+//! User name lookups, or plain numeric user ids when built without the users feature
+
+#[cfg(feature = "users")]
+pub use files::UsersCache;
+#[cfg(feature = "users")]
+pub use uzers::{get_current_uid, get_effective_uid, Groups, Users};
+
+#[cfg(not(feature = "users"))]
+pub use numeric::*;
+
+#[cfg(not(feature = "users"))]
+mod numeric {
+    use std::ffi::OsStr;
+
+    pub enum User {}
+
+    impl User {
+        pub fn uid(&self) -> u32 {
+            match *self {}
+        }
+        pub fn name(&self) -> &OsStr {
+            match *self {}
+        }
+    }
+
+    pub enum Group {}
+
+    impl Group {
+        pub fn name(&self) -> &OsStr {
+            match *self {}
+        }
+    }
+
+    pub trait Users {
+        fn get_user_by_uid(&self, uid: u32) -> Option<User>;
+        fn get_user_by_name(&self, name: &str) -> Option<User>;
+    }
+
+    pub trait Groups {
+        fn get_group_by_gid(&self, gid: u32) -> Option<Group>;
+    }
+
+    pub struct UsersCache;
+
+    impl UsersCache {
+        pub fn new() -> Self {
+            UsersCache
+        }
+    }
+
+    impl Users for UsersCache {
+        fn get_user_by_uid(&self, _uid: u32) -> Option<User> {
+            None
+        }
+        fn get_user_by_name(&self, _name: &str) -> Option<User> {
+            None
+        }
+    }
+
+    impl Groups for UsersCache {
+        fn get_group_by_gid(&self, _gid: u32) -> Option<Group> {
+            None
+        }
+    }
+
+    fn status() -> Option<procfs::process::Status> {
+        procfs::process::Process::myself().ok()?.status().ok()
+    }
+
+    pub fn get_current_uid() -> u32 {
+        status().map_or(u32::MAX, |s| s.ruid)
+    }
+
+    pub fn get_effective_uid() -> u32 {
+        status().map_or(u32::MAX, |s| s.euid)
+    }
+}
+
+/// With --proc-root, the users and groups of the system it belongs to instead of the running one
+#[cfg(feature = "users")]
+mod files {
+    use crate::procs;
+    use std::{ffi::OsStr, fs::read_to_string, sync::Arc};
+    use uzers::{mock::MockUsers, Group, Groups, User, Users};
+
+    pub enum UsersCache {
+        System(uzers::UsersCache),
+        /// From etc/passwd and etc/group, MockUsers is just a map of them
+        Files(MockUsers),
+    }
+
+    impl UsersCache {
+        pub fn new() -> Self {
+            if !procs::foreign_root() {
+                return UsersCache::System(uzers::UsersCache::new());
+            }
+            let mut users = MockUsers::with_current_uid(uzers::get_current_uid());
+            let etc = procs::sysroot().join("etc");
+            // name:password:id:…, the same for both files up to the id
+            let entries = |file: &str| {
+                let content = read_to_string(etc.join(file)).unwrap_or_default();
+                (content.lines())
+                    .filter_map(|line| {
+                        let mut fields = line.split(':');
+                        let name = fields.next()?.to_owned();
+                        let id = fields.nth(1)?.parse::<u32>().ok()?;
+                        let gid = fields.next().and_then(|g| g.parse().ok());
+                        Some((name, id, gid))
+                    })
+                    .collect::<Vec<_>>()
+            };
+            for (name, uid, gid) in entries("passwd") {
+                users.add_user(User::new(uid, &name, gid.unwrap_or(uid)));
+            }
+            for (name, gid, _) in entries("group") {
+                users.add_group(Group::new(gid, &name));
+            }
+            UsersCache::Files(users)
+        }
+    }
+
+    macro_rules! delegate {
+        ($self:ident.$method:ident($($arg:expr),*)) => {
+            match $self {
+                UsersCache::System(users) => users.$method($($arg),*),
+                UsersCache::Files(users) => users.$method($($arg),*),
+            }
+        };
+    }
+
+    impl Users for UsersCache {
+        fn get_user_by_uid(&self, uid: u32) -> Option<Arc<User>> {
+            delegate!(self.get_user_by_uid(uid))
+        }
+        fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, name: &S) -> Option<Arc<User>> {
+            delegate!(self.get_user_by_name(name))
+        }
+        fn get_current_uid(&self) -> u32 {
+            delegate!(self.get_current_uid())
+        }
+        fn get_current_username(&self) -> Option<Arc<OsStr>> {
+            delegate!(self.get_current_username())
+        }
+        fn get_effective_uid(&self) -> u32 {
+            delegate!(self.get_effective_uid())
+        }
+        fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+            delegate!(self.get_effective_username())
+        }
+    }
+
+    impl Groups for UsersCache {
+        fn get_group_by_gid(&self, gid: u32) -> Option<Arc<Group>> {
+            delegate!(self.get_group_by_gid(gid))
+        }
+        fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, name: &S) -> Option<Arc<Group>> {
+            delegate!(self.get_group_by_name(name))
+        }
+        fn get_current_gid(&self) -> u32 {
+            delegate!(self.get_current_gid())
+        }
+        fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+            delegate!(self.get_current_groupname())
+        }
+        fn get_effective_gid(&self) -> u32 {
+            delegate!(self.get_effective_gid())
+        }
+        fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+            delegate!(self.get_effective_groupname())
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! Connection counts of listeners, end to end in network namespaces
+#![cfg(all(feature = "netlink", feature = "json"))]
+
+mod netns;
+
+use netns::{find, Netns};
+use std::net::TcpStream;
+
+#[test]
+#[ignore = "needs root and iproute2, run with --ignored"]
+fn accepted_connections() {
+    let ns = Netns::new();
+    let _wildcard = ns.tcp("[::]:8080");
+    let _idle = ns.tcp("127.0.0.1:8081");
+    let _conns = ns.enter(|| {
+        ["127.0.0.1:8080", "127.0.0.1:8080", "[::1]:8080"]
+            .map(|addr| TcpStream::connect(addr).expect("Connect in namespace"))
+    });
+
+    let sockets = ns.lls(&["-o", "port,proto,conns"]);
+    assert_eq!(find(&sockets, 8080, "tcp")["conns"], 3);
+    assert_eq!(find(&sockets, 8081, "tcp")["conns"], 0);
+    assert!(find(&ns.lls(&[]), 8080, "tcp")["conns"].is_null());
+}
@@ -0,0 +1,47 @@
+//! --diff against a --json snapshot, end to end in network namespaces
+#![cfg(all(feature = "netlink", feature = "json"))]
+
+mod netns;
+
+use netns::Netns;
+use std::fs;
+
+#[test]
+#[ignore = "needs root and iproute2, run with --ignored"]
+fn added_and_removed() {
+    let ns = Netns::new();
+    let listener = ns.tcp("127.0.0.1:8080");
+    let _kept = ns.tcp("0.0.0.0:8081");
+    let (_, snapshot) = ns.lls_output(&["--json"]);
+    let path = std::env::temp_dir().join(format!("{}.json", ns.name));
+    fs::write(&path, snapshot).expect("Write snapshot");
+    let path = path.to_str().expect("Temporary path");
+
+    assert_eq!(ns.lls_output(&["--diff", path]), (1, String::new()));
+    drop(listener);
+    let _new = ns.udp("0.0.0.0:5353");
+    let (code, diff) = ns.lls_output(&["--diff", path]);
+    fs::remove_file(path).ok();
+    assert_eq!(code, 0);
+    let lines = diff.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2, "{diff}");
+    assert!(lines[0].starts_with("+ ") && lines[0].ends_with(" / :5353 udp / 0.0.0.0"));
+    assert!(lines[1].starts_with("- ") && lines[1].ends_with(" / :8080 tcp / 127.0.0.1"));
+}
+
+#[test]
+#[ignore = "needs root and iproute2, run with --ignored"]
+fn unchanged_despite_partial_data() {
+    let ns = Netns::new();
+    let _listener = ns.tcp("127.0.0.1:8080");
+    let (_, snapshot) = ns.lls_output(&["--json"]);
+    let path = std::env::temp_dir().join(format!("{}.json", ns.name));
+    fs::write(&path, snapshot).expect("Write snapshot");
+    let path = path.to_str().expect("Temporary path");
+
+    // Without --diff, stopping the scan early makes the data partial
+    assert_eq!(ns.lls_output(&["--max-procs", "1", ":9"]).0, 3);
+    let diff = ns.lls_output(&["--diff", path, "--max-procs", "1", ":9"]);
+    fs::remove_file(path).ok();
+    assert_eq!(diff, (1, String::new()));
+}
@@ -0,0 +1,149 @@
+//! Interface attribution, end to end in network namespaces
+#![cfg(all(feature = "netlink", feature = "json"))]
+
+mod netns;
+
+use netns::{find, Netns};
+use std::os::fd::AsRawFd;
+
+#[test]
+#[ignore = "needs root and iproute2, run with --ignored"]
+fn route_attribution() {
+    let ns = Netns::new();
+    ns.veth("veth0", "10.200.0.1/24");
+    let _routed = ns.tcp("10.200.0.1:8080");
+    let _local = ns.tcp("127.0.0.1:8081");
+    let _wildcard = ns.udp("0.0.0.0:5353");
+    let sockets = ns.lls(&[]);
+
+    let routed = find(&sockets, 8080, "tcp");
+    assert_eq!(routed["iface"], "veth0");
+    assert_eq!(routed["iface_bound"], false);
+    assert_eq!(routed["owner"]["pid"], std::process::id());
+    assert_eq!(find(&sockets, 8081, "tcp")["iface"], "lo");
+    assert!(find(&sockets, 5353, "udp")["iface"].is_null());
+
+    let filtered = ns.lls(&["-i", "veth0"]);
+    assert!(filtered.iter().all(|s| s["port"] != 8081));
+    assert!(filtered.iter().any(|s| s["port"] == 5353));
+}
+
+#[test]
+#[ignore = "needs root and iproute2, run with --ignored"]
+fn addr_filter() {
+    let ns = Netns::new();
+    ns.veth("veth0", "10.200.0.1/24");
+    let _bound = ns.tcp("10.200.0.1:8080");
+    let _v4 = ns.tcp("0.0.0.0:8082");
+    let _dual = ns.tcp("[::]:8083");
+    let _v6 = ns.tcp("[::1]:8084");
+    let ports = |args: &[&str]| {
+        let sockets = ns.lls(args);
+        let mut ports: Vec<_> = sockets.iter().filter_map(|s| s["port"].as_u64()).collect();
+        ports.sort();
+        ports
+    };
+
+    assert_eq!(ports(&["-a", "10.200.0.0/24"]), [8080, 8082, 8083]);
+    assert_eq!(ports(&["-a", "::1"]), [8083, 8084]);
+    assert_eq!(ports(&["--strict-addr", "-a", "10.200.0.0/24"]), [8080]);
+    assert_eq!(ports(&["--strict-addr", "veth0"]), [8080]);
+}
+
+#[test]
+#[ignore = "needs root and iproute2, run with --ignored"]
+fn bound_device() {
+    let ns = Netns::new();
+    ns.veth("veth0", "10.200.0.1/24");
+    let socket = ns.udp("0.0.0.0:6000");
+    let dev = b"veth0-peer";
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            dev.as_ptr().cast(),
+            dev.len() as libc::socklen_t,
+        )
+    };
+    assert_eq!(ret, 0, "SO_BINDTODEVICE failed");
+
+    let sockets = ns.lls(&[":6000"]);
+    let bound = find(&sockets, 6000, "udp");
+    assert_eq!(bound["iface"], "veth0-peer");
+    assert_eq!(bound["iface_bound"], true);
+    assert!(ns.lls(&["-i", "veth0", ":6000"]).is_empty());
+}
+
+#[test]
+#[ignore = "needs root and iproute2, run with --ignored"]
+fn v4_mapped() {
+    let ns = Netns::new();
+    ns.veth("veth0", "10.200.0.1/24");
+    let _mapped = ns.tcp("[::ffff:10.200.0.1]:8443");
+
+    let sockets = ns.lls(&["10.200.0.1"]);
+    let mapped = find(&sockets, 8443, "tcp");
+    assert_eq!(mapped["addr"], "::ffff:10.200.0.1");
+    assert_eq!(mapped["family"], "v6");
+    assert_eq!(mapped["v4_mapped"], true);
+    assert_eq!(mapped["iface"], "veth0");
+    assert!(ns.lls(&["-a", "::/0", ":8443"]).is_empty());
+}
+
+#[test]
+#[ignore = "needs root and iproute2, run with --ignored"]
+fn route_tables() {
+    let ns = Netns::new();
+    ns.veth("veth0", "10.200.0.1/24");
+    let _listener = ns.tcp("10.200.0.1:8080");
+    // As with a VRF, which keeps the local routes of its interfaces in its own table
+    ns.ip(&[
+        "route",
+        "del",
+        "local",
+        "10.200.0.1",
+        "dev",
+        "veth0",
+        "table",
+        "local",
+    ]);
+    ns.ip(&[
+        "route",
+        "add",
+        "local",
+        "10.200.0.1",
+        "dev",
+        "veth0",
+        "table",
+        "1010",
+    ]);
+
+    assert!(find(&ns.lls(&[]), 8080, "tcp")["iface"].is_null());
+    let vrf = ns.lls(&["--route-table", "1010"]);
+    assert_eq!(find(&vrf, 8080, "tcp")["iface"], "veth0");
+    let all = ns.lls(&["--route-table=all"]);
+    assert_eq!(find(&all, 8080, "tcp")["iface"], "veth0");
+}
+
+#[test]
+#[ignore = "needs root and iproute2, run with --ignored"]
+fn tunnel_ports() {
+    let ns = Netns::new();
+    ns.veth("veth0", "10.200.0.1/24");
+    ns.vxlan("vx0", "veth0", 4790);
+    let sockets = ns.lls(&[]);
+    let vxlan = find(&sockets, 4790, "udp");
+    assert_eq!(vxlan["owner"]["kind"], "interface");
+    assert_eq!(vxlan["owner"]["interface"], "vx0");
+
+    if !ns.wireguard("wg0") {
+        eprintln!("No wireguard support, skipping its part");
+        return;
+    }
+    let sockets = ns.lls(&[]);
+    let wireguard = (sockets.iter())
+        .find(|s| s["owner"]["interface"] == "wg0")
+        .unwrap_or_else(|| panic!("No socket of wg0 in {sockets:#?}"));
+    assert_eq!(wireguard["protocol"], "udp");
+}
@@ -0,0 +1,147 @@
+//! Throwaway network namespaces to run lls in, for tests of what it finds
+//!
+//! Needs root and iproute2, so the tests using it are ignored by default, and run with
+//! cargo test -- --ignored. They fail if the namespace can't be created.
+
+// Each test crate uses its own part of it
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::{
+    fs::File,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    os::fd::AsRawFd,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Netns {
+    pub name: String,
+}
+
+impl Netns {
+    /// A namespace with lo up
+    pub fn new() -> Netns {
+        let name = format!(
+            "lls-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Relaxed)
+        );
+        let added = Command::new("ip").args(["netns", "add", &name]).output();
+        assert!(
+            added.is_ok_and(|out| out.status.success()),
+            "Can't create network namespace {name}, needs root and iproute2"
+        );
+        let netns = Netns { name };
+        netns.ip(&["link", "set", "lo", "up"]);
+        netns
+    }
+
+    /// Run ip -n <netns> args, and panic if it fails
+    pub fn ip(&self, args: &[&str]) {
+        assert!(self.try_ip(args), "ip {} failed", args.join(" "));
+    }
+
+    fn try_ip(&self, args: &[&str]) -> bool {
+        let out = Command::new("ip")
+            .args(["-n", &self.name])
+            .args(args)
+            .output()
+            .expect("Run ip");
+        if !out.status.success() {
+            eprintln!("{}", String::from_utf8_lossy(&out.stderr));
+        }
+        out.status.success()
+    }
+
+    /// A veth pair with both ends in the namespace, name with addr, and name-peer
+    pub fn veth(&self, name: &str, addr: &str) {
+        let peer = format!("{name}-peer");
+        self.ip(&["link", "add", name, "type", "veth", "peer", "name", &peer]);
+        self.ip(&["addr", "add", addr, "dev", name]);
+        self.ip(&["link", "set", name, "up"]);
+        self.ip(&["link", "set", &peer, "up"]);
+    }
+
+    /// A wireguard interface, if the kernel has wireguard
+    pub fn wireguard(&self, name: &str) -> bool {
+        let added = self.try_ip(&["link", "add", name, "type", "wireguard"]);
+        added && self.try_ip(&["link", "set", name, "up"])
+    }
+
+    /// A VXLAN interface on top of dev, whose socket the kernel opens
+    pub fn vxlan(&self, name: &str, dev: &str, port: u16) {
+        let port = port.to_string();
+        self.ip(&[
+            "link", "add", name, "type", "vxlan", "id", "42", "dstport", &port, "dev", dev,
+        ]);
+        self.ip(&["link", "set", name, "up"]);
+    }
+
+    /// Run f on a thread that has entered the namespace, e.g. to open sockets in it
+    pub fn enter<T: Send>(&self, f: impl FnOnce() -> T + Send) -> T {
+        let ns = File::open(format!("/run/netns/{}", self.name)).expect("Open namespace");
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    // Only changes the namespace of this thread
+                    let ret = unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) };
+                    assert_eq!(ret, 0, "setns: {}", std::io::Error::last_os_error());
+                    f()
+                })
+                .join()
+                .expect("Thread in namespace panicked")
+        })
+    }
+
+    pub fn tcp(&self, addr: &str) -> TcpListener {
+        let addr = addr.parse::<SocketAddr>().expect("Socket address");
+        self.enter(|| TcpListener::bind(addr).expect("Listen in namespace"))
+    }
+
+    pub fn udp(&self, addr: &str) -> UdpSocket {
+        let addr = addr.parse::<SocketAddr>().expect("Socket address");
+        self.enter(|| UdpSocket::bind(addr).expect("Bind in namespace"))
+    }
+
+    /// Run lls with args in the namespace, and return the sockets of lls --json --flat
+    pub fn lls(&self, args: &[&str]) -> Vec<Value> {
+        let args = [&["--json", "--flat", "--stable"], args].concat();
+        let (_, stdout) = self.lls_output(&args);
+        let doc: Value = serde_json::from_str(&stdout)
+            .unwrap_or_else(|e| panic!("lls output isn't JSON: {e}: {stdout}"));
+        doc["sockets"].as_array().cloned().unwrap_or_default()
+    }
+
+    /// Run lls with args in the namespace, and return its exit code and output
+    pub fn lls_output(&self, args: &[&str]) -> (i32, String) {
+        let out = Command::new("ip")
+            .args(["netns", "exec", &self.name, env!("CARGO_BIN_EXE_lls")])
+            .args(args)
+            .output()
+            .expect("Run lls");
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        // 1 is no matches, 3 is partial data, e.g. for processes that exited during the scan
+        let code = out.status.code().filter(|code| matches!(code, 0 | 1 | 3));
+        let code = code.unwrap_or_else(|| panic!("lls failed with {}: {stderr}", out.status));
+        (code, String::from_utf8_lossy(&out.stdout).into())
+    }
+}
+
+impl Drop for Netns {
+    fn drop(&mut self) {
+        Command::new("ip")
+            .args(["netns", "del", &self.name])
+            .output()
+            .ok();
+    }
+}
+
+/// The socket on port with protocol, or panic with all sockets
+pub fn find<'s>(sockets: &'s [Value], port: u16, protocol: &str) -> &'s Value {
+    (sockets.iter())
+        .find(|s| s["port"] == port && s["protocol"] == protocol)
+        .unwrap_or_else(|| panic!("No socket on :{port} {protocol} in {sockets:#?}"))
+}